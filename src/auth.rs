@@ -0,0 +1,405 @@
+use crate::config::{AuthConfig, AuthMode};
+use crate::error::Error;
+use crate::webhook::routes::SharedHandler;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::{header, request::Parts},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::time::{SystemTime, UNIX_EPOCH};
+use subtle::ConstantTimeEq;
+use tracing::warn;
+
+/// Unix timestamp (seconds) the HMAC signature was computed at.
+pub const TIMESTAMP_HEADER: &str = "x-webhook-timestamp";
+/// `sha256=<hex>` HMAC over [`signing_payload`].
+pub const SIGNATURE_HEADER: &str = "x-webhook-signature";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// The bytes a client signs: `"<timestamp>\n<METHOD>\n<path?query>\n"` followed by the raw
+/// body. Binding the method and path stops a captured signature being replayed against a
+/// different endpoint; the timestamp bounds how long it can be replayed at all.
+pub fn signing_payload(timestamp: u64, method: &str, path: &str, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{timestamp}\n{method}\n{path}\n").into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+/// Compute the `x-webhook-signature` header value for a request.
+#[cfg(test)]
+pub fn sign(key: &str, timestamp: u64, method: &str, path: &str, body: &[u8]) -> String {
+    let mut mac =
+        HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(&signing_payload(timestamp, method, path, body));
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+/// Check a request's credentials against `auth`. `body` is only consulted in HMAC mode.
+pub fn verify(auth: &AuthConfig, parts: &Parts, body: &[u8], now: u64) -> Result<(), String> {
+    match auth.mode {
+        AuthMode::None => Ok(()),
+        AuthMode::Bearer => {
+            let token = parts
+                .headers
+                .get(header::AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .and_then(|v| v.strip_prefix("Bearer "))
+                .ok_or("missing bearer token")?;
+            // Check every key so timing doesn't reveal which one (if any) matched.
+            let matched = auth.keys.iter().fold(false, |matched, key| {
+                matched | bool::from(key.expose().as_bytes().ct_eq(token.as_bytes()))
+            });
+            if matched {
+                Ok(())
+            } else {
+                Err("invalid bearer token".to_string())
+            }
+        }
+        AuthMode::Hmac => {
+            let header = |name: &str| parts.headers.get(name).and_then(|v| v.to_str().ok());
+            let timestamp: u64 = header(TIMESTAMP_HEADER)
+                .ok_or("missing signature timestamp")?
+                .parse()
+                .map_err(|_| "malformed signature timestamp")?;
+            if timestamp.abs_diff(now) > auth.max_clock_skew_seconds {
+                return Err("signature timestamp outside the allowed window".to_string());
+            }
+            let signature = header(SIGNATURE_HEADER)
+                .and_then(|v| v.strip_prefix("sha256="))
+                .ok_or("missing signature")?;
+            let signature = hex::decode(signature).map_err(|_| "malformed signature")?;
+
+            let path = parts
+                .uri
+                .path_and_query()
+                .map(|p| p.as_str())
+                .unwrap_or("/");
+            let payload = signing_payload(timestamp, parts.method.as_str(), path, body);
+            let matched = auth.keys.iter().any(|key| {
+                let mut mac = HmacSha256::new_from_slice(key.expose().as_bytes())
+                    .expect("HMAC accepts keys of any length");
+                mac.update(&payload);
+                mac.verify_slice(&signature).is_ok()
+            });
+            if matched {
+                Ok(())
+            } else {
+                Err("invalid signature".to_string())
+            }
+        }
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Reject requests that don't carry valid credentials for the configured mode. The admin API
+/// is checked against `admin_auth` when that is set, and answers `404` when it would be
+/// unauthenticated. Exempt paths (health probes, metrics) always pass. Keys come from the
+/// current config snapshot, so rotated key files take effect on reload.
+pub async fn auth_middleware(
+    State(handler): State<SharedHandler>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let handler = handler.load_full();
+    let config = handler.config();
+    let Some(auth) = config.auth_for(request.uri().path()) else {
+        return Error::NotFound(
+            "the admin API is disabled without authentication; configure admin_auth or auth"
+                .to_string(),
+        )
+        .into_response();
+    };
+
+    if auth.mode == AuthMode::None || auth.is_exempt(request.uri().path()) {
+        return next.run(request).await;
+    }
+
+    let (parts, body) = request.into_parts();
+
+    // Bearer auth never looks at the body; pass it through untouched.
+    if auth.mode == AuthMode::Bearer {
+        return match verify(auth, &parts, &[], unix_now()) {
+            Ok(()) => next.run(Request::from_parts(parts, body)).await,
+            Err(reason) => reject(&parts, reason),
+        };
+    }
+
+    let body = match axum::body::to_bytes(body, config.max_body_bytes).await {
+        Ok(bytes) => bytes,
+        Err(_) => {
+            return Error::PayloadTooLarge(format!(
+                "request body exceeds {} bytes",
+                config.max_body_bytes
+            ))
+            .into_response();
+        }
+    };
+
+    match verify(auth, &parts, &body, unix_now()) {
+        Ok(()) => next.run(Request::from_parts(parts, Body::from(body))).await,
+        Err(reason) => reject(&parts, reason),
+    }
+}
+
+fn reject(parts: &Parts, reason: String) -> Response {
+    warn!(method = %parts.method, path = %parts.uri.path(), "Rejected request: {}", reason);
+    Error::Unauthorized(reason).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretString;
+    use axum::http::Request as HttpRequest;
+
+    fn auth(mode: AuthMode, keys: &[&str]) -> AuthConfig {
+        AuthConfig {
+            mode,
+            keys: keys.iter().map(|k| SecretString::from(*k)).collect(),
+            ..AuthConfig::default()
+        }
+    }
+
+    fn parts(headers: &[(&str, String)]) -> Parts {
+        let mut builder = HttpRequest::builder().method("POST").uri("/records");
+        for (name, value) in headers {
+            builder = builder.header(*name, value);
+        }
+        builder.body(()).unwrap().into_parts().0
+    }
+
+    #[test]
+    fn bearer_accepts_any_configured_token() {
+        let auth = auth(AuthMode::Bearer, &["old", "new"]);
+        for token in ["old", "new"] {
+            let parts = parts(&[("authorization", format!("Bearer {token}"))]);
+            assert!(verify(&auth, &parts, b"", 0).is_ok());
+        }
+    }
+
+    #[test]
+    fn bearer_rejects_wrong_or_missing_token() {
+        let auth = auth(AuthMode::Bearer, &["secret"]);
+        let wrong = parts(&[("authorization", "Bearer nope".to_string())]);
+        assert!(verify(&auth, &wrong, b"", 0).is_err());
+        assert!(verify(&auth, &parts(&[]), b"", 0).is_err());
+    }
+
+    fn signed(key: &str, timestamp: u64, body: &[u8]) -> Parts {
+        parts(&[
+            (TIMESTAMP_HEADER, timestamp.to_string()),
+            (
+                SIGNATURE_HEADER,
+                sign(key, timestamp, "POST", "/records", body),
+            ),
+        ])
+    }
+
+    #[test]
+    fn hmac_accepts_valid_signature_from_any_key() {
+        let auth = auth(AuthMode::Hmac, &["k1", "k2"]);
+        let now = 1_700_000_000;
+        for key in ["k1", "k2"] {
+            let parts = signed(key, now, b"{}");
+            assert!(verify(&auth, &parts, b"{}", now).is_ok());
+        }
+    }
+
+    #[test]
+    fn hmac_rejects_tampered_body() {
+        let auth = auth(AuthMode::Hmac, &["k1"]);
+        let now = 1_700_000_000;
+        let parts = signed("k1", now, b"{}");
+        assert!(verify(&auth, &parts, b"{\"create\":[]}", now).is_err());
+    }
+
+    #[test]
+    fn hmac_rejects_stale_timestamp() {
+        let auth = auth(AuthMode::Hmac, &["k1"]);
+        let signed_at = 1_700_000_000;
+        let parts = signed("k1", signed_at, b"{}");
+        let replayed_at = signed_at + auth.max_clock_skew_seconds + 1;
+        assert!(verify(&auth, &parts, b"{}", replayed_at).is_err());
+    }
+
+    #[test]
+    fn hmac_rejects_unknown_key() {
+        let auth = auth(AuthMode::Hmac, &["k1"]);
+        let now = 1_700_000_000;
+        let parts = signed("other", now, b"{}");
+        assert!(verify(&auth, &parts, b"{}", now).is_err());
+    }
+
+    mod middleware {
+        use super::super::*;
+        use crate::config::Config;
+        use crate::webhook::testing;
+        use arc_swap::ArcSwap;
+        use axum::{
+            http::StatusCode,
+            routing::{get, post},
+            Router,
+        };
+        use std::sync::Arc;
+        use tower::ServiceExt;
+
+        fn app(config: Config) -> Router {
+            let handler: SharedHandler =
+                Arc::new(ArcSwap::from_pointee(testing::offline_handler(config)));
+
+            Router::new()
+                .route("/records", post(|body: String| async move { body }))
+                .route("/healthz", get(|| async { "ok" }))
+                .route("/admin/history", get(|| async { "history" }))
+                .layer(axum::middleware::from_fn_with_state(
+                    handler,
+                    auth_middleware,
+                ))
+        }
+
+        fn request(method: &str, path: &str, token: Option<&str>) -> Request {
+            let mut builder = Request::builder().method(method).uri(path);
+            if let Some(token) = token {
+                builder = builder.header(header::AUTHORIZATION, format!("Bearer {token}"));
+            }
+            builder.body(Body::empty()).unwrap()
+        }
+
+        async fn status(app: Router, request: Request) -> StatusCode {
+            app.oneshot(request).await.unwrap().status()
+        }
+
+        #[tokio::test]
+        async fn rejection_is_a_json_401() {
+            let config = Config {
+                auth: super::auth(AuthMode::Bearer, &["secret"]),
+                ..Config::default()
+            };
+            let response = app(config)
+                .oneshot(request("POST", "/records", Some("nope")))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            let body: serde_json::Value =
+                serde_json::from_slice(&bytes).expect("error body should be JSON");
+            assert_eq!(body["status"], 401);
+        }
+
+        #[tokio::test]
+        async fn valid_credentials_and_exempt_paths_pass() {
+            let config = Config {
+                auth: super::auth(AuthMode::Bearer, &["secret"]),
+                ..Config::default()
+            };
+            let app = app(config);
+            let authorized = request("POST", "/records", Some("secret"));
+            assert_eq!(status(app.clone(), authorized).await, StatusCode::OK);
+            let probe = request("GET", "/healthz", None);
+            assert_eq!(status(app, probe).await, StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn hmac_signature_is_checked_against_the_body() {
+            let config = Config {
+                auth: super::auth(AuthMode::Hmac, &["k1"]),
+                ..Config::default()
+            };
+            let now = unix_now();
+            let signed = |body: &'static str| {
+                Request::builder()
+                    .method("POST")
+                    .uri("/records")
+                    .header(TIMESTAMP_HEADER, now.to_string())
+                    .header(SIGNATURE_HEADER, sign("k1", now, "POST", "/records", b"{}"))
+                    .body(Body::from(body))
+                    .unwrap()
+            };
+            let app = app(config);
+            let response = app.clone().oneshot(signed("{}")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            // The handler still sees the body the middleware buffered.
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(&bytes[..], b"{}");
+            assert_eq!(
+                status(app, signed("{\"create\":[]}")).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        #[tokio::test]
+        async fn admin_api_uses_admin_auth_when_set() {
+            let config = Config {
+                auth: super::auth(AuthMode::Bearer, &["provider"]),
+                admin_auth: Some(super::auth(AuthMode::Bearer, &["admin"])),
+                ..Config::default()
+            };
+            let app = app(config);
+            let as_provider = request("GET", "/admin/history", Some("provider"));
+            assert_eq!(
+                status(app.clone(), as_provider).await,
+                StatusCode::UNAUTHORIZED
+            );
+            let as_admin = request("GET", "/admin/history", Some("admin"));
+            assert_eq!(status(app.clone(), as_admin).await, StatusCode::OK);
+            // The admin key doesn't open the provider endpoints.
+            let admin_on_provider = request("POST", "/records", Some("admin"));
+            assert_eq!(
+                status(app, admin_on_provider).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        #[tokio::test]
+        async fn admin_api_falls_back_to_provider_auth() {
+            let config = Config {
+                auth: super::auth(AuthMode::Bearer, &["provider"]),
+                ..Config::default()
+            };
+            let app = app(config);
+            let anonymous = request("GET", "/admin/history", None);
+            assert_eq!(
+                status(app.clone(), anonymous).await,
+                StatusCode::UNAUTHORIZED
+            );
+            let as_provider = request("GET", "/admin/history", Some("provider"));
+            assert_eq!(status(app, as_provider).await, StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn unauthenticated_admin_api_is_not_found() {
+            let app = app(Config::default());
+            let history = request("GET", "/admin/history", None);
+            assert_eq!(status(app.clone(), history).await, StatusCode::NOT_FOUND);
+            // Without auth the provider endpoints stay open.
+            let records = request("POST", "/records", None);
+            assert_eq!(status(app, records).await, StatusCode::OK);
+        }
+
+        #[tokio::test]
+        async fn admin_auth_of_none_disables_the_admin_api() {
+            let config = Config {
+                auth: super::auth(AuthMode::Bearer, &["provider"]),
+                admin_auth: Some(AuthConfig::default()),
+                ..Config::default()
+            };
+            let history = request("GET", "/admin/history", Some("provider"));
+            assert_eq!(status(app(config), history).await, StatusCode::NOT_FOUND);
+        }
+    }
+}
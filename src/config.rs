@@ -1,12 +1,94 @@
-use anyhow::Result;
+use crate::secret::SecretString;
+use anyhow::{bail, Context, Result};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::env;
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Environment variables that override keys from the config file, as `(variable, key)`.
+/// Precedence is defaults < config file < environment.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("NJALLA_API_TOKEN", "njalla_api_token"),
+    ("NJALLA_API_TOKEN_FILE", "njalla_api_token_file"),
+    ("WEBHOOK_HOST", "webhook_host"),
+    ("WEBHOOK_PORT", "webhook_port"),
+    ("DRY_RUN", "dry_run"),
+    ("CACHE_TTL_SECONDS", "cache_ttl_seconds"),
+    ("NJALLA_MAX_RETRIES", "njalla_max_retries"),
+    ("NJALLA_RETRY_BASE_MS", "njalla_retry_base_ms"),
+    ("RELOAD_INTERVAL_SECONDS", "reload_interval_seconds"),
+    ("PAYLOAD_LOG", "payload_log"),
+    ("PAYLOAD_LOG_MAX_BYTES", "payload_log_max_bytes"),
+    ("MAX_BODY_BYTES", "max_body_bytes"),
+    ("REQUEST_TIMEOUT_SECONDS", "request_timeout_seconds"),
+    ("MAX_CONCURRENT_REQUESTS", "max_concurrent_requests"),
+    ("APPLY_DEADLINE_SECONDS", "apply_deadline_seconds"),
+    ("APPLY_RETRY_BUDGET", "apply_retry_budget"),
+    ("AUTH_MODE", "auth.mode"),
+    ("AUTH_MAX_CLOCK_SKEW_SECONDS", "auth.max_clock_skew_seconds"),
+    ("ADMIN_AUTH_MODE", "admin_auth.mode"),
+    ("TLS_CERT_FILE", "tls.cert_file"),
+    ("TLS_KEY_FILE", "tls.key_file"),
+    ("TLS_CLIENT_CA_FILE", "tls.client_ca_file"),
+    ("UNIX_SOCKET_MODE", "unix_socket_mode"),
+    ("JOURNAL_FILE", "journal_file"),
+    ("JOURNAL_RECOVERY", "journal_recovery"),
+    ("HISTORY_FILE", "history_file"),
+    ("SNAPSHOT_DIR", "snapshot_dir"),
+    ("SNAPSHOT_INTERVAL_SECONDS", "snapshot_interval_seconds"),
+    ("SNAPSHOT_RETENTION", "snapshot_retention"),
+    (
+        "DRIFT_CHECK_INTERVAL_SECONDS",
+        "drift_check_interval_seconds",
+    ),
+    ("DRIFT_REPAIR", "drift_repair"),
+    ("DEDUPE_INTERVAL_SECONDS", "dedupe_interval_seconds"),
+    ("DEDUPE_MAX_REMOVALS", "dedupe_max_removals"),
+    ("DEDUPE_REMOVAL_DELAY_MS", "dedupe_removal_delay_ms"),
+    ("REGISTRY_TXT_PREFIX", "registry_txt_prefix"),
+    ("RETARGET_MAX_EDITS", "retarget_max_edits"),
+    ("RETARGET_EDIT_DELAY_MS", "retarget_edit_delay_ms"),
+];
+
+/// Comma-separated environment variables that override list keys, as `(variable, key)`.
+const LIST_ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("DOMAIN_FILTER", "domain_filter"),
+    ("LISTEN", "listen"),
+    ("PAYLOAD_REDACT_TYPES", "payload_redact_types"),
+    ("AUTH_BEARER_TOKEN_FILES", "auth.bearer_token_files"),
+    ("AUTH_HMAC_KEY_FILES", "auth.hmac_key_files"),
+    ("AUTH_EXEMPT_PATHS", "auth.exempt_paths"),
+    (
+        "ADMIN_AUTH_BEARER_TOKEN_FILES",
+        "admin_auth.bearer_token_files",
+    ),
+    ("ADMIN_AUTH_HMAC_KEY_FILES", "admin_auth.hmac_key_files"),
+];
+
+/// Record types external-dns can ask us to manage; used to validate per-zone policy.
+pub(crate) const KNOWN_RECORD_TYPES: &[&str] =
+    &["A", "AAAA", "CNAME", "TXT", "MX", "SRV", "NS", "CAA"];
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub njalla_api_token: String,
+    /// Redacted in `Debug` and serialized output; see [`SecretString`].
+    #[serde(default, skip_serializing_if = "SecretString::is_empty")]
+    pub njalla_api_token: SecretString,
+    /// File holding the API token (e.g. a mounted Kubernetes secret). When set, the token is
+    /// read from it on every (re)load instead of `njalla_api_token`.
+    pub njalla_api_token_file: Option<PathBuf>,
     pub webhook_host: String,
     pub webhook_port: u16,
+    /// Where to accept connections: any of `tcp:<ip>:<port>`, `unix:<path>` and `systemd`
+    /// (sockets passed via `LISTEN_FDS`). Empty means `tcp:<webhook_host>:<webhook_port>`.
+    #[serde(default)]
+    pub listen: Vec<String>,
+    /// Octal permissions applied to Unix sockets after they are bound.
+    pub unix_socket_mode: String,
     pub domain_filter: Option<Vec<String>>,
     pub dry_run: bool,
     pub cache_ttl_seconds: u64,
@@ -15,54 +97,615 @@ pub struct Config {
     pub njalla_max_retries: u32,
     /// Base delay in milliseconds for the exponential backoff between retries.
     pub njalla_retry_base_ms: u64,
+    /// How often the config and token files are checked for changes; 0 disables watching
+    /// (SIGHUP still triggers a reload).
+    pub reload_interval_seconds: u64,
+    /// How much of each POST `/records` payload is logged (at DEBUG).
+    pub payload_log: PayloadLogMode,
+    /// Logged payloads are truncated to this many bytes.
+    pub payload_log_max_bytes: usize,
+    /// Record types whose targets are replaced with `[REDACTED]` in logged payloads; `*`
+    /// redacts every type.
+    pub payload_redact_types: Vec<String>,
+    /// Request bodies larger than this are rejected with 413.
+    pub max_body_bytes: usize,
+    /// Requests still running after this many seconds are aborted with 504; 0 disables.
+    pub request_timeout_seconds: u64,
+    /// In-flight requests allowed per route before new ones get 503; 0 disables.
+    pub max_concurrent_requests: usize,
+    /// Time a POST `/records` batch may spend on Njalla calls, including retries. Changes not
+    /// started by then are reported as not attempted. Keep it below the timeout external-dns
+    /// applies to webhook requests; 0 disables.
+    pub apply_deadline_seconds: u64,
+    /// Retries shared by all Njalla calls of one batch; 0 leaves only `njalla_max_retries`.
+    pub apply_retry_budget: u32,
+    /// Write-ahead journal of record changes, so a change interrupted by a crash is reconciled
+    /// on the next start. Unset disables journaling.
+    pub journal_file: Option<PathBuf>,
+    /// What to do with changes found unfinished in the journal at startup.
+    pub journal_recovery: JournalRecovery,
+    /// Append-only JSON Lines history of applied record changes, listed and undone through the
+    /// admin API. Unset disables history.
+    pub history_file: Option<PathBuf>,
+    /// Where zone snapshots are written (JSON and BIND zone file per snapshot). Unset disables
+    /// snapshots.
+    pub snapshot_dir: Option<PathBuf>,
+    /// How often every managed zone is snapshotted; 0 leaves only on-demand snapshots.
+    pub snapshot_interval_seconds: u64,
+    /// Snapshots kept per zone, newest first; 0 keeps all.
+    pub snapshot_retention: usize,
+    /// How often zones are compared with the records this webhook last applied; 0 leaves only
+    /// on-demand checks.
+    pub drift_check_interval_seconds: u64,
+    /// Put drifted zones back to their baseline after each background check. Drift the history
+    /// file records is adopted instead; without one, changes made with the CLI are reverted too.
+    pub drift_repair: bool,
+    /// How often duplicate records are removed from every managed zone; 0 leaves only on-demand
+    /// runs.
+    pub dedupe_interval_seconds: u64,
+    /// Most duplicates one dedupe run removes, across all zones. The rest wait for the next run.
+    pub dedupe_max_removals: usize,
+    /// Pause between two dedupe removals, to stay clear of Njalla's rate limit.
+    pub dedupe_removal_delay_ms: u64,
+    /// external-dns's `--txt-prefix`: the start of its ownership TXT record names.
+    pub registry_txt_prefix: String,
+    /// Most records one retarget run may edit. A larger plan is refused as a whole, so a
+    /// renumbering is never left half done by the cap.
+    pub retarget_max_edits: usize,
+    /// Pause between two retarget edits, to stay clear of Njalla's rate limit.
+    pub retarget_edit_delay_ms: u64,
+    /// Inbound authentication for the webhook API (`[auth]` in TOML).
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Authentication for the admin API under `/admin` (`[admin_auth]` in TOML), replacing
+    /// `auth` there. Unset, the admin API uses `auth`.
+    #[serde(default)]
+    pub admin_auth: Option<AuthConfig>,
+    /// HTTPS on the webhook listener (`[tls]` in TOML).
+    #[serde(default)]
+    pub tls: TlsConfig,
+    /// Per-zone options, keyed by zone name. Only settable from the config file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub zones: BTreeMap<String, ZoneConfig>,
+    /// Standard record sets, keyed by template name. Only settable from the config file.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub templates: BTreeMap<String, Template>,
+}
+
+/// Payload logging for POST `/records`: nothing, change counts and dnsNames, or the full
+/// (redacted, truncated) body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadLogMode {
+    Off,
+    #[default]
+    Summary,
+    Full,
+}
+
+/// How changes left unfinished by a crash are reconciled at startup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalRecovery {
+    /// Carry out the operations that hadn't happened yet.
+    #[default]
+    Complete,
+    /// Undo the operations that had already happened.
+    Rollback,
+}
+
+/// How inbound requests are authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// No authentication (the default, for loopback sidecars).
+    #[default]
+    None,
+    /// `Authorization: Bearer <token>` matching one of the configured tokens.
+    Bearer,
+    /// HMAC-SHA256 signature over the timestamp, method, path and body.
+    Hmac,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct AuthConfig {
+    pub mode: AuthMode,
+    /// Files holding accepted bearer tokens, one per line. Several tokens may be valid at once
+    /// so they can be rotated without downtime.
+    pub bearer_token_files: Vec<PathBuf>,
+    /// Files holding accepted HMAC keys, one per line.
+    pub hmac_key_files: Vec<PathBuf>,
+    /// Signed requests whose timestamp is further than this from now are rejected as replays.
+    pub max_clock_skew_seconds: u64,
+    /// Paths that never require authentication (health probes, metrics).
+    pub exempt_paths: Vec<String>,
+    /// Keys read from the files of the active mode on every (re)load.
+    #[serde(skip)]
+    pub keys: Vec<SecretString>,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            mode: AuthMode::None,
+            bearer_token_files: Vec::new(),
+            hmac_key_files: Vec::new(),
+            max_clock_skew_seconds: 300,
+            exempt_paths: vec![
+                "/healthz".to_string(),
+                "/ready".to_string(),
+                "/metrics".to_string(),
+            ],
+            keys: Vec::new(),
+        }
+    }
+}
+
+impl AuthConfig {
+    /// Key files for the active mode.
+    pub fn key_files(&self) -> &[PathBuf] {
+        match self.mode {
+            AuthMode::None => &[],
+            AuthMode::Bearer => &self.bearer_token_files,
+            AuthMode::Hmac => &self.hmac_key_files,
+        }
+    }
+
+    /// Read the keys of the active mode. `section` names the config section in errors.
+    fn load_keys(&mut self, section: &str) -> Result<()> {
+        let mut keys = Vec::new();
+        for path in self.key_files() {
+            let contents =
+                zeroize::Zeroizing::new(std::fs::read_to_string(path).with_context(|| {
+                    format!("{section}: cannot read key file {}", path.display())
+                })?);
+            keys.extend(
+                contents
+                    .lines()
+                    .map(str::trim)
+                    .filter(|line| !line.is_empty())
+                    .map(SecretString::from),
+            );
+        }
+        self.keys = keys;
+        Ok(())
+    }
+
+    fn validate(&self, section: &str) -> Result<()> {
+        match self.mode {
+            AuthMode::None => {}
+            AuthMode::Bearer if self.bearer_token_files.is_empty() => {
+                bail!("{section}.bearer_token_files: required when {section}.mode = \"bearer\"")
+            }
+            AuthMode::Hmac if self.hmac_key_files.is_empty() => {
+                bail!("{section}.hmac_key_files: required when {section}.mode = \"hmac\"")
+            }
+            _ if self.keys.is_empty() => {
+                bail!("{section}: the configured key files contain no keys")
+            }
+            _ => {}
+        }
+        if let Some(path) = self.exempt_paths.iter().find(|p| !p.starts_with('/')) {
+            bail!("{section}.exempt_paths: '{path}' must start with '/'");
+        }
+        Ok(())
+    }
+
+    pub fn is_exempt(&self, path: &str) -> bool {
+        self.exempt_paths.iter().any(|p| p == path)
+    }
+}
+
+/// TLS for the webhook listener. TLS is on when `cert_file` is set; adding `client_ca_file`
+/// makes clients authenticate with a certificate signed by that CA (mutual TLS).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_file: Option<PathBuf>,
+    /// PEM private key for `cert_file`.
+    pub key_file: Option<PathBuf>,
+    /// PEM CA bundle used to verify client certificates.
+    pub client_ca_file: Option<PathBuf>,
+}
+
+impl TlsConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.cert_file.is_some()
+    }
+
+    /// Certificate, key and CA files, watched so renewed certificates are picked up.
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.cert_file
+            .iter()
+            .chain(self.key_file.iter())
+            .chain(self.client_ca_file.iter())
+    }
+
+    fn validate(&self) -> Result<()> {
+        match (&self.cert_file, &self.key_file) {
+            (Some(_), None) => bail!("tls.key_file: required when tls.cert_file is set"),
+            (None, Some(_)) => bail!("tls.cert_file: required when tls.key_file is set"),
+            _ => {}
+        }
+        if self.client_ca_file.is_some() && self.cert_file.is_none() {
+            bail!("tls.client_ca_file: requires tls.cert_file and tls.key_file");
+        }
+        Ok(())
+    }
+}
+
+/// One entry of `listen`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+    /// Every socket systemd passed in through socket activation.
+    Systemd,
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            _ if s == "systemd" => Ok(Self::Systemd),
+            Some(("tcp", addr)) => addr
+                .parse()
+                .map(Self::Tcp)
+                .map_err(|_| format!("'{s}': expected tcp:<ip>:<port>, e.g. tcp:[::1]:8888")),
+            Some(("unix", path)) if !path.is_empty() => Ok(Self::Unix(PathBuf::from(path))),
+            _ => Err(format!(
+                "'{s}': expected tcp:<ip>:<port>, unix:<path> or systemd"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+            Self::Unix(path) => write!(f, "unix:{}", path.display()),
+            Self::Systemd => f.write_str("systemd"),
+        }
+    }
+}
+
+/// Options that apply to a single managed zone (`[zones."example.com"]` in TOML).
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ZoneConfig {
+    /// TTL used when external-dns does not send `recordTTL` for an endpoint.
+    pub default_ttl: Option<u32>,
+    /// Policy: when set, only these record types may be created or deleted in the zone.
+    pub record_types: Option<Vec<String>>,
+    /// Templates the zone should carry; the drift check reports their missing records.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub templates: Vec<String>,
+}
+
+/// Records every zone using the template should have (`[templates.mail]` in TOML). `{zone}` in
+/// a record's name or content stands for the zone name.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct Template {
+    pub records: Vec<TemplateRecord>,
+}
+
+/// One record of a [`Template`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateRecord {
+    /// Relative to the zone; `@` or empty for the apex.
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub content: String,
+    /// Defaults to the zone's `default_ttl`, then 3600.
+    pub ttl: Option<u32>,
+    pub priority: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            njalla_api_token: SecretString::default(),
+            njalla_api_token_file: None,
+            webhook_host: "127.0.0.1".to_string(),
+            webhook_port: 8888,
+            listen: Vec::new(),
+            unix_socket_mode: "660".to_string(),
+            domain_filter: None,
+            dry_run: false,
+            cache_ttl_seconds: 60,
+            njalla_max_retries: 3,
+            njalla_retry_base_ms: 500,
+            reload_interval_seconds: 10,
+            payload_log: PayloadLogMode::default(),
+            payload_log_max_bytes: 4096,
+            payload_redact_types: vec!["TXT".to_string()],
+            max_body_bytes: 10 * 1024 * 1024,
+            request_timeout_seconds: 120,
+            max_concurrent_requests: 4,
+            apply_deadline_seconds: 25,
+            apply_retry_budget: 10,
+            journal_file: None,
+            journal_recovery: JournalRecovery::default(),
+            history_file: None,
+            snapshot_dir: None,
+            snapshot_interval_seconds: 3600,
+            snapshot_retention: 48,
+            drift_check_interval_seconds: 300,
+            drift_repair: false,
+            dedupe_interval_seconds: 0,
+            dedupe_max_removals: 500,
+            dedupe_removal_delay_ms: 200,
+            registry_txt_prefix: "_externaldns.".to_string(),
+            retarget_max_edits: 100,
+            retarget_edit_delay_ms: 200,
+            auth: AuthConfig::default(),
+            admin_auth: None,
+            tls: TlsConfig::default(),
+            zones: BTreeMap::new(),
+            templates: BTreeMap::new(),
+        }
+    }
 }
 
 impl Config {
-    pub fn from_env() -> Result<Self> {
+    /// Load the configuration: built-in defaults, then the optional TOML file, then
+    /// environment variables (including a `.env` file), and validate the result.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
         dotenvy::dotenv().ok();
+        Self::load_with_env(path, |var| env::var(var).ok())
+    }
 
-        let njalla_api_token = env::var("NJALLA_API_TOKEN")
-            .expect("NJALLA_API_TOKEN environment variable is required");
+    /// [`Self::load`] with an injectable environment, so precedence can be tested without
+    /// mutating the process environment.
+    pub(crate) fn load_with_env(
+        path: Option<&Path>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let mut builder = config::Config::builder().add_source(
+            config::Config::try_from(&Config::default())
+                .context("failed to build default configuration")?,
+        );
 
-        let webhook_host = env::var("WEBHOOK_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
+        if let Some(path) = path {
+            if !path.is_file() {
+                bail!("config file {} does not exist", path.display());
+            }
+            builder = builder.add_source(
+                config::File::from(path)
+                    .format(config::FileFormat::Toml)
+                    .required(true),
+            );
+        }
+
+        for (var, key) in ENV_OVERRIDES {
+            builder = builder.set_override_option(*key, env(var))?;
+        }
 
-        let webhook_port = env::var("WEBHOOK_PORT")
-            .unwrap_or_else(|_| "8888".to_string())
-            .parse::<u16>()?;
+        for (var, key) in LIST_ENV_OVERRIDES {
+            if let Some(s) = env(var) {
+                let items: Vec<String> = s
+                    .split(',')
+                    .map(|item| item.trim().to_string())
+                    .filter(|item| !item.is_empty())
+                    .collect();
+                builder = builder.set_override(*key, items)?;
+            }
+        }
 
-        let domain_filter = env::var("DOMAIN_FILTER").ok().map(|s| {
-            s.split(',')
-                .map(|d| Self::normalize_domain(d.trim()))
-                .filter(|d| !d.is_empty())
-                .collect()
-        });
+        let mut config: Config =
+            builder
+                .build()
+                .and_then(|c| c.try_deserialize())
+                .map_err(|e| match path {
+                    Some(path) => anyhow::anyhow!(
+                        "invalid configuration ({} + environment): {e}",
+                        path.display()
+                    ),
+                    None => anyhow::anyhow!("invalid configuration: {e}"),
+                })?;
 
-        let dry_run = env::var("DRY_RUN")
-            .unwrap_or_else(|_| "false".to_string())
-            .parse::<bool>()?;
+        config.normalize();
+        config.read_token_file()?;
+        config.auth.load_keys("auth")?;
+        if let Some(admin_auth) = config.admin_auth.as_mut() {
+            admin_auth.load_keys("admin_auth")?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
 
-        let cache_ttl_seconds = env::var("CACHE_TTL_SECONDS")
-            .unwrap_or_else(|_| "60".to_string())
-            .parse::<u64>()?;
+    fn read_token_file(&mut self) -> Result<()> {
+        let Some(path) = self.njalla_api_token_file.as_ref() else {
+            return Ok(());
+        };
+        if !self.njalla_api_token.is_empty() {
+            bail!(
+                "njalla_api_token and njalla_api_token_file are mutually exclusive; set only one"
+            );
+        }
+        let token =
+            zeroize::Zeroizing::new(std::fs::read_to_string(path).with_context(|| {
+                format!("njalla_api_token_file: cannot read {}", path.display())
+            })?);
+        self.njalla_api_token = SecretString::from(token.trim());
+        Ok(())
+    }
 
-        let njalla_max_retries = env::var("NJALLA_MAX_RETRIES")
-            .unwrap_or_else(|_| "3".to_string())
-            .parse::<u32>()?;
+    /// Files whose contents feed this configuration and should be watched for reloads.
+    pub fn watched_files(&self, config_path: Option<&Path>) -> Vec<PathBuf> {
+        config_path
+            .map(Path::to_path_buf)
+            .into_iter()
+            .chain(self.njalla_api_token_file.clone())
+            .chain(self.auth.key_files().iter().cloned())
+            .chain(
+                self.admin_auth
+                    .iter()
+                    .flat_map(|a| a.key_files().iter().cloned()),
+            )
+            .chain(self.tls.files().cloned())
+            .collect()
+    }
 
-        let njalla_retry_base_ms = env::var("NJALLA_RETRY_BASE_MS")
-            .unwrap_or_else(|_| "500".to_string())
-            .parse::<u64>()?;
+    /// The authentication that applies to requests for `path`, or `None` for admin paths when
+    /// the admin API would be unauthenticated: it can rewrite any managed zone, so it is only
+    /// served behind `admin_auth`, or `auth` when that isn't set, with a mode other than none.
+    pub fn auth_for(&self, path: &str) -> Option<&AuthConfig> {
+        let is_admin = path == "/admin" || path.starts_with("/admin/");
+        let auth = match &self.admin_auth {
+            Some(admin_auth) if is_admin => admin_auth,
+            _ => &self.auth,
+        };
+        (!is_admin || auth.mode != AuthMode::None).then_some(auth)
+    }
 
-        Ok(Config {
-            njalla_api_token,
-            webhook_host,
-            webhook_port,
-            domain_filter,
-            dry_run,
-            cache_ttl_seconds,
-            njalla_max_retries,
-            njalla_retry_base_ms,
-        })
+    /// Canonicalize domain names coming from either the file or the environment.
+    fn normalize(&mut self) {
+        if let Some(filter) = self.domain_filter.as_mut() {
+            for d in filter.iter_mut() {
+                *d = Self::normalize_domain(d.trim());
+            }
+            filter.retain(|d| !d.is_empty());
+        }
+        self.zones = std::mem::take(&mut self.zones)
+            .into_iter()
+            .map(|(zone, mut options)| {
+                if let Some(types) = options.record_types.as_mut() {
+                    for t in types.iter_mut() {
+                        *t = t.to_ascii_uppercase();
+                    }
+                }
+                (Self::normalize_domain(&zone), options)
+            })
+            .collect();
+        for t in self.payload_redact_types.iter_mut() {
+            *t = t.to_ascii_uppercase();
+        }
+    }
+
+    /// Check semantic constraints the type system can't express. Errors name the offending key.
+    pub fn validate(&self) -> Result<()> {
+        if self.njalla_api_token.expose().trim().is_empty() {
+            bail!("njalla_api_token is required (set NJALLA_API_TOKEN or njalla_api_token in the config file)");
+        }
+        // Checks `webhook_host` too, unless `listen` replaces it.
+        self.listen_addrs()?;
+        self.unix_socket_mode()?;
+        if self.max_body_bytes == 0 {
+            bail!("max_body_bytes: must be greater than 0");
+        }
+        if self.dedupe_max_removals == 0 {
+            bail!("dedupe_max_removals: must be greater than 0");
+        }
+        if self.retarget_max_edits == 0 {
+            bail!("retarget_max_edits: must be greater than 0");
+        }
+        self.auth.validate("auth")?;
+        if let Some(admin_auth) = &self.admin_auth {
+            admin_auth.validate("admin_auth")?;
+        }
+        self.tls.validate()?;
+        for (zone, options) in &self.zones {
+            if zone.is_empty() {
+                bail!("zones: zone name must not be empty");
+            }
+            if options.default_ttl == Some(0) {
+                bail!("zones.\"{zone}\".default_ttl: must be greater than 0");
+            }
+            for t in options.record_types.iter().flatten() {
+                if !KNOWN_RECORD_TYPES.contains(&t.as_str()) {
+                    bail!("zones.\"{zone}\".record_types: unknown record type '{t}'");
+                }
+            }
+            for t in &options.templates {
+                if !self.templates.contains_key(t) {
+                    bail!("zones.\"{zone}\".templates: unknown template '{t}'");
+                }
+            }
+        }
+        for (name, template) in &self.templates {
+            if template.records.is_empty() {
+                bail!("templates.{name}.records: must not be empty");
+            }
+            for record in &template.records {
+                if !KNOWN_RECORD_TYPES.contains(&record.record_type.as_str()) {
+                    bail!(
+                        "templates.{name}.records: unknown record type '{}'",
+                        record.record_type
+                    );
+                }
+                if record.ttl == Some(0) {
+                    bail!("templates.{name}.records: ttl must be greater than 0");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The parsed `listen` entries, defaulting to `webhook_host`:`webhook_port`.
+    pub fn listen_addrs(&self) -> Result<Vec<ListenAddr>> {
+        if self.listen.is_empty() {
+            let ip: IpAddr = self.webhook_host.parse().with_context(|| {
+                format!(
+                    "webhook_host: '{}' is not a valid IP address",
+                    self.webhook_host
+                )
+            })?;
+            return Ok(vec![ListenAddr::Tcp(SocketAddr::new(
+                ip,
+                self.webhook_port,
+            ))]);
+        }
+        self.listen
+            .iter()
+            .map(|s| s.parse().map_err(|e| anyhow::anyhow!("listen: {e}")))
+            .collect()
+    }
+
+    /// `unix_socket_mode` as permission bits.
+    pub fn unix_socket_mode(&self) -> Result<u32> {
+        u32::from_str_radix(self.unix_socket_mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .with_context(|| {
+                format!(
+                    "unix_socket_mode: '{}' is not an octal permission mode like 660",
+                    self.unix_socket_mode
+                )
+            })
+    }
+
+    /// Render the effective configuration as TOML. The API token serializes as `[REDACTED]`.
+    pub fn to_redacted_toml(&self) -> Result<String> {
+        toml::to_string_pretty(self).context("failed to render configuration")
+    }
+
+    /// Options for `zone`, if the config file defines any.
+    pub fn zone(&self, zone: &str) -> Option<&ZoneConfig> {
+        self.zones.get(&Self::normalize_domain(zone))
+    }
+
+    /// Whether targets of `record_type` must be redacted from logged payloads.
+    pub fn is_payload_redacted(&self, record_type: &str) -> bool {
+        self.payload_redact_types
+            .iter()
+            .any(|t| t == "*" || t.eq_ignore_ascii_case(record_type))
+    }
+
+    /// Whether the zone's policy (if any) permits managing `record_type`.
+    pub fn is_record_type_allowed(&self, zone: &str, record_type: &str) -> bool {
+        match self.zone(zone).and_then(|z| z.record_types.as_ref()) {
+            Some(types) => types.iter().any(|t| t.eq_ignore_ascii_case(record_type)),
+            None => true,
+        }
     }
 
     pub fn is_domain_allowed(&self, domain: &str) -> bool {
@@ -89,14 +732,8 @@ mod tests {
 
     fn config_with_filter(domains: Vec<&str>) -> Config {
         Config {
-            njalla_api_token: String::new(),
-            webhook_host: String::new(),
-            webhook_port: 8888,
             domain_filter: Some(domains.into_iter().map(Config::normalize_domain).collect()),
-            dry_run: false,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
+            ..Config::default()
         }
     }
 
@@ -151,15 +788,362 @@ mod tests {
     #[test]
     fn none_filter_allows_all() {
         let config = Config {
-            njalla_api_token: String::new(),
-            webhook_host: String::new(),
-            webhook_port: 8888,
             domain_filter: None,
-            dry_run: false,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
+            ..Config::default()
         };
         assert!(config.is_domain_allowed("anything.com"));
     }
+
+    fn write_config(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::Builder::new()
+            .suffix(".toml")
+            .tempfile()
+            .expect("temp file");
+        std::io::Write::write_all(&mut file, contents.as_bytes()).expect("write config");
+        file
+    }
+
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: std::collections::HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn env_only_uses_defaults() {
+        let config =
+            Config::load_with_env(None, env_from(&[("NJALLA_API_TOKEN", "secret")])).unwrap();
+        assert_eq!(config.njalla_api_token.expose(), "secret");
+        assert_eq!(config.webhook_host, "127.0.0.1");
+        assert_eq!(config.webhook_port, 8888);
+        assert!(config.domain_filter.is_none());
+    }
+
+    #[test]
+    fn file_values_are_loaded() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "from-file"
+            webhook_port = 9999
+            domain_filter = ["Example.COM."]
+
+            [zones."example.com"]
+            default_ttl = 300
+            record_types = ["a", "TXT"]
+            "#,
+        );
+        let config = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap();
+        assert_eq!(config.njalla_api_token.expose(), "from-file");
+        assert_eq!(config.webhook_port, 9999);
+        assert_eq!(config.domain_filter, Some(vec!["example.com".to_string()]));
+        assert_eq!(config.zone("EXAMPLE.com.").unwrap().default_ttl, Some(300));
+        assert!(config.is_record_type_allowed("example.com", "A"));
+        assert!(!config.is_record_type_allowed("example.com", "CNAME"));
+        assert!(config.is_record_type_allowed("other.com", "CNAME"));
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "from-file"
+            webhook_port = 9999
+            domain_filter = ["example.com"]
+            "#,
+        );
+        let config = Config::load_with_env(
+            Some(file.path()),
+            env_from(&[
+                ("NJALLA_API_TOKEN", "from-env"),
+                ("WEBHOOK_PORT", "7777"),
+                ("DOMAIN_FILTER", "a.com, b.com"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.njalla_api_token.expose(), "from-env");
+        assert_eq!(config.webhook_port, 7777);
+        assert_eq!(
+            config.domain_filter,
+            Some(vec!["a.com".to_string(), "b.com".to_string()])
+        );
+    }
+
+    #[test]
+    fn bad_value_error_names_the_key() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "t"
+            webhook_port = "not-a-port"
+            "#,
+        );
+        let err = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap_err();
+        assert!(err.to_string().contains("webhook_port"), "{err}");
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "t"
+            webhook_prot = 1
+            "#,
+        );
+        let err = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap_err();
+        assert!(err.to_string().contains("webhook_prot"), "{err}");
+    }
+
+    #[test]
+    fn payload_log_settings_from_env() {
+        let config = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("PAYLOAD_LOG", "full"),
+                ("PAYLOAD_REDACT_TYPES", "txt, A"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(config.payload_log, PayloadLogMode::Full);
+        assert!(config.is_payload_redacted("TXT"));
+        assert!(config.is_payload_redacted("a"));
+        assert!(!config.is_payload_redacted("CNAME"));
+    }
+
+    #[test]
+    fn auth_keys_are_loaded_from_several_files() {
+        let first = write_config("key-one\nkey-two\n");
+        let second = write_config("key-three");
+        let files = format!("{},{}", first.path().display(), second.path().display());
+        let config = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("AUTH_MODE", "bearer"),
+                ("AUTH_BEARER_TOKEN_FILES", &files),
+            ]),
+        )
+        .unwrap();
+        let keys: Vec<&str> = config.auth.keys.iter().map(|k| k.expose()).collect();
+        assert_eq!(keys, vec!["key-one", "key-two", "key-three"]);
+    }
+
+    #[test]
+    fn auth_mode_without_key_files_is_rejected() {
+        let err = Config::load_with_env(
+            None,
+            env_from(&[("NJALLA_API_TOKEN", "t"), ("AUTH_MODE", "hmac")]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("auth.hmac_key_files"), "{err}");
+    }
+
+    #[test]
+    fn admin_api_has_its_own_auth() {
+        let webhook = write_config("webhook-token");
+        let admin = write_config("admin-token");
+        let config = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("AUTH_MODE", "bearer"),
+                (
+                    "AUTH_BEARER_TOKEN_FILES",
+                    &webhook.path().display().to_string(),
+                ),
+                ("ADMIN_AUTH_MODE", "bearer"),
+                (
+                    "ADMIN_AUTH_BEARER_TOKEN_FILES",
+                    &admin.path().display().to_string(),
+                ),
+            ]),
+        )
+        .unwrap();
+        let key = |path: &str| config.auth_for(path).unwrap().keys[0].expose().to_string();
+        assert_eq!(key("/records"), "webhook-token");
+        assert_eq!(key("/admin/zones"), "admin-token");
+        assert_eq!(key("/administrator"), "webhook-token");
+
+        let err = Config::load_with_env(
+            None,
+            env_from(&[("NJALLA_API_TOKEN", "t"), ("ADMIN_AUTH_MODE", "hmac")]),
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string().contains("admin_auth.hmac_key_files"),
+            "{err}"
+        );
+
+        let config = Config::load_with_env(None, env_from(&[("NJALLA_API_TOKEN", "t")])).unwrap();
+        assert!(config.admin_auth.is_none());
+        assert!(config.auth_for("/records").is_some());
+        assert!(config.auth_for("/admin/history").is_none());
+    }
+
+    #[test]
+    fn tls_cert_without_key_is_rejected() {
+        let err = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("TLS_CERT_FILE", "/etc/tls/tls.crt"),
+            ]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("tls.key_file"), "{err}");
+    }
+
+    #[test]
+    fn listen_defaults_to_webhook_host_and_port() {
+        let config = Config::load_with_env(
+            None,
+            env_from(&[("NJALLA_API_TOKEN", "t"), ("WEBHOOK_PORT", "9000")]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.listen_addrs().unwrap(),
+            vec![ListenAddr::Tcp("127.0.0.1:9000".parse().unwrap())]
+        );
+    }
+
+    #[test]
+    fn webhook_host_only_matters_without_listen() {
+        let env = |listen: &'static str| {
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("WEBHOOK_HOST", "localhost"),
+                ("LISTEN", listen),
+            ])
+        };
+        assert!(Config::load_with_env(None, env("")).is_err());
+        assert!(Config::load_with_env(None, env("unix:/run/webhook.sock")).is_ok());
+    }
+
+    #[test]
+    fn listen_accepts_several_forms() {
+        let config = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "t"),
+                ("LISTEN", "tcp:[::1]:8888, unix:/run/webhook.sock,systemd"),
+                ("UNIX_SOCKET_MODE", "0o600"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            config.listen_addrs().unwrap(),
+            vec![
+                ListenAddr::Tcp("[::1]:8888".parse().unwrap()),
+                ListenAddr::Unix(PathBuf::from("/run/webhook.sock")),
+                ListenAddr::Systemd,
+            ]
+        );
+        assert_eq!(config.unix_socket_mode().unwrap(), 0o600);
+    }
+
+    #[test]
+    fn invalid_listen_entry_is_rejected() {
+        for listen in ["tcp:localhost:80", "udp:1.2.3.4:53", "unix:"] {
+            let err = Config::load_with_env(
+                None,
+                env_from(&[("NJALLA_API_TOKEN", "t"), ("LISTEN", listen)]),
+            )
+            .unwrap_err();
+            assert!(err.to_string().contains("listen:"), "{err}");
+        }
+    }
+
+    #[test]
+    fn missing_token_is_rejected() {
+        let err = Config::load_with_env(None, env_from(&[])).unwrap_err();
+        assert!(err.to_string().contains("njalla_api_token"), "{err}");
+    }
+
+    #[test]
+    fn unknown_policy_record_type_is_rejected() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "t"
+            [zones."example.com"]
+            record_types = ["A", "BOGUS"]
+            "#,
+        );
+        let err = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap_err();
+        assert!(err.to_string().contains("record_types"), "{err}");
+    }
+
+    #[test]
+    fn templates_are_loaded_and_checked() {
+        let file = write_config(
+            r#"
+            njalla_api_token = "t"
+            [templates.mail]
+            records = [
+                { name = "@", type = "MX", content = "mx.example.net", priority = 10 },
+                { name = "_dmarc", type = "TXT", content = "v=DMARC1; rua=mailto:dmarc@{zone}" },
+            ]
+            [zones."example.com"]
+            templates = ["mail"]
+            "#,
+        );
+        let config = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap();
+        assert_eq!(config.templates["mail"].records[0].priority, Some(10));
+        assert_eq!(config.zone("example.com").unwrap().templates, ["mail"]);
+
+        let file = write_config(
+            r#"
+            njalla_api_token = "t"
+            [zones."example.com"]
+            templates = ["mail"]
+            "#,
+        );
+        let err = Config::load_with_env(Some(file.path()), env_from(&[])).unwrap_err();
+        assert!(err.to_string().contains("unknown template 'mail'"), "{err}");
+    }
+
+    #[test]
+    fn token_is_read_from_file() {
+        let token = write_config("  file-token\n");
+        let config = Config::load_with_env(
+            None,
+            env_from(&[("NJALLA_API_TOKEN_FILE", token.path().to_str().unwrap())]),
+        )
+        .unwrap();
+        assert_eq!(config.njalla_api_token.expose(), "file-token");
+    }
+
+    #[test]
+    fn token_and_token_file_are_exclusive() {
+        let token = write_config("file-token");
+        let err = Config::load_with_env(
+            None,
+            env_from(&[
+                ("NJALLA_API_TOKEN", "inline"),
+                ("NJALLA_API_TOKEN_FILE", token.path().to_str().unwrap()),
+            ]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("mutually exclusive"), "{err}");
+    }
+
+    #[test]
+    fn debug_output_hides_token() {
+        let config = Config {
+            njalla_api_token: SecretString::from("super-secret"),
+            ..Config::default()
+        };
+        assert!(!format!("{config:?}").contains("super-secret"));
+    }
+
+    #[test]
+    fn redacted_toml_hides_token() {
+        let config = Config {
+            njalla_api_token: SecretString::from("super-secret"),
+            ..Config::default()
+        };
+        let rendered = config.to_redacted_toml().unwrap();
+        assert!(!rendered.contains("super-secret"));
+        assert!(rendered.contains("[REDACTED]"));
+    }
 }
@@ -0,0 +1,283 @@
+//! Removal of duplicate records from zones flooded by earlier versions.
+//!
+//! Njalla's `add-record` never deduplicates, and before `create_endpoint` checked for existing
+//! records every redundant CREATE from external-dns added another identical copy. This finds
+//! records with the same name, type and content, keeps the oldest (lowest Njalla id) and plans
+//! the removal of the rest. Runs are capped by `dedupe_max_removals` and paced by
+//! `dedupe_removal_delay_ms`; a background task can repeat them every
+//! `dedupe_interval_seconds`.
+
+use crate::config::Config;
+use crate::error::Result;
+use crate::journal::Operation;
+use crate::njalla::{Budget, DnsRecord};
+use crate::periodic::{spawn_periodic, Start};
+use crate::planner::relative_name;
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::routes::SharedHandler;
+use crate::webhook::types::DedupeQuery;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Identical copies of one record.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DuplicateGroup {
+    pub name: String,
+    pub record_type: String,
+    pub content: String,
+    /// Id of the copy that stays.
+    pub keep: String,
+    /// The copies to remove.
+    #[serde(skip)]
+    pub remove: Vec<DnsRecord>,
+    /// How many copies are removed.
+    pub duplicates: usize,
+}
+
+/// Groups of identical records in `records`, ordered by name, type and content.
+pub fn find(records: &[DnsRecord]) -> Vec<DuplicateGroup> {
+    let mut groups: BTreeMap<(String, String, &str), Vec<&DnsRecord>> = BTreeMap::new();
+    for record in records {
+        groups
+            .entry((
+                relative_name(&record.name).to_ascii_lowercase(),
+                record.record_type.to_ascii_uppercase(),
+                record.content.as_str(),
+            ))
+            .or_default()
+            .push(record);
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, copies)| copies.len() > 1)
+        .map(|((name, record_type, content), mut copies)| {
+            copies.sort_by(|a, b| id_order(&a.id).cmp(&id_order(&b.id)));
+            let keep = copies.remove(0);
+            DuplicateGroup {
+                name,
+                record_type,
+                content: content.to_string(),
+                keep: keep.id.clone(),
+                duplicates: copies.len(),
+                remove: copies.into_iter().cloned().collect(),
+            }
+        })
+        .collect()
+}
+
+/// Njalla ids are increasing integers; compare them numerically where they are.
+fn id_order(id: &str) -> (u64, &str) {
+    (id.parse().unwrap_or(u64::MAX), id)
+}
+
+/// What a dedupe run found in one zone and what it did about it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DedupeReport {
+    pub zone: String,
+    /// Copies found beyond the one kept per group.
+    pub duplicates: usize,
+    /// Copies removed by this run; 0 for a dry run.
+    pub removed: usize,
+    /// Copies left for a later run: not applied, over the cap, or failed.
+    pub remaining: usize,
+    pub groups: Vec<DuplicateGroup>,
+}
+
+/// The removals for `groups`, in group order.
+pub fn operations(zone: &str, groups: &[DuplicateGroup]) -> Vec<Operation> {
+    groups
+        .iter()
+        .flat_map(|group| &group.remove)
+        .map(|record| Operation::Remove {
+            domain: zone.to_string(),
+            record: record.clone(),
+        })
+        .collect()
+}
+
+/// Deduplicate every managed zone on the configured interval.
+pub fn spawn(handler: SharedHandler) -> tokio::task::JoinHandle<()> {
+    let interval = |config: &Config| config.dedupe_interval_seconds;
+    spawn_periodic(
+        handler,
+        Start::AfterInterval,
+        interval,
+        |handler| async move {
+            let query = DedupeQuery {
+                zone: None,
+                apply: true,
+            };
+            match handler.dedupe(query).await {
+                Ok(reports) => info!(
+                    "Dedupe: removed {} duplicate record(s), {} remaining",
+                    reports.iter().map(|r| r.removed).sum::<usize>(),
+                    reports.iter().map(|r| r.remaining).sum::<usize>()
+                ),
+                Err(e) => error!("Dedupe failed: {}", e),
+            }
+        },
+    )
+}
+
+// The dedupe admin endpoint.
+impl WebhookHandler {
+    /// Find records with the same name, type and content in one zone or every managed zone.
+    /// With `apply`, remove all but the oldest copy of each, one call at a time with
+    /// `dedupe_removal_delay_ms` between calls and at most `dedupe_max_removals` per run.
+    pub async fn dedupe(&self, query: DedupeQuery) -> Result<Vec<DedupeReport>> {
+        let budget = Budget::unlimited();
+        let zones = self.selected_zones(query.zone.as_deref(), &budget).await?;
+        let apply = query.apply && !self.config.dry_run;
+        let delay = Duration::from_millis(self.config.dedupe_removal_delay_ms);
+        let mut allowance = self.config.dedupe_max_removals;
+        let batch = Batch::new(budget, None);
+
+        let mut reports = Vec::new();
+        let mut last_error = None;
+        for zone in zones {
+            let records = match self.njalla_client.list_records(&zone, &batch.budget).await {
+                Ok(records) => records,
+                Err(e) => {
+                    error!("Failed to read zone {} for dedupe: {}", zone, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let groups = find(&records);
+            let ops = operations(&zone, &groups);
+            let mut removed = 0;
+            if apply {
+                for op in ops.iter().take(allowance) {
+                    if removed > 0 {
+                        tokio::time::sleep(delay).await;
+                    }
+                    if let Err(e) = self
+                        .run_change(&format!("Dedupe {zone}"), std::slice::from_ref(op), &batch)
+                        .await
+                    {
+                        error!("Dedupe of {} stopped at {}: {}", zone, op, e);
+                        break;
+                    }
+                    removed += 1;
+                }
+                allowance -= removed;
+            }
+            info!(
+                "Dedupe of {}: {} duplicate(s) in {} group(s), {} removed",
+                zone,
+                ops.len(),
+                groups.len(),
+                removed
+            );
+            reports.push(DedupeReport {
+                zone,
+                duplicates: ops.len(),
+                removed,
+                remaining: ops.len() - removed,
+                groups,
+            });
+        }
+        match last_error {
+            Some(e) if reports.is_empty() => Err(e),
+            _ => Ok(reports),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use serde_json::json;
+
+    fn record(id: &str, name: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: "TXT".to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn keeps_the_oldest_copy_of_each_record() {
+        let records = [
+            record("120", "www", "heritage"),
+            record("9", "www", "heritage"),
+            record("100", "WWW", "heritage"),
+            record("11", "www", "other"),
+            record("12", "@", "apex"),
+            record("13", "", "apex"),
+        ];
+        let groups = find(&records);
+
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].name, "");
+        assert_eq!(groups[0].keep, "12");
+        assert_eq!(groups[1].name, "www");
+        assert_eq!(groups[1].keep, "9");
+        assert_eq!(groups[1].duplicates, 2);
+
+        let removed: Vec<String> = operations("example.com", &groups)
+            .iter()
+            .map(|op| match op {
+                Operation::Remove { record, .. } => record.id.clone(),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(removed, ["13", "100", "120"]);
+    }
+
+    #[tokio::test]
+    async fn dedupe_reports_first_and_removes_up_to_the_cap() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "4", "name": "www", "type": "TXT", "content": "heritage", "ttl": 300},
+                {"id": "2", "name": "www", "type": "TXT", "content": "heritage", "ttl": 300},
+                {"id": "3", "name": "www", "type": "TXT", "content": "heritage", "ttl": 300},
+                {"id": "5", "name": "www", "type": "TXT", "content": "heritage", "ttl": 300},
+                {"id": "6", "name": "api", "type": "A", "content": "192.0.2.1", "ttl": 300}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({}), json!({}), 2)
+            .await;
+
+        let config = Config {
+            dedupe_max_removals: 2,
+            dedupe_removal_delay_ms: 0,
+            ..testing::config()
+        };
+        let handler = njalla.handler(config);
+
+        let report = handler
+            .dedupe(DedupeQuery {
+                zone: None,
+                apply: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].duplicates, 3);
+        assert_eq!(report[0].removed, 0);
+        assert_eq!(report[0].groups[0].keep, "2");
+        assert!(!removed.matched_async().await, "a report must not write");
+
+        let report = handler
+            .dedupe(DedupeQuery {
+                zone: Some("example.com".to_string()),
+                apply: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(report[0].removed, 2);
+        assert_eq!(report[0].remaining, 1);
+        removed.assert_async().await;
+    }
+}
@@ -0,0 +1,229 @@
+//! `njalla-webhook doctor`: checks of the configuration and the Njalla API, for setting up and
+//! debugging a deployment without starting the server.
+
+use crate::config::Config;
+use crate::njalla::{Budget, DomainLister};
+use crate::output::Tabular;
+use serde::Serialize;
+use std::path::Path;
+
+/// Outcome of one check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Works, but probably not as intended.
+    Warn,
+    Fail,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    pub fn new(name: &'static str, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name,
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl Tabular for Check {
+    fn headers() -> &'static [&'static str] {
+        &["CHECK", "STATUS", "DETAIL"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        vec![
+            self.name.to_string(),
+            status.to_string(),
+            self.detail.clone(),
+        ]
+    }
+}
+
+/// Check a loaded (and so already validated) config: that the API token works, that the
+/// filtered and configured zones are on the account, and that the files it names can be used.
+pub async fn run(config: &Config, domains: &dyn DomainLister) -> Vec<Check> {
+    let mut checks = Vec::new();
+
+    let account = match domains.list_domains(&Budget::unlimited()).await {
+        Ok(account) => {
+            checks.push(Check::new(
+                "njalla api",
+                Status::Ok,
+                format!("token accepted, {} domain(s) on the account", account.len()),
+            ));
+            Some(account)
+        }
+        Err(e) => {
+            checks.push(Check::new("njalla api", Status::Fail, e.to_string()));
+            None
+        }
+    };
+
+    if let Some(account) = &account {
+        let on_account = |zone: &str| account.iter().any(|d| d.name.eq_ignore_ascii_case(zone));
+        checks.push(match &config.domain_filter {
+            None => Check::new(
+                "domain filter",
+                Status::Warn,
+                format!(
+                    "not set; all {} domain(s) on the account are managed",
+                    account.len()
+                ),
+            ),
+            Some(filter) => match missing(filter.iter().map(String::as_str), on_account) {
+                missing if missing.is_empty() => Check::new(
+                    "domain filter",
+                    Status::Ok,
+                    format!("{} domain(s), all on the account", filter.len()),
+                ),
+                missing => Check::new(
+                    "domain filter",
+                    Status::Fail,
+                    format!("not on the account: {}", missing.join(", ")),
+                ),
+            },
+        });
+        if !config.zones.is_empty() {
+            let missing = missing(config.zones.keys().map(String::as_str), on_account);
+            checks.push(if missing.is_empty() {
+                Check::new(
+                    "zones",
+                    Status::Ok,
+                    format!("{} zone(s) configured", config.zones.len()),
+                )
+            } else {
+                Check::new(
+                    "zones",
+                    Status::Warn,
+                    format!("configured but not on the account: {}", missing.join(", ")),
+                )
+            });
+        }
+    }
+
+    for (name, path) in [
+        ("journal file", config.journal_file.as_deref()),
+        ("history file", config.history_file.as_deref()),
+    ] {
+        if let Some(path) = path {
+            checks.push(file_check(name, path));
+        }
+    }
+    if let Some(dir) = &config.snapshot_dir {
+        checks.push(if dir.is_dir() {
+            Check::new("snapshot dir", Status::Ok, dir.display().to_string())
+        } else {
+            Check::new(
+                "snapshot dir",
+                Status::Warn,
+                format!(
+                    "{} doesn't exist yet; it is created on the first snapshot",
+                    dir.display()
+                ),
+            )
+        });
+    }
+    for (name, path) in [
+        ("tls cert", config.tls.cert_file.as_deref()),
+        ("tls key", config.tls.key_file.as_deref()),
+        ("tls client ca", config.tls.client_ca_file.as_deref()),
+    ] {
+        if let Some(path) = path {
+            checks.push(match std::fs::metadata(path) {
+                Ok(_) => Check::new(name, Status::Ok, path.display().to_string()),
+                Err(e) => Check::new(name, Status::Fail, format!("{}: {e}", path.display())),
+            });
+        }
+    }
+
+    if config.dry_run {
+        checks.push(Check::new(
+            "dry run",
+            Status::Warn,
+            "DRY_RUN is on; nothing will be written to Njalla",
+        ));
+    }
+    checks
+}
+
+fn missing<'a>(
+    zones: impl Iterator<Item = &'a str>,
+    on_account: impl Fn(&str) -> bool,
+) -> Vec<&'a str> {
+    zones.filter(|zone| !on_account(zone)).collect()
+}
+
+/// A file that is opened for appending: it must exist in a directory, or be creatable there.
+fn file_check(name: &'static str, path: &Path) -> Check {
+    if path.is_file() {
+        return Check::new(name, Status::Ok, path.display().to_string());
+    }
+    match path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        Some(dir) if !dir.is_dir() => Check::new(
+            name,
+            Status::Fail,
+            format!("directory {} doesn't exist", dir.display()),
+        ),
+        _ => Check::new(
+            name,
+            Status::Ok,
+            format!("{} (created on first use)", path.display()),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::njalla::Domain;
+
+    struct Account(Vec<&'static str>);
+
+    #[async_trait::async_trait]
+    impl DomainLister for Account {
+        async fn list_domains(&self, _budget: &Budget) -> crate::error::Result<Vec<Domain>> {
+            Ok(self
+                .0
+                .iter()
+                .map(|name| Domain {
+                    name: name.to_string(),
+                    status: "active".to_string(),
+                    expiry: None,
+                })
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_domains_missing_from_the_account_and_unusable_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            domain_filter: Some(vec!["example.com".to_string(), "example.org".to_string()]),
+            history_file: Some(dir.path().join("history.jsonl")),
+            journal_file: Some(dir.path().join("missing/journal.jsonl")),
+            ..Config::default()
+        };
+        let checks = run(&config, &Account(vec!["example.com"])).await;
+        let status = |name: &str| checks.iter().find(|c| c.name == name).unwrap();
+
+        assert_eq!(status("njalla api").status, Status::Ok);
+        assert_eq!(status("domain filter").status, Status::Fail);
+        assert!(status("domain filter").detail.contains("example.org"));
+        assert_eq!(status("history file").status, Status::Ok);
+        assert_eq!(status("journal file").status, Status::Fail);
+    }
+}
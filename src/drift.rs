@@ -0,0 +1,611 @@
+//! Drift between what this webhook last applied and what Njalla actually serves.
+//!
+//! Each zone has a baseline: its records as first read (by `GET /records` or an explicit
+//! accept), then kept up to date with every operation the webhook applies itself. Anything else
+//! that changes the zone — edits in the Njalla web UI, another client, an apply that failed
+//! halfway — shows up as a difference between the baseline and a fresh `list_records`. A
+//! background task checks every zone with a baseline each `drift_check_interval_seconds`,
+//! exports the result as metrics and, with `drift_repair`, puts the baseline back. Before an
+//! automatic repair, drift the history file explains — changes made through the CLI, another
+//! replica sharing the file, or this webhook before a restart — is adopted into the baseline
+//! instead of reverted. The same task checks zones tagged with templates for missing template
+//! records.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::history::{Action, HistoryEntry, HistoryFilter};
+use crate::journal::Operation;
+use crate::njalla::{Budget, DnsRecord};
+use crate::periodic::{spawn_periodic, Start};
+use crate::planner::{self, relative_name};
+use crate::template::TemplateDrift;
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::routes::SharedHandler;
+use crate::webhook::types::{ApplyQuery, RepairResponse};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use tracing::{error, info, warn};
+
+/// A record whose content, TTL or priority changed under the same Njalla id.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangedRecord {
+    pub expected: DnsRecord,
+    pub live: DnsRecord,
+}
+
+/// Differences between a zone's baseline and its live records.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DriftReport {
+    pub zone: String,
+    pub checked_at: DateTime<Utc>,
+    /// In the baseline but gone from the zone.
+    pub missing: Vec<DnsRecord>,
+    /// In the zone but not in the baseline.
+    pub extra: Vec<DnsRecord>,
+    pub changed: Vec<ChangedRecord>,
+}
+
+impl DriftReport {
+    pub fn has_drift(&self) -> bool {
+        !(self.missing.is_empty() && self.extra.is_empty() && self.changed.is_empty())
+    }
+}
+
+fn same_content(a: &DnsRecord, b: &DnsRecord) -> bool {
+    relative_name(&a.name).eq_ignore_ascii_case(relative_name(&b.name))
+        && a.record_type.eq_ignore_ascii_case(&b.record_type)
+        && a.content == b.content
+}
+
+/// Compare a zone's baseline with its live records. Records are paired by Njalla id first, so
+/// an edit in place is reported as changed; a record re-created with identical content under a
+/// new id is not drift.
+pub fn compare(zone: &str, baseline: &[DnsRecord], live: &[DnsRecord]) -> DriftReport {
+    let mut report = DriftReport {
+        zone: zone.to_string(),
+        checked_at: Utc::now(),
+        missing: Vec::new(),
+        extra: Vec::new(),
+        changed: Vec::new(),
+    };
+    let mut matched = vec![false; live.len()];
+
+    let mut unpaired = Vec::new();
+    for expected in baseline {
+        match live.iter().position(|r| r.id == expected.id) {
+            Some(i) => {
+                matched[i] = true;
+                let have = &live[i];
+                if !same_content(expected, have)
+                    || expected.ttl != have.ttl
+                    || expected.priority != have.priority
+                {
+                    report.changed.push(ChangedRecord {
+                        expected: expected.clone(),
+                        live: have.clone(),
+                    });
+                }
+            }
+            None => unpaired.push(expected),
+        }
+    }
+    for expected in unpaired {
+        match live
+            .iter()
+            .enumerate()
+            .position(|(i, r)| !matched[i] && same_content(expected, r))
+        {
+            Some(i) => matched[i] = true,
+            None => report.missing.push(expected.clone()),
+        }
+    }
+    report.extra = live
+        .iter()
+        .zip(&matched)
+        .filter(|(_, matched)| !**matched)
+        .map(|(record, _)| record.clone())
+        .collect();
+    report
+}
+
+/// `baseline` with the drift in `report` that `history` (the zone's entries) accounts for
+/// taken on: removals of missing records, and adds or edits that left an extra or changed
+/// record with exactly its live content. Everything else stays as it was.
+pub fn adopt_recorded(
+    report: &DriftReport,
+    baseline: &[DnsRecord],
+    history: &[HistoryEntry],
+) -> Vec<DnsRecord> {
+    let recorded = |id: &str, actions: &[Action], content: Option<&str>| {
+        history.iter().any(|e| {
+            e.record_id == id
+                && actions.contains(&e.action)
+                && content.is_none_or(|c| e.after.as_ref().is_some_and(|a| a.content == c))
+        })
+    };
+    let mut adopted: Vec<DnsRecord> = baseline
+        .iter()
+        .filter(|r| !(report.missing.contains(r) && recorded(&r.id, &[Action::Remove], None)))
+        .map(|r| {
+            report
+                .changed
+                .iter()
+                .find(|c| {
+                    c.expected.id == r.id && recorded(&r.id, &[Action::Edit], Some(&c.live.content))
+                })
+                .map_or_else(|| r.clone(), |c| c.live.clone())
+        })
+        .collect();
+    adopted.extend(
+        report
+            .extra
+            .iter()
+            .filter(|r| recorded(&r.id, &[Action::Add, Action::Edit], Some(&r.content)))
+            .cloned(),
+    );
+    adopted
+}
+
+#[derive(Default)]
+struct State {
+    baselines: BTreeMap<String, Vec<DnsRecord>>,
+    reports: BTreeMap<String, DriftReport>,
+    templates: Vec<TemplateDrift>,
+}
+
+/// Baselines and the latest drift report per zone. Shared by every handler across reloads.
+#[derive(Default)]
+pub struct DriftTracker {
+    state: Mutex<State>,
+}
+
+impl DriftTracker {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Records read from `zone`. Becomes the baseline if the zone has none yet; later reads
+    /// don't replace it, or drift would be absorbed before it is noticed.
+    pub fn observe(&self, zone: &str, records: &[DnsRecord]) {
+        self.lock()
+            .baselines
+            .entry(zone.to_string())
+            .or_insert_with(|| records.to_vec());
+    }
+
+    /// Replace the baseline of `zone`, accepting its current state.
+    pub fn accept(&self, zone: &str, records: &[DnsRecord]) {
+        let mut state = self.lock();
+        state.baselines.insert(zone.to_string(), records.to_vec());
+        state.reports.remove(zone);
+    }
+
+    /// Follow an operation the webhook applied. `added` is the record Njalla created for an
+    /// add.
+    pub fn applied(&self, op: &Operation, added: Option<&DnsRecord>) {
+        let mut state = self.lock();
+        match (op, added) {
+            (Operation::Add(request), Some(record)) => {
+                if let Some(baseline) = state.baselines.get_mut(&request.domain) {
+                    baseline.push(record.clone());
+                }
+            }
+            (Operation::Remove { domain, record }, _) => {
+                if let Some(baseline) = state.baselines.get_mut(domain) {
+                    baseline.retain(|r| r.id != record.id);
+                }
+            }
+            (
+                Operation::Edit {
+                    domain,
+                    record,
+                    content,
+                },
+                _,
+            ) => {
+                if let Some(baseline) = state.baselines.get_mut(domain) {
+                    for r in baseline.iter_mut().filter(|r| r.id == record.id) {
+                        r.content = content.clone();
+                    }
+                }
+            }
+            (Operation::Add(_), None) => {}
+        }
+    }
+
+    pub fn baseline(&self, zone: &str) -> Option<Vec<DnsRecord>> {
+        self.lock().baselines.get(zone).cloned()
+    }
+
+    pub fn zones(&self) -> Vec<String> {
+        self.lock().baselines.keys().cloned().collect()
+    }
+
+    pub fn record_report(&self, report: DriftReport) {
+        self.lock().reports.insert(report.zone.clone(), report);
+    }
+
+    /// The latest report of every checked zone.
+    pub fn reports(&self) -> Vec<DriftReport> {
+        self.lock().reports.values().cloned().collect()
+    }
+
+    /// Replace the template reports with those of the latest check.
+    pub fn record_template_reports(&self, reports: Vec<TemplateDrift>) {
+        self.lock().templates = reports;
+    }
+
+    pub fn template_reports(&self) -> Vec<TemplateDrift> {
+        self.lock().templates.clone()
+    }
+}
+
+/// Check every zone with a baseline on the configured interval, repairing drift when
+/// `drift_repair` is set.
+pub fn spawn(handler: SharedHandler) -> tokio::task::JoinHandle<()> {
+    let interval = |config: &Config| config.drift_check_interval_seconds;
+    spawn_periodic(handler, Start::AfterInterval, interval, check_zones)
+}
+
+/// One round of the background task: drift of every zone, then template records.
+async fn check_zones(handler: Arc<WebhookHandler>) {
+    match handler.check_drift().await {
+        Ok(reports) => {
+            let drifted: Vec<&str> = reports
+                .iter()
+                .filter(|r| r.has_drift())
+                .map(|r| r.zone.as_str())
+                .collect();
+            info!(
+                "Drift check: {} zone(s) checked, {} drifted",
+                reports.len(),
+                drifted.len()
+            );
+            if handler.config().drift_repair {
+                for zone in drifted {
+                    if let Err(e) = handler.auto_repair_drift(zone).await {
+                        error!("Drift repair of {} failed: {}", zone, e);
+                    }
+                }
+            }
+        }
+        Err(e) => warn!("Drift check failed: {}", e),
+    }
+    match handler.check_templates().await {
+        Ok(reports) => {
+            let missing: usize = reports.iter().map(|r| r.missing.len()).sum();
+            if missing > 0 {
+                warn!("Template check: {} template record(s) missing", missing);
+            }
+        }
+        Err(e) => warn!("Template check failed: {}", e),
+    }
+}
+
+// The drift admin endpoints.
+impl WebhookHandler {
+    /// Compare every zone with a baseline against its live records and keep the reports for
+    /// `/metrics` and `GET /admin/drift`. A zone that can't be read is logged and skipped; the
+    /// call fails only if no zone could be checked.
+    pub async fn check_drift(&self) -> Result<Vec<DriftReport>> {
+        let budget = Budget::unlimited();
+        let mut reports = Vec::new();
+        let mut last_error = None;
+        for zone in self.drift.zones() {
+            if !self.config.is_domain_allowed(&zone) {
+                continue;
+            }
+            let Some(baseline) = self.drift.baseline(&zone) else {
+                continue;
+            };
+            let live = match self.njalla_client.list_records(&zone, &budget).await {
+                Ok(live) => live,
+                Err(e) => {
+                    error!("Failed to check zone {} for drift: {}", zone, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            let report = compare(&zone, &baseline, &live);
+            if report.has_drift() {
+                warn!(
+                    zone = %zone,
+                    missing = report.missing.len(),
+                    extra = report.extra.len(),
+                    changed = report.changed.len(),
+                    "Zone drift detected"
+                );
+            }
+            self.drift.record_report(report.clone());
+            reports.push(report);
+        }
+        match last_error {
+            Some(e) if reports.is_empty() => Err(e),
+            _ => Ok(reports),
+        }
+    }
+
+    /// The latest drift report of every checked zone.
+    pub async fn drift_reports(&self) -> Result<Json<Vec<DriftReport>>> {
+        Ok(Json(self.drift.reports()))
+    }
+
+    /// Plan putting `zone` back to its baseline. With `apply`, carry the plan out as one batch
+    /// and take the repaired zone as the new baseline.
+    pub async fn repair_drift(
+        &self,
+        zone: &str,
+        query: ApplyQuery,
+    ) -> Result<Json<RepairResponse>> {
+        self.repair(zone, query.apply, false).await.map(Json)
+    }
+
+    /// The background `drift_repair`: first adopt the drift the history accounts for, then
+    /// repair the rest. Without a history file every change is reverted.
+    pub async fn auto_repair_drift(&self, zone: &str) -> Result<RepairResponse> {
+        self.repair(zone, true, true).await
+    }
+
+    async fn repair(
+        &self,
+        zone: &str,
+        apply: bool,
+        adopt_recorded: bool,
+    ) -> Result<RepairResponse> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let mut baseline = self.require_baseline(&zone)?;
+
+        let batch = Batch::new(Budget::unlimited(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        if let (true, Some(history)) = (adopt_recorded, &self.history) {
+            let entries = history
+                .query(&HistoryFilter {
+                    zone: Some(zone.clone()),
+                    limit: Some(usize::MAX),
+                    ..HistoryFilter::default()
+                })
+                .await?;
+            let report = compare(&zone, &baseline, &live);
+            let adopted = self::adopt_recorded(&report, &baseline, &entries);
+            if adopted != baseline {
+                info!(
+                    "Drift of {} recorded in the history adopted, not repaired",
+                    zone
+                );
+                self.drift.accept(&zone, &adopted);
+                baseline = adopted;
+            }
+        }
+        let plan = planner::diff(
+            &zone,
+            &baseline,
+            &live,
+            |_| true,
+            |record_type| self.config.is_record_type_allowed(&zone, record_type),
+        );
+        info!("Drift repair of {}: {}", zone, plan.summary());
+
+        let applied = apply && !self.config.dry_run && !plan.is_empty();
+        if apply {
+            self.run_change(&format!("Repair drift in {zone}"), &plan.operations, &batch)
+                .await?;
+        }
+        if applied {
+            // Repaired records have new ids; the zone as it is now is the baseline.
+            let repaired = self
+                .njalla_client
+                .list_records(&zone, &batch.budget)
+                .await?;
+            self.drift.accept(&zone, &repaired);
+        }
+
+        Ok(RepairResponse {
+            applied,
+            batch: applied.then_some(batch.id),
+            plan,
+        })
+    }
+
+    /// Take the live records of `zone` as its new baseline. Returns the drift being accepted.
+    pub async fn accept_drift(&self, zone: &str) -> Result<Json<DriftReport>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let baseline = self.require_baseline(&zone)?;
+        let live = self
+            .njalla_client
+            .list_records(&zone, &Budget::unlimited())
+            .await?;
+        let report = compare(&zone, &baseline, &live);
+        self.drift.accept(&zone, &live);
+        info!("Accepted the live records of {} as its baseline", zone);
+        Ok(Json(report))
+    }
+
+    fn require_baseline(&self, zone: &str) -> Result<Vec<DnsRecord>> {
+        self.drift.baseline(zone).ok_or_else(|| {
+            Error::NotFound(format!(
+                "no baseline for zone {zone}; it is taken on the first read of the zone"
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::njalla::AddRecordRequest;
+    use crate::webhook::testing::{self, MockNjalla};
+    use serde_json::json;
+
+    fn record(id: &str, name: &str, content: &str, ttl: u32) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: Some(ttl),
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn reports_missing_extra_and_changed_records() {
+        let baseline = [
+            record("1", "www", "192.0.2.1", 300),
+            record("2", "api", "192.0.2.2", 300),
+            record("3", "mail", "192.0.2.3", 300),
+            record("4", "old", "192.0.2.4", 300),
+        ];
+        let live = [
+            record("1", "www", "192.0.2.1", 300),
+            record("2", "api", "192.0.2.9", 300),
+            // Re-created with the same content: not drift.
+            record("30", "mail", "192.0.2.3", 300),
+            record("5", "new", "192.0.2.5", 300),
+        ];
+        let report = compare("example.com", &baseline, &live);
+
+        assert!(report.has_drift());
+        assert_eq!(report.missing, [record("4", "old", "192.0.2.4", 300)]);
+        assert_eq!(report.extra, [record("5", "new", "192.0.2.5", 300)]);
+        assert_eq!(report.changed.len(), 1);
+        assert_eq!(report.changed[0].live.content, "192.0.2.9");
+
+        assert!(!compare("example.com", &live, &live).has_drift());
+    }
+
+    #[test]
+    fn adopts_only_drift_the_history_records() {
+        let baseline = [
+            record("1", "www", "192.0.2.1", 300),
+            record("2", "api", "192.0.2.2", 300),
+            record("3", "mail", "192.0.2.3", 300),
+        ];
+        let live = [
+            record("1", "www", "192.0.2.9", 300),
+            record("4", "cli", "192.0.2.4", 300),
+            record("5", "ui", "192.0.2.5", 300),
+        ];
+        let report = compare("example.com", &baseline, &live);
+        let batch = uuid::Uuid::new_v4();
+        let history = [
+            // A CLI edit of www, the removal of api and the add of cli; mail and ui were
+            // changed by hand.
+            HistoryEntry::edited(batch, None, "example.com", &baseline[0], &live[0]),
+            HistoryEntry::new(batch, None, Action::Remove, "example.com", &baseline[1]),
+            HistoryEntry::new(batch, None, Action::Add, "example.com", &live[1]),
+        ];
+
+        assert_eq!(
+            adopt_recorded(&report, &baseline, &history),
+            [
+                record("1", "www", "192.0.2.9", 300),
+                record("3", "mail", "192.0.2.3", 300),
+                record("4", "cli", "192.0.2.4", 300),
+            ]
+        );
+        assert_eq!(adopt_recorded(&report, &baseline, &[]), baseline);
+    }
+
+    #[test]
+    fn baseline_follows_observations_and_applied_operations() {
+        let tracker = DriftTracker::default();
+        tracker.observe("example.com", &[record("1", "www", "192.0.2.1", 300)]);
+        // A later read doesn't move the baseline.
+        tracker.observe("example.com", &[]);
+
+        tracker.applied(
+            &Operation::Add(AddRecordRequest {
+                domain: "example.com".to_string(),
+                name: "api".to_string(),
+                record_type: "A".to_string(),
+                content: "192.0.2.2".to_string(),
+                ttl: 300,
+                priority: None,
+            }),
+            Some(&record("2", "api", "192.0.2.2", 300)),
+        );
+        tracker.applied(
+            &Operation::Remove {
+                domain: "example.com".to_string(),
+                record: record("1", "www", "192.0.2.1", 300),
+            },
+            None,
+        );
+        assert_eq!(
+            tracker.baseline("example.com").unwrap(),
+            [record("2", "api", "192.0.2.2", 300)]
+        );
+
+        tracker.accept("example.com", &[]);
+        assert_eq!(tracker.baseline("example.com").unwrap(), []);
+        assert_eq!(tracker.zones(), ["example.com"]);
+    }
+
+    #[tokio::test]
+    async fn drift_is_reported_planned_and_accepted() {
+        let mut njalla = MockNjalla::new().await;
+        // Since the baseline, www was deleted and a record added by hand.
+        njalla
+            .records(json!([
+                {"id": "3", "name": "mail", "type": "A", "content": "192.0.2.3", "ttl": 300},
+                {"id": "7", "name": "manual", "type": "A", "content": "192.0.2.7", "ttl": 300}
+            ]))
+            .await;
+
+        let handler = njalla.handler(testing::config());
+        let record = |id: &str, name: &str, content: &str| DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        };
+        assert!(matches!(
+            handler
+                .repair_drift("example.com", ApplyQuery { apply: false })
+                .await,
+            Err(Error::NotFound(_))
+        ));
+        handler.drift_tracker().observe(
+            "example.com",
+            &[
+                record("1", "www", "192.0.2.1"),
+                record("3", "mail", "192.0.2.3"),
+            ],
+        );
+
+        let reports = handler.check_drift().await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].missing, [record("1", "www", "192.0.2.1")]);
+        assert_eq!(reports[0].extra, [record("7", "manual", "192.0.2.7")]);
+        assert!(handler
+            .metrics()
+            .await
+            .contains("njalla_webhook_drift_records{zone=\"example.com\",kind=\"extra\"} 1"));
+
+        let Json(repair) = handler
+            .repair_drift("example.com", ApplyQuery { apply: false })
+            .await
+            .unwrap();
+        assert!(!repair.applied);
+        assert_eq!(repair.plan.summary(), "1 to add, 1 to remove");
+
+        let Json(accepted) = handler.accept_drift("example.com").await.unwrap();
+        assert!(accepted.has_drift());
+        let reports = handler.check_drift().await.unwrap();
+        assert!(!reports[0].has_drift());
+    }
+}
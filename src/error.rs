@@ -16,9 +16,18 @@ pub enum Error {
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
     #[error("Domain not allowed: {0}")]
     DomainNotAllowed(String),
 
+    #[error("Denied by zone policy: {0}")]
+    PolicyDenied(String),
+
+    #[error("Not found: {0}")]
+    NotFound(String),
+
     #[error("Record not found: {0}")]
     #[allow(dead_code)]
     RecordNotFound(String),
@@ -35,29 +44,126 @@ pub enum Error {
     #[error("Internal error: {0}")]
     Internal(String),
 
+    /// A transient upstream problem (rate limit, 5xx, network, contention) that outlasted
+    /// our own retries. Retrying later can succeed.
+    #[error("Temporarily unavailable: {0}")]
+    Unavailable(String),
+
+    /// Changes that can't be applied no matter how often they are retried.
+    #[error("Changes rejected: {0}")]
+    Rejected(String),
+
+    #[error("Payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("Request timed out: {0}")]
+    Timeout(String),
+
+    #[error("Too many concurrent requests: {0}")]
+    Overloaded(String),
+
     #[error(transparent)]
     Other(#[from] anyhow::Error),
 }
 
+impl Error {
+    /// The HTTP status for this error. external-dns treats 5xx as a soft error (logs it and
+    /// retries on the next sync) and 4xx as fatal, so retryable problems must map to 5xx and
+    /// permanent ones to 4xx.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Error::NjallaApi(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            Error::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Error::DomainNotAllowed(_) => StatusCode::FORBIDDEN,
+            Error::PolicyDenied(_) => StatusCode::FORBIDDEN,
+            Error::NotFound(_) | Error::RecordNotFound(_) => StatusCode::NOT_FOUND,
+            Error::Configuration(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Network(_) => StatusCode::BAD_GATEWAY,
+            Error::Json(_) => StatusCode::BAD_REQUEST,
+            Error::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Error::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Rejected(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            Error::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            // Not 408: external-dns treats every 4xx as fatal, and a timeout isn't.
+            Error::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            Error::Overloaded(_) => StatusCode::SERVICE_UNAVAILABLE,
+            Error::Other(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Whether the same request could succeed if retried later.
+    pub fn is_retryable(&self) -> bool {
+        self.status().is_server_error()
+    }
+}
+
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let (status, error_message) = match self {
-            Error::NjallaApi(msg) => (StatusCode::BAD_GATEWAY, msg),
-            Error::InvalidRequest(msg) => (StatusCode::BAD_REQUEST, msg),
-            Error::DomainNotAllowed(msg) => (StatusCode::FORBIDDEN, msg),
-            Error::RecordNotFound(msg) => (StatusCode::NOT_FOUND, msg),
-            Error::Configuration(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            Error::Network(e) => (StatusCode::BAD_GATEWAY, e.to_string()),
-            Error::Json(e) => (StatusCode::BAD_REQUEST, e.to_string()),
-            Error::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg),
-            Error::Other(e) => (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+        let status = self.status();
+        let error_message = match self {
+            Error::NjallaApi(msg)
+            | Error::InvalidRequest(msg)
+            | Error::Unauthorized(msg)
+            | Error::DomainNotAllowed(msg)
+            | Error::PolicyDenied(msg)
+            | Error::NotFound(msg)
+            | Error::RecordNotFound(msg)
+            | Error::Configuration(msg)
+            | Error::Internal(msg)
+            | Error::Unavailable(msg)
+            | Error::Rejected(msg)
+            | Error::PayloadTooLarge(msg)
+            | Error::Timeout(msg)
+            | Error::Overloaded(msg) => msg,
+            Error::Network(e) => e.to_string(),
+            Error::Json(e) => e.to_string(),
+            Error::Other(e) => e.to_string(),
         };
 
-        let body = Json(json!({
-            "error": error_message,
-            "status": status.as_u16(),
-        }));
+        error_response(status, error_message)
+    }
+}
+
+/// The JSON error body every failed request gets: `{"error": <message>, "status": <code>}`.
+pub fn error_response(status: StatusCode, message: impl Into<String>) -> Response {
+    let body = Json(json!({
+        "error": message.into(),
+        "status": status.as_u16(),
+    }));
+
+    (status, body).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transient_failures_are_retryable_server_errors() {
+        for err in [
+            Error::Unavailable("HTTP 429".into()),
+            Error::Timeout("slow".into()),
+            Error::Overloaded("busy".into()),
+            Error::Internal("bug".into()),
+        ] {
+            assert!(err.is_retryable(), "{err:?}");
+            assert!(err.status().is_server_error(), "{err:?}");
+        }
+    }
 
-        (status, body).into_response()
+    #[test]
+    fn permanent_failures_are_client_errors() {
+        for err in [
+            Error::NjallaApi("API error 400: invalid content".into()),
+            Error::InvalidRequest("bad".into()),
+            Error::DomainNotAllowed("other.com".into()),
+            Error::PolicyDenied("TXT".into()),
+            Error::Rejected("1 failed".into()),
+            Error::PayloadTooLarge("big".into()),
+        ] {
+            assert!(!err.is_retryable(), "{err:?}");
+            assert!(err.status().is_client_error(), "{err:?}");
+        }
     }
 }
@@ -0,0 +1,899 @@
+//! Local history of applied record changes.
+//!
+//! Every Njalla operation that succeeds is appended to a JSON Lines file with the record before
+//! and after, the Njalla record id, the batch it belonged to and when it happened. The admin
+//! API lists it and can undo a whole batch by applying the inverse operations.
+//!
+//! A batch counts as undone once an undo of it has carried out all its operations, which is
+//! recorded with a marker line of its own. An undo that fails partway can be retried.
+//!
+//! Batch lookups go through an index of where each batch's lines are, kept up to date by
+//! reading only what was appended since the last lookup (possibly by another process sharing
+//! the file), so undoing a batch doesn't rescan the whole file.
+
+use crate::error::{Error, Result};
+use crate::journal::Operation;
+use crate::njalla::{AddRecordRequest, DnsRecord};
+use crate::planner::{self, fqdn};
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::UndoResponse;
+use anyhow::Context;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+/// Entries returned by a history query when no `limit` is given.
+const DEFAULT_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Add,
+    Remove,
+    /// Content changed in place; the record keeps its id.
+    Edit,
+}
+
+/// Content of a record on one side of a change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordContent {
+    pub content: String,
+    pub ttl: Option<u32>,
+    pub priority: Option<u32>,
+}
+
+impl From<&DnsRecord> for RecordContent {
+    fn from(record: &DnsRecord) -> Self {
+        Self {
+            content: record.content.clone(),
+            ttl: record.ttl,
+            priority: record.priority,
+        }
+    }
+}
+
+/// One applied Njalla operation.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub batch: Uuid,
+    /// The batch this one reverted, for entries written by an undo.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub undoes: Option<Uuid>,
+    pub timestamp: DateTime<Utc>,
+    pub action: Action,
+    pub zone: String,
+    /// Fully qualified record name, without a trailing dot.
+    pub name: String,
+    pub record_type: String,
+    pub record_id: String,
+    pub before: Option<RecordContent>,
+    pub after: Option<RecordContent>,
+}
+
+impl HistoryEntry {
+    /// An entry for `record` having been added to (`Action::Add`) or removed from `zone`. For
+    /// `Action::Edit` this records only the new side; [`Self::edited`] records both.
+    pub fn new(
+        batch: Uuid,
+        undoes: Option<Uuid>,
+        action: Action,
+        zone: &str,
+        record: &DnsRecord,
+    ) -> Self {
+        let (before, after) = match action {
+            Action::Add => (None, Some(record.into())),
+            Action::Remove => (Some(record.into()), None),
+            Action::Edit => (None, Some(record.into())),
+        };
+        Self {
+            batch,
+            undoes,
+            timestamp: Utc::now(),
+            action,
+            zone: zone.to_string(),
+            name: fqdn(&record.name, zone),
+            record_type: record.record_type.clone(),
+            record_id: record.id.clone(),
+            before,
+            after,
+        }
+    }
+
+    /// An entry for a record of `zone` edited from `before` to `after`.
+    pub fn edited(
+        batch: Uuid,
+        undoes: Option<Uuid>,
+        zone: &str,
+        before: &DnsRecord,
+        after: &DnsRecord,
+    ) -> Self {
+        Self {
+            before: Some(before.into()),
+            ..Self::new(batch, undoes, Action::Edit, zone, after)
+        }
+    }
+
+    /// The record name relative to the zone, as Njalla expects it (empty for the apex).
+    pub fn relative_name(&self) -> &str {
+        if self.name == self.zone {
+            ""
+        } else {
+            self.name
+                .strip_suffix(&format!(".{}", self.zone))
+                .unwrap_or(&self.name)
+        }
+    }
+}
+
+/// A line of the history file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Entry(HistoryEntry),
+    /// Batch `undone` was fully reverted by batch `by`.
+    Undone {
+        undone: Uuid,
+        by: Uuid,
+        timestamp: DateTime<Utc>,
+    },
+}
+
+/// Which entries a history query returns. All conditions must match.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HistoryFilter {
+    pub zone: Option<String>,
+    /// Fully qualified record name.
+    pub name: Option<String>,
+    pub batch: Option<Uuid>,
+    /// Only entries at or after this time (RFC 3339).
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries before this time (RFC 3339).
+    pub until: Option<DateTime<Utc>>,
+    /// At most this many entries, newest first.
+    pub limit: Option<usize>,
+}
+
+impl HistoryFilter {
+    fn matches(&self, entry: &HistoryEntry) -> bool {
+        let same = |wanted: &Option<String>, actual: &str| {
+            wanted.as_deref().is_none_or(|w| {
+                w.strip_suffix('.')
+                    .unwrap_or(w)
+                    .eq_ignore_ascii_case(actual)
+            })
+        };
+        same(&self.zone, &entry.zone)
+            && same(&self.name, &entry.name)
+            && self.batch.is_none_or(|b| b == entry.batch)
+            && self.since.is_none_or(|t| entry.timestamp >= t)
+            && self.until.is_none_or(|t| entry.timestamp < t)
+    }
+}
+
+pub struct History {
+    files: Arc<Files>,
+    /// Batches an undo is in progress for.
+    undoing: Mutex<HashSet<Uuid>>,
+}
+
+/// The history file and its index. Only used on the blocking pool.
+struct Files {
+    path: PathBuf,
+    file: Mutex<File>,
+    index: Mutex<Index>,
+}
+
+/// Where in the file each batch's entries are, up to `scanned` bytes.
+#[derive(Default)]
+struct Index {
+    scanned: u64,
+    batches: HashMap<Uuid, Vec<u64>>,
+    /// Batch to the batch that undid it.
+    undone: HashMap<Uuid, Uuid>,
+}
+
+/// Held while a batch is being undone; see [`History::claim_undo`].
+pub struct UndoClaim<'a> {
+    history: &'a History,
+    batch: Uuid,
+}
+
+impl Drop for UndoClaim<'_> {
+    fn drop(&mut self) {
+        self.history
+            .undoing
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.batch);
+    }
+}
+
+impl History {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("history: cannot open {}", path.display()))?;
+        Ok(Self {
+            files: Arc::new(Files {
+                path: path.to_path_buf(),
+                file: Mutex::new(file),
+                index: Mutex::new(Index::default()),
+            }),
+            undoing: Mutex::new(HashSet::new()),
+        })
+    }
+
+    pub async fn record(&self, entry: &HistoryEntry) -> Result<()> {
+        let entry = entry.clone();
+        self.blocking(move |files| files.append(&entry)).await
+    }
+
+    /// Record that batch `by` has reverted every operation of batch `undone`.
+    pub async fn mark_undone(&self, undone: Uuid, by: Uuid) -> Result<()> {
+        let line = Line::Undone {
+            undone,
+            by,
+            timestamp: Utc::now(),
+        };
+        self.blocking(move |files| files.append(&line)).await
+    }
+
+    /// Entries matching `filter`, newest first.
+    pub async fn query(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>> {
+        let filter = filter.clone();
+        self.blocking(move |files| {
+            let mut entries = files.matching(&filter)?;
+            entries.reverse();
+            entries.truncate(filter.limit.unwrap_or(DEFAULT_LIMIT));
+            Ok(entries)
+        })
+        .await
+    }
+
+    /// Every entry of `batch`, in the order the operations were applied.
+    pub async fn batch(&self, batch: Uuid) -> Result<Vec<HistoryEntry>> {
+        self.blocking(move |files| files.batch(batch)).await
+    }
+
+    /// The batch that undid `batch`, if any.
+    pub async fn undone_by(&self, batch: Uuid) -> Result<Option<Uuid>> {
+        self.blocking(move |files| Ok(files.index()?.undone.get(&batch).copied()))
+            .await
+    }
+
+    /// Claim the undo of `batch`, so that a second undo of it can't start until this one has
+    /// finished and recorded itself. Fails when an undo of `batch` is already in progress in
+    /// this process.
+    pub fn claim_undo(&self, batch: Uuid) -> Result<UndoClaim<'_>> {
+        let mut undoing = self.undoing.lock().unwrap_or_else(|e| e.into_inner());
+        if !undoing.insert(batch) {
+            return Err(Error::InvalidRequest(format!(
+                "batch {batch} is already being undone"
+            )));
+        }
+        Ok(UndoClaim {
+            history: self,
+            batch,
+        })
+    }
+
+    /// Run `f` on the blocking pool: appends and full-file scans would otherwise stall the
+    /// runtime's worker for as long as the disk takes.
+    async fn blocking<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&Files) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let files = self.files.clone();
+        tokio::task::spawn_blocking(move || f(&files))
+            .await
+            .map_err(|e| Error::Internal(format!("history {}: {e}", self.files.path.display())))?
+    }
+}
+
+impl Files {
+    /// Append one line; a [`HistoryEntry`] serializes as [`Line::Entry`].
+    fn append(&self, line: &impl Serialize) -> Result<()> {
+        let mut line = serde_json::to_vec(line)?;
+        line.push(b'\n');
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        file.write_all(&line).map_err(|e| self.io_error(e))
+    }
+
+    /// Every entry of `batch`, in the order the operations were applied.
+    fn batch(&self, batch: Uuid) -> Result<Vec<HistoryEntry>> {
+        let offsets = self
+            .index()?
+            .batches
+            .get(&batch)
+            .cloned()
+            .unwrap_or_default();
+        if offsets.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut reader = BufReader::new(File::open(&self.path).map_err(|e| self.io_error(e))?);
+        let mut entries = Vec::with_capacity(offsets.len());
+        let mut line = String::new();
+        for offset in offsets {
+            reader
+                .seek(SeekFrom::Start(offset))
+                .map_err(|e| self.io_error(e))?;
+            line.clear();
+            reader.read_line(&mut line).map_err(|e| self.io_error(e))?;
+            entries.push(serde_json::from_str(&line)?);
+        }
+        Ok(entries)
+    }
+
+    /// The index, brought up to date with every complete line appended since the last call.
+    fn index(&self) -> Result<MutexGuard<'_, Index>> {
+        let mut index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        let mut file = File::open(&self.path).map_err(|e| self.io_error(e))?;
+        let len = file.metadata().map_err(|e| self.io_error(e))?.len();
+        if len < index.scanned {
+            // Truncated or replaced; start over.
+            *index = Index::default();
+        }
+        file.seek(SeekFrom::Start(index.scanned))
+            .map_err(|e| self.io_error(e))?;
+        let mut reader = BufReader::new(file);
+        let mut line = String::new();
+        loop {
+            line.clear();
+            let read = reader.read_line(&mut line).map_err(|e| self.io_error(e))?;
+            // A line without its newline is still being written; it is picked up next time.
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            let offset = index.scanned;
+            index.scanned += read as u64;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Line>(&line) {
+                Ok(Line::Entry(entry)) => {
+                    index.batches.entry(entry.batch).or_default().push(offset);
+                }
+                Ok(Line::Undone { undone, by, .. }) => {
+                    index.undone.entry(undone).or_insert(by);
+                }
+                Err(e) => warn!("Skipping unreadable history line at byte {}: {}", offset, e),
+            }
+        }
+        Ok(index)
+    }
+
+    /// Matching entries in file order. Lines that don't parse (e.g. one cut short by a crash)
+    /// are skipped.
+    fn matching(&self, filter: &HistoryFilter) -> Result<Vec<HistoryEntry>> {
+        let file = File::open(&self.path).map_err(|e| self.io_error(e))?;
+        let mut entries = Vec::new();
+        for (i, line) in BufReader::new(file).lines().enumerate() {
+            let line = line.map_err(|e| self.io_error(e))?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            match serde_json::from_str::<Line>(&line) {
+                Ok(Line::Entry(entry)) if filter.matches(&entry) => entries.push(entry),
+                Ok(_) => {}
+                Err(e) => warn!("Skipping unreadable history line {}: {}", i + 1, e),
+            }
+        }
+        Ok(entries)
+    }
+
+    fn io_error(&self, e: std::io::Error) -> Error {
+        Error::Internal(format!("history {}: {e}", self.path.display()))
+    }
+}
+
+// The history and undo admin endpoints.
+impl WebhookHandler {
+    /// Applied operations matching `filter`, newest first.
+    pub async fn history(&self, filter: HistoryFilter) -> Result<Json<Vec<HistoryEntry>>> {
+        Ok(Json(self.require_history()?.query(&filter).await?))
+    }
+
+    /// Revert a batch by applying the inverse of its operations, newest first, as a new batch.
+    /// Operations whose effect is already gone from the live zone are skipped.
+    pub async fn undo_batch(&self, batch_id: Uuid) -> Result<Json<UndoResponse>> {
+        let history = self.require_history()?;
+        // Held until the undo is recorded, so a concurrent undo of the same batch sees it.
+        let _claim = history.claim_undo(batch_id)?;
+        let entries = history.batch(batch_id).await?;
+        if entries.is_empty() {
+            return Err(Error::NotFound(format!("no history for batch {batch_id}")));
+        }
+        if let Some(undo) = history.undone_by(batch_id).await? {
+            return Err(Error::InvalidRequest(format!(
+                "batch {batch_id} was already undone by batch {undo}"
+            )));
+        }
+
+        let batch = Batch::new(self.batch_budget(), Some(batch_id));
+        let mut live: BTreeMap<String, Vec<DnsRecord>> = BTreeMap::new();
+        let mut ops = Vec::new();
+        let mut skipped = Vec::new();
+        for entry in entries.iter().rev() {
+            if !self.config.is_domain_allowed(&entry.zone) {
+                return Err(Error::DomainNotAllowed(entry.zone.clone()));
+            }
+            self.ensure_record_type_allowed(&entry.zone, &entry.record_type)?;
+            if !live.contains_key(&entry.zone) {
+                let records = self
+                    .njalla_client
+                    .list_records(&entry.zone, &batch.budget)
+                    .await?;
+                live.insert(entry.zone.clone(), records);
+            }
+            // The zone as the operations planned so far leave it, so a record this undo
+            // removes doesn't count as already restoring one it re-adds.
+            let records = live.entry(entry.zone.clone()).or_default();
+
+            match (entry.action, &entry.before) {
+                (Action::Add, _) => match records.iter().position(|r| r.id == entry.record_id) {
+                    Some(i) => ops.push(Operation::Remove {
+                        domain: entry.zone.clone(),
+                        record: records.remove(i),
+                    }),
+                    None => skipped.push(format!(
+                        "{} {} (id {}) no longer exists",
+                        entry.record_type, entry.name, entry.record_id
+                    )),
+                },
+                (Action::Remove, Some(before)) => {
+                    let present = planner::exists(
+                        records,
+                        entry.relative_name(),
+                        &entry.record_type,
+                        &before.content,
+                    );
+                    if present {
+                        skipped.push(format!(
+                            "{} {} -> {} already exists",
+                            entry.record_type, entry.name, before.content
+                        ));
+                    } else {
+                        ops.push(Operation::Add(AddRecordRequest {
+                            domain: entry.zone.clone(),
+                            name: entry.relative_name().to_string(),
+                            record_type: entry.record_type.clone(),
+                            content: before.content.clone(),
+                            ttl: before.ttl.unwrap_or_else(|| self.default_ttl(&entry.zone)),
+                            priority: before.priority,
+                        }));
+                    }
+                }
+                (Action::Remove, None) => skipped.push(format!(
+                    "{} {} (id {}) has no recorded content to restore",
+                    entry.record_type, entry.name, entry.record_id
+                )),
+                (Action::Edit, before) => {
+                    let edited = records.iter().find(|r| {
+                        r.id == entry.record_id
+                            && entry.after.as_ref().is_some_and(|a| a.content == r.content)
+                    });
+                    match (edited, before) {
+                        (Some(record), Some(before)) => ops.push(Operation::Edit {
+                            domain: entry.zone.clone(),
+                            record: record.clone(),
+                            content: before.content.clone(),
+                        }),
+                        (Some(_), None) => skipped.push(format!(
+                            "{} {} (id {}) has no recorded content to restore",
+                            entry.record_type, entry.name, entry.record_id
+                        )),
+                        (None, _) => skipped.push(format!(
+                            "{} {} (id {}) was changed or removed since",
+                            entry.record_type, entry.name, entry.record_id
+                        )),
+                    }
+                }
+            }
+        }
+
+        info!(
+            "Undoing batch {} as batch {}: {} operation(s), {} skipped",
+            batch_id,
+            batch.id,
+            ops.len(),
+            skipped.len()
+        );
+        self.run_change(&format!("Undo batch {batch_id}"), &ops, &batch)
+            .await?;
+        if !self.config.dry_run {
+            history.mark_undone(batch_id, batch.id).await?;
+        }
+
+        Ok(Json(UndoResponse {
+            batch: batch.id,
+            undoes: batch_id,
+            dry_run: self.config.dry_run,
+            applied: ops.iter().map(ToString::to_string).collect(),
+            skipped,
+        }))
+    }
+
+    fn require_history(&self) -> Result<&History> {
+        self.history
+            .as_deref()
+            .ok_or_else(|| Error::NotFound("history is not enabled (set HISTORY_FILE)".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use axum::http::StatusCode;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn record(id: &str, name: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn entries_store_fqdn_and_both_sides() {
+        let batch = Uuid::new_v4();
+        let added = HistoryEntry::new(
+            batch,
+            None,
+            Action::Add,
+            "example.com",
+            &record("1", "api", "192.0.2.1"),
+        );
+        assert_eq!(added.name, "api.example.com");
+        assert_eq!(added.relative_name(), "api");
+        assert_eq!(added.before, None);
+        assert_eq!(added.after.unwrap().content, "192.0.2.1");
+
+        let removed = HistoryEntry::new(
+            batch,
+            None,
+            Action::Remove,
+            "example.com",
+            &record("2", "@", "192.0.2.2"),
+        );
+        assert_eq!(removed.name, "example.com");
+        assert_eq!(removed.relative_name(), "");
+        assert_eq!(removed.before.unwrap().ttl, Some(300));
+        assert_eq!(removed.after, None);
+
+        let edited = HistoryEntry::edited(
+            batch,
+            None,
+            "example.com",
+            &record("3", "www", "192.0.2.3"),
+            &record("3", "www", "192.0.2.4"),
+        );
+        assert_eq!(edited.action, Action::Edit);
+        assert_eq!(edited.before.unwrap().content, "192.0.2.3");
+        assert_eq!(edited.after.unwrap().content, "192.0.2.4");
+    }
+
+    #[tokio::test]
+    async fn query_filters_and_returns_newest_first() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(&dir.path().join("history.jsonl")).unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        history
+            .record(&HistoryEntry::new(
+                first,
+                None,
+                Action::Add,
+                "example.com",
+                &record("1", "api", "192.0.2.1"),
+            ))
+            .await
+            .unwrap();
+        history
+            .record(&HistoryEntry::new(
+                first,
+                None,
+                Action::Add,
+                "example.org",
+                &record("2", "api", "192.0.2.2"),
+            ))
+            .await
+            .unwrap();
+        history
+            .record(&HistoryEntry::new(
+                second,
+                Some(first),
+                Action::Remove,
+                "example.com",
+                &record("1", "api", "192.0.2.1"),
+            ))
+            .await
+            .unwrap();
+
+        let api = history
+            .query(&HistoryFilter {
+                name: Some("API.example.com.".to_string()),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(api.len(), 2);
+        assert_eq!(api[0].batch, second);
+
+        let org = history
+            .query(&HistoryFilter {
+                zone: Some("example.org".to_string()),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(org.len(), 1);
+
+        assert_eq!(history.batch(first).await.unwrap().len(), 2);
+        // Entries of an undo alone don't make the batch undone; the undo may have failed.
+        assert_eq!(history.undone_by(first).await.unwrap(), None);
+        history.mark_undone(first, second).await.unwrap();
+        assert_eq!(history.undone_by(first).await.unwrap(), Some(second));
+        assert_eq!(history.undone_by(second).await.unwrap(), None);
+        assert_eq!(history.batch(first).await.unwrap().len(), 2);
+
+        let limited = history
+            .query(&HistoryFilter {
+                limit: Some(1),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(limited.len(), 1);
+        assert_eq!(limited[0].batch, second);
+
+        let future = history
+            .query(&HistoryFilter {
+                since: Some(Utc::now() + chrono::Duration::hours(1)),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert!(future.is_empty());
+    }
+
+    #[tokio::test]
+    async fn index_follows_appends_from_other_writers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.jsonl");
+        let history = History::open(&path).unwrap();
+        let other = History::open(&path).unwrap();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let entry = |batch, undoes| {
+            HistoryEntry::new(
+                batch,
+                undoes,
+                Action::Add,
+                "example.com",
+                &record("1", "api", "192.0.2.1"),
+            )
+        };
+
+        history.record(&entry(first, None)).await.unwrap();
+        assert_eq!(history.undone_by(first).await.unwrap(), None);
+        other.record(&entry(second, Some(first))).await.unwrap();
+        other.mark_undone(first, second).await.unwrap();
+        assert_eq!(history.undone_by(first).await.unwrap(), Some(second));
+        let undo = history.batch(second).await.unwrap();
+        assert_eq!(undo.len(), 1);
+        assert_eq!(undo[0].undoes, Some(first));
+    }
+
+    #[test]
+    fn an_undo_can_only_be_claimed_once_at_a_time() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::open(&dir.path().join("history.jsonl")).unwrap();
+        let batch = Uuid::new_v4();
+
+        let claim = history.claim_undo(batch).unwrap();
+        assert!(history.claim_undo(batch).is_err());
+        assert!(history.claim_undo(Uuid::new_v4()).is_ok());
+        drop(claim);
+        assert!(history.claim_undo(batch).is_ok());
+    }
+
+    #[tokio::test]
+    async fn undo_reverts_a_batch_and_can_only_run_once() {
+        let mut njalla = MockNjalla::new().await;
+        // The batch replaced record 7 (192.0.2.1) with record 8 (192.0.2.2).
+        njalla
+            .records(json!([
+                {"id": "8", "name": "api", "type": "A", "content": "192.0.2.2", "ttl": 60}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "8"}), json!({}), 1)
+            .await;
+        let restored = njalla
+            .expect(
+                "add-record",
+                json!({"name": "api", "content": "192.0.2.1", "ttl": 300}),
+                json!({"id": "9", "name": "api", "type": "A", "content": "192.0.2.1", "ttl": 300}),
+                1,
+            )
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let history = Arc::new(History::open(&dir.path().join("history.jsonl")).unwrap());
+        let batch = Uuid::new_v4();
+        let record = |id: &str, content: &str, ttl| DnsRecord {
+            id: id.to_string(),
+            name: "api".to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: Some(ttl),
+            priority: None,
+        };
+        for (action, record) in [
+            (Action::Remove, record("7", "192.0.2.1", 300)),
+            (Action::Add, record("8", "192.0.2.2", 60)),
+        ] {
+            history
+                .record(&HistoryEntry::new(
+                    batch,
+                    None,
+                    action,
+                    "example.com",
+                    &record,
+                ))
+                .await
+                .unwrap();
+        }
+
+        let handler = njalla
+            .handler(testing::config())
+            .with_history(history.clone());
+
+        let Json(undo) = handler.undo_batch(batch).await.unwrap();
+        assert_eq!(undo.undoes, batch);
+        assert_eq!(undo.applied.len(), 2);
+        assert!(undo.skipped.is_empty());
+        removed.assert_async().await;
+        restored.assert_async().await;
+
+        let Json(entries) = handler
+            .history(HistoryFilter {
+                name: Some("api.example.com".to_string()),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(entries[..2]
+            .iter()
+            .all(|e| e.batch == undo.batch && e.undoes == Some(batch)));
+
+        let err = handler.undo_batch(batch).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        let err = handler.undo_batch(Uuid::new_v4()).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn undo_restores_a_record_whose_ttl_changed() {
+        let mut njalla = MockNjalla::new().await;
+        // The batch changed only the TTL: record 7 was replaced by record 8.
+        njalla
+            .records(json!([
+                {"id": "8", "name": "api", "type": "A", "content": "192.0.2.1", "ttl": 60}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "8"}), json!({}), 1)
+            .await;
+        let restored = njalla
+            .expect(
+                "add-record",
+                json!({"name": "api", "content": "192.0.2.1", "ttl": 300}),
+                json!({"id": "9", "name": "api", "type": "A", "content": "192.0.2.1", "ttl": 300}),
+                1,
+            )
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let history = Arc::new(History::open(&dir.path().join("history.jsonl")).unwrap());
+        let batch = Uuid::new_v4();
+        let mut changed = record("8", "api", "192.0.2.1");
+        changed.ttl = Some(60);
+        for (action, record) in [
+            (Action::Remove, record("7", "api", "192.0.2.1")),
+            (Action::Add, changed),
+        ] {
+            history
+                .record(&HistoryEntry::new(
+                    batch,
+                    None,
+                    action,
+                    "example.com",
+                    &record,
+                ))
+                .await
+                .unwrap();
+        }
+
+        let handler = njalla
+            .handler(testing::config())
+            .with_history(history.clone());
+        let Json(undo) = handler.undo_batch(batch).await.unwrap();
+
+        assert_eq!(undo.applied.len(), 2, "{undo:?}");
+        assert!(undo.skipped.is_empty(), "{:?}", undo.skipped);
+        removed.assert_async().await;
+        restored.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn a_failed_undo_can_be_retried() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "8", "name": "api", "type": "A", "content": "192.0.2.1", "ttl": 300},
+                {"id": "9", "name": "www", "type": "A", "content": "192.0.2.2", "ttl": 300}
+            ]))
+            .await;
+        let failing = njalla
+            .reject_call("remove-record", json!({"id": "8"}), 500, "internal error")
+            .await;
+        njalla.answer("remove-record", json!({}), json!({})).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let history = Arc::new(History::open(&dir.path().join("history.jsonl")).unwrap());
+        let batch = Uuid::new_v4();
+        for record in [
+            record("8", "api", "192.0.2.1"),
+            record("9", "www", "192.0.2.2"),
+        ] {
+            history
+                .record(&HistoryEntry::new(
+                    batch,
+                    None,
+                    Action::Add,
+                    "example.com",
+                    &record,
+                ))
+                .await
+                .unwrap();
+        }
+        let handler = njalla
+            .handler(testing::config())
+            .with_history(history.clone());
+
+        // Record 9 is removed, record 8 isn't.
+        assert!(handler.undo_batch(batch).await.is_err());
+        assert_eq!(history.undone_by(batch).await.unwrap(), None);
+
+        failing.remove_async().await;
+        let Json(undo) = handler.undo_batch(batch).await.unwrap();
+        assert_eq!(history.undone_by(batch).await.unwrap(), Some(undo.batch));
+        let err = handler.undo_batch(batch).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn history_requires_a_history_file() {
+        let err = testing::offline_handler(testing::config())
+            .history(HistoryFilter::default())
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+}
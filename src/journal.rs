@@ -0,0 +1,797 @@
+//! Write-ahead journal for record changes.
+//!
+//! A change (one create, update or delete from a batch) can take several Njalla calls — an
+//! update deletes the old records and then adds the new ones. Before the first call, every
+//! planned operation is appended to the journal and flushed to disk; each operation is marked
+//! done as it completes and the change is closed when it finishes (successfully or not). If the
+//! process dies in between, the next start finds the change still open and reconciles it
+//! against the live zone: [`JournalRecovery::Complete`] carries out what is missing,
+//! [`JournalRecovery::Rollback`] undoes what already happened. A change whose request is
+//! dropped before it finishes (it timed out, say) is closed as abandoned instead: the process
+//! is still running and external-dns retries the request.
+//!
+//! The file holds one JSON entry per line and is truncated whenever no change is open, so it
+//! stays small. An open journal holds an exclusive lock on its file, so the server and the CLI
+//! commands each journal to a file of their own (see [`cli_path`]).
+
+use crate::config::JournalRecovery;
+use crate::error::{Error, Result};
+use crate::njalla::{
+    AddRecordRequest, Budget, Client, DnsRecord, RemoveRecordRequest, UpdateRecordRequest,
+};
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tracing::{info, warn};
+
+/// One Njalla call a change is going to make.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Add(AddRecordRequest),
+    /// The full record is kept so a rollback can put it back.
+    Remove {
+        domain: String,
+        record: DnsRecord,
+    },
+    /// Change the content of `record` in place, keeping its id and TTL. The record as it was
+    /// is kept so a rollback can restore its content.
+    Edit {
+        domain: String,
+        record: DnsRecord,
+        content: String,
+    },
+}
+
+impl Operation {
+    /// The `edit-record` request for an edit of `record` to `content`.
+    pub fn edit_request(domain: &str, record: &DnsRecord, content: &str) -> UpdateRecordRequest {
+        UpdateRecordRequest {
+            domain: domain.to_string(),
+            id: record.id.clone(),
+            content: content.to_string(),
+            ttl: record.ttl,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Add(request) => write!(
+                f,
+                "add {} '{}' -> {} in {}",
+                request.record_type, request.name, request.content, request.domain
+            ),
+            Self::Remove { domain, record } => write!(
+                f,
+                "remove {} '{}' -> {} (id {}) from {}",
+                record.record_type, record.name, record.content, record.id, domain
+            ),
+            Self::Edit {
+                domain,
+                record,
+                content,
+            } => write!(
+                f,
+                "edit {} '{}' {} -> {} (id {}) in {}",
+                record.record_type, record.name, record.content, content, record.id, domain
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "entry", rename_all = "snake_case")]
+enum Entry {
+    Begin {
+        change: u64,
+        description: String,
+        ops: Vec<Operation>,
+    },
+    Done {
+        change: u64,
+        op: usize,
+    },
+    End {
+        change: u64,
+    },
+    /// Closed without finishing because the request carrying it out was dropped.
+    Abandoned {
+        change: u64,
+    },
+}
+
+/// A change that was begun but never closed, as found when opening the journal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingChange {
+    pub change: u64,
+    pub description: String,
+    pub ops: Vec<Operation>,
+    /// Whether each operation was confirmed done.
+    pub done: Vec<bool>,
+}
+
+struct State {
+    file: File,
+    next_change: u64,
+    /// Changes begun but not yet ended, including pending ones still to be recovered.
+    open: usize,
+}
+
+pub struct Journal {
+    path: PathBuf,
+    state: Arc<Mutex<State>>,
+}
+
+/// A change begun with [`Journal::begin`]. Dropping it before [`Self::end`] closes it as
+/// abandoned.
+pub struct OpenChange<'a> {
+    journal: &'a Journal,
+    id: u64,
+    slot: Arc<Mutex<Slot>>,
+    ended: bool,
+}
+
+/// Shared by an [`OpenChange`] and the write that begins it, which may still be running on the
+/// blocking pool when the change is dropped.
+#[derive(Default)]
+struct Slot {
+    change: Option<u64>,
+    abandoned: bool,
+}
+
+impl Journal {
+    /// Open (or create) the journal at `path` and return the changes a previous process left
+    /// unfinished. They count as open until [`recover`] closes them.
+    pub fn open(path: &Path) -> anyhow::Result<(Self, Vec<PendingChange>)> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("journal: cannot open {}", path.display()))?;
+        file.try_lock()
+            .with_context(|| format!("journal: {} is in use by another process", path.display()))?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)
+            .with_context(|| format!("journal: cannot read {}", path.display()))?;
+        let pending =
+            parse(&contents).with_context(|| format!("journal: {} is corrupt", path.display()))?;
+
+        if pending.is_empty() {
+            file.set_len(0)?;
+        } else if !contents.ends_with('\n') {
+            // Terminate a line torn by the crash so new entries start on their own line.
+            file.write_all(b"\n")?;
+        }
+        file.sync_data()?;
+
+        let next_change = pending.iter().map(|p| p.change).max().unwrap_or(0) + 1;
+        let journal = Self {
+            path: path.to_path_buf(),
+            state: Arc::new(Mutex::new(State {
+                file,
+                next_change,
+                open: pending.len(),
+            })),
+        };
+        Ok((journal, pending))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Record the operations a change is about to make.
+    pub async fn begin(&self, description: &str, ops: &[Operation]) -> Result<OpenChange<'_>> {
+        let mut open = OpenChange {
+            journal: self,
+            id: 0,
+            slot: Arc::default(),
+            ended: false,
+        };
+        let slot = open.slot.clone();
+        let description = description.to_string();
+        let ops = ops.to_vec();
+        open.id = self
+            .write(move |state| {
+                let change = state.next_change;
+                append(
+                    &mut state.file,
+                    &Entry::Begin {
+                        change,
+                        description,
+                        ops,
+                    },
+                )?;
+                state.next_change += 1;
+                state.open += 1;
+                let mut slot = lock(&slot);
+                if slot.abandoned {
+                    // Dropped while this write was under way.
+                    close(state, &Entry::Abandoned { change })?;
+                } else {
+                    slot.change = Some(change);
+                }
+                Ok(change)
+            })
+            .await?;
+        Ok(open)
+    }
+
+    /// Mark operation `op` (an index into the ops given to [`Self::begin`]) as done.
+    pub async fn done(&self, change: u64, op: usize) -> Result<()> {
+        self.write(move |state| append(&mut state.file, &Entry::Done { change, op }))
+            .await
+    }
+
+    /// Close a change. Once no change is open the journal is emptied.
+    pub async fn end(&self, change: u64) -> Result<()> {
+        self.write(move |state| close(state, &Entry::End { change }))
+            .await
+    }
+
+    /// Run `f` on the state on the blocking pool: every write is synced to disk, which would
+    /// otherwise stall the runtime's worker for as long as the disk takes.
+    async fn write<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(&mut State) -> std::io::Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let state = self.state.clone();
+        tokio::task::spawn_blocking(move || f(&mut lock(&state)))
+            .await
+            .map_err(|e| Error::Internal(format!("journal {}: {e}", self.path.display())))?
+            .map_err(|e| self.io_error(e))
+    }
+
+    fn io_error(&self, e: std::io::Error) -> Error {
+        Error::Internal(format!("journal {}: {e}", self.path.display()))
+    }
+}
+
+impl OpenChange<'_> {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Close the change; see [`Journal::end`].
+    pub async fn end(mut self) -> Result<()> {
+        self.ended = true;
+        self.journal.end(self.id).await
+    }
+}
+
+impl Drop for OpenChange<'_> {
+    fn drop(&mut self) {
+        if self.ended {
+            return;
+        }
+        let mut slot = lock(&self.slot);
+        slot.abandoned = true;
+        let Some(change) = slot.change.take() else {
+            // Still being begun; that write closes it.
+            return;
+        };
+        warn!(change, "Change abandoned before it finished");
+        let state = self.journal.state.clone();
+        let path = self.journal.path.clone();
+        let abandon = move || {
+            if let Err(e) = close(&mut lock(&state), &Entry::Abandoned { change }) {
+                warn!(
+                    "journal {}: cannot close change {}: {}",
+                    path.display(),
+                    change,
+                    e
+                );
+            }
+        };
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => drop(runtime.spawn_blocking(abandon)),
+            Err(_) => abandon(),
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    // A panic mid-write can at worst leave a torn line, which `parse` tolerates.
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Append `entry`, which closes a change. Once no change is open the journal is emptied.
+fn close(state: &mut State, entry: &Entry) -> std::io::Result<()> {
+    append(&mut state.file, entry)?;
+    state.open = state.open.saturating_sub(1);
+    if state.open == 0 {
+        state.file.set_len(0)?;
+        state.file.sync_data()?;
+    }
+    Ok(())
+}
+
+fn append(file: &mut File, entry: &Entry) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(entry).map_err(std::io::Error::other)?;
+    line.push(b'\n');
+    file.write_all(&line)?;
+    file.sync_data()
+}
+
+/// The journal the CLI commands use next to the server's journal at `server`.
+pub fn cli_path(server: &Path) -> PathBuf {
+    let mut path = server.as_os_str().to_owned();
+    path.push(".cli");
+    PathBuf::from(path)
+}
+
+/// The changes in `contents` that were begun but not ended. A final line that doesn't parse is
+/// one the crash cut short and is ignored; anywhere else it is an error.
+fn parse(contents: &str) -> anyhow::Result<Vec<PendingChange>> {
+    let lines: Vec<&str> = contents.lines().filter(|l| !l.trim().is_empty()).collect();
+    let mut open: BTreeMap<u64, PendingChange> = BTreeMap::new();
+    for (i, line) in lines.iter().enumerate() {
+        let entry = match serde_json::from_str::<Entry>(line) {
+            Ok(entry) => entry,
+            Err(_) if i + 1 == lines.len() => {
+                warn!("Ignoring incomplete last journal entry");
+                break;
+            }
+            Err(e) => anyhow::bail!("line {}: {e}", i + 1),
+        };
+        match entry {
+            Entry::Begin {
+                change,
+                description,
+                ops,
+            } => {
+                let done = vec![false; ops.len()];
+                open.insert(
+                    change,
+                    PendingChange {
+                        change,
+                        description,
+                        ops,
+                        done,
+                    },
+                );
+            }
+            Entry::Done { change, op } => {
+                if let Some(done) = open.get_mut(&change).and_then(|p| p.done.get_mut(op)) {
+                    *done = true;
+                }
+            }
+            Entry::End { change } | Entry::Abandoned { change } => {
+                open.remove(&change);
+            }
+        }
+    }
+    Ok(open.into_values().collect())
+}
+
+/// Reconcile the changes a previous process left unfinished with the live zones, then close
+/// them in the journal.
+pub async fn recover(
+    journal: &Journal,
+    pending: Vec<PendingChange>,
+    client: &Client,
+    mode: JournalRecovery,
+) -> anyhow::Result<()> {
+    for change in pending {
+        info!(
+            change = change.change,
+            mode = ?mode,
+            "Recovering unfinished change: {}", change.description
+        );
+        match mode {
+            JournalRecovery::Complete => complete(&change, client).await,
+            JournalRecovery::Rollback => roll_back(&change, client).await,
+        }
+        .with_context(|| format!("journal: cannot recover '{}'", change.description))?;
+        journal.end(change.change).await?;
+    }
+    Ok(())
+}
+
+/// Carry out the operations that weren't confirmed, skipping those the live zone shows already
+/// took effect.
+async fn complete(change: &PendingChange, client: &Client) -> Result<()> {
+    let budget = Budget::unlimited();
+    for (op, _) in change
+        .ops
+        .iter()
+        .zip(&change.done)
+        .filter(|(_, done)| !**done)
+    {
+        let live = client.list_records(domain_of(op), &budget).await?;
+        match op {
+            Operation::Add(request) => {
+                if live.iter().any(|r| matches_add(r, request)) {
+                    info!("Already applied: {op}");
+                } else {
+                    info!("Completing: {op}");
+                    client.add_record(request.clone(), &budget).await?;
+                }
+            }
+            Operation::Remove { domain, record } => {
+                if live.iter().any(|r| r.id == record.id) {
+                    info!("Completing: {op}");
+                    client
+                        .remove_record(
+                            RemoveRecordRequest {
+                                domain: domain.clone(),
+                                id: record.id.clone(),
+                            },
+                            &budget,
+                        )
+                        .await?;
+                } else {
+                    info!("Already applied: {op}");
+                }
+            }
+            Operation::Edit {
+                domain,
+                record,
+                content,
+            } => match live.iter().find(|r| r.id == record.id) {
+                Some(r) if r.content == *content => info!("Already applied: {op}"),
+                Some(_) => {
+                    info!("Completing: {op}");
+                    client
+                        .update_record(Operation::edit_request(domain, record, content), &budget)
+                        .await?;
+                }
+                None => warn!("Cannot complete, the record is gone: {op}"),
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Undo, newest first, every operation that may have run: the confirmed ones and the one in
+/// flight when the process stopped. Operations run in order, so later ones never started.
+async fn roll_back(change: &PendingChange, client: &Client) -> Result<()> {
+    let budget = Budget::unlimited();
+    let started = change
+        .done
+        .iter()
+        .position(|done| !done)
+        .map_or(change.ops.len(), |first_pending| first_pending + 1);
+    for op in change.ops[..started].iter().rev() {
+        let live = client.list_records(domain_of(op), &budget).await?;
+        match op {
+            Operation::Add(request) => {
+                // A create is only planned when no identical record exists, so a match is ours.
+                match live.iter().find(|r| matches_add(r, request)) {
+                    Some(added) => {
+                        info!("Rolling back: {op}");
+                        client
+                            .remove_record(
+                                RemoveRecordRequest {
+                                    domain: request.domain.clone(),
+                                    id: added.id.clone(),
+                                },
+                                &budget,
+                            )
+                            .await?;
+                    }
+                    None => info!("Nothing to roll back: {op}"),
+                }
+            }
+            Operation::Remove { domain, record } => {
+                let present = live.iter().any(|r| {
+                    r.id == record.id
+                        || (same_name(&r.name, &record.name)
+                            && r.record_type == record.record_type
+                            && r.content == record.content)
+                });
+                if present {
+                    info!("Nothing to roll back: {op}");
+                } else {
+                    info!("Rolling back: {op}");
+                    client
+                        .add_record(
+                            AddRecordRequest {
+                                domain: domain.clone(),
+                                name: record.name.clone(),
+                                record_type: record.record_type.clone(),
+                                content: record.content.clone(),
+                                ttl: record.ttl.unwrap_or(3600),
+                                priority: record.priority,
+                            },
+                            &budget,
+                        )
+                        .await?;
+                }
+            }
+            Operation::Edit {
+                domain,
+                record,
+                content,
+            } => match live.iter().find(|r| r.id == record.id) {
+                Some(r) if r.content == *content => {
+                    info!("Rolling back: {op}");
+                    client
+                        .update_record(
+                            Operation::edit_request(domain, record, &record.content),
+                            &budget,
+                        )
+                        .await?;
+                }
+                _ => info!("Nothing to roll back: {op}"),
+            },
+        }
+    }
+    Ok(())
+}
+
+fn domain_of(op: &Operation) -> &str {
+    match op {
+        Operation::Add(request) => &request.domain,
+        Operation::Remove { domain, .. } | Operation::Edit { domain, .. } => domain,
+    }
+}
+
+/// Njalla reports the apex as `@`; requests use an empty name.
+fn same_name(a: &str, b: &str) -> bool {
+    fn apex(name: &str) -> &str {
+        if name == "@" {
+            ""
+        } else {
+            name
+        }
+    }
+    apex(a) == apex(b)
+}
+
+fn matches_add(record: &DnsRecord, request: &AddRecordRequest) -> bool {
+    same_name(&record.name, &request.name)
+        && record.record_type == request.record_type
+        && record.content == request.content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::SecretString;
+    use mockito::Matcher;
+    use serde_json::json;
+    use std::time::Duration;
+
+    fn add(name: &str, content: &str) -> Operation {
+        Operation::Add(AddRecordRequest {
+            domain: "example.com".to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: 300,
+            priority: None,
+        })
+    }
+
+    fn remove(id: &str, name: &str, content: &str) -> Operation {
+        Operation::Remove {
+            domain: "example.com".to_string(),
+            record: DnsRecord {
+                id: id.to_string(),
+                name: name.to_string(),
+                record_type: "A".to_string(),
+                content: content.to_string(),
+                ttl: Some(300),
+                priority: None,
+            },
+        }
+    }
+
+    fn pending(ops: Vec<Operation>, done: Vec<bool>) -> PendingChange {
+        PendingChange {
+            change: 1,
+            description: "Update www.example.com".to_string(),
+            ops,
+            done,
+        }
+    }
+
+    #[tokio::test]
+    async fn closed_changes_leave_an_empty_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let (journal, pending) = Journal::open(&path).unwrap();
+        assert!(pending.is_empty());
+
+        let change = journal
+            .begin("Create www.example.com", &[add("www", "192.0.2.1")])
+            .await
+            .unwrap();
+        journal.done(change.id(), 0).await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().is_empty());
+        change.end().await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+        // Only one process at a time journals to a file.
+        assert!(Journal::open(&path).is_err());
+        drop(journal);
+        let (_, pending) = Journal::open(&path).unwrap();
+        assert!(pending.is_empty());
+    }
+
+    #[tokio::test]
+    async fn unfinished_changes_are_pending_after_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let ops = [remove("7", "www", "192.0.2.1"), add("www", "192.0.2.2")];
+        {
+            let (journal, _) = Journal::open(&path).unwrap();
+            let finished = journal
+                .begin("Create a.example.com", &[add("a", "192.0.2.9")])
+                .await;
+            let change = journal.begin("Update www.example.com", &ops).await.unwrap();
+            journal.done(change.id(), 0).await.unwrap();
+            finished.unwrap().end().await.unwrap();
+            // The process dies here, without running destructors.
+            std::mem::forget(change);
+        }
+
+        let (journal, pending) = Journal::open(&path).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].description, "Update www.example.com");
+        assert_eq!(pending[0].ops, ops);
+        assert_eq!(pending[0].done, [true, false]);
+
+        // New changes get fresh ids and the file is kept until the pending one is closed.
+        let change = journal.begin("Create b.example.com", &[]).await.unwrap();
+        assert!(change.id() > pending[0].change);
+        change.end().await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().is_empty());
+        journal.end(pending[0].change).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn dropped_changes_are_closed_as_abandoned() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("journal");
+        let (journal, _) = Journal::open(&path).unwrap();
+
+        let change = journal
+            .begin("Create www.example.com", &[add("www", "192.0.2.1")])
+            .await
+            .unwrap();
+        drop(change);
+        // Dropped while its begin is (most likely) still being written.
+        let ops = [add("api", "192.0.2.2")];
+        let begin = journal.begin("Create api.example.com", &ops);
+        let _ = tokio::time::timeout(Duration::ZERO, begin).await;
+
+        // Both are closed on the blocking pool.
+        for _ in 0..500 {
+            if std::fs::read_to_string(&path).unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+    }
+
+    #[test]
+    fn torn_last_line_is_ignored_but_corruption_elsewhere_is_not() {
+        let begin =
+            r#"{"entry":"begin","change":3,"description":"Delete www.example.com","ops":[]}"#;
+        let pending = parse(&format!("{begin}\n{{\"entry\":\"do")).unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].change, 3);
+
+        assert!(parse(&format!("garbage\n{begin}\n")).is_err());
+    }
+
+    fn client_for(server: &mockito::Server) -> Client {
+        Client::with_api_url(
+            &SecretString::from("token"),
+            0,
+            Duration::ZERO,
+            &server.url(),
+        )
+        .expect("client should build")
+    }
+
+    async fn mock_method(
+        server: &mut mockito::Server,
+        method: &str,
+        result: serde_json::Value,
+    ) -> mockito::Mock {
+        server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({ "method": method })))
+            .with_body(json!({"jsonrpc": "2.0", "id": 1, "result": result}).to_string())
+            .create_async()
+            .await
+    }
+
+    #[tokio::test]
+    async fn complete_finishes_an_interrupted_update() {
+        let mut server = mockito::Server::new_async().await;
+        // The old record is gone; the new one was never added.
+        let list = mock_method(&mut server, "list-records", json!({"records": []}))
+            .await
+            .expect(2);
+        let added = mock_method(
+            &mut server,
+            "add-record",
+            json!({"id": "8", "name": "www", "type": "A", "content": "192.0.2.2"}),
+        )
+        .await
+        .expect(1);
+        let removed = mock_method(&mut server, "remove-record", json!({}))
+            .await
+            .expect(0);
+
+        let change = pending(
+            vec![remove("7", "www", "192.0.2.1"), add("www", "192.0.2.2")],
+            vec![false, false],
+        );
+        complete(&change, &client_for(&server)).await.unwrap();
+
+        list.assert_async().await;
+        added.assert_async().await;
+        removed.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn roll_back_restores_a_removed_record() {
+        let mut server = mockito::Server::new_async().await;
+        mock_method(&mut server, "list-records", json!({"records": []})).await;
+        let re_added = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(json!({
+                "method": "add-record",
+                "params": {"name": "www", "content": "192.0.2.1", "ttl": 300},
+            })))
+            .with_body(
+                json!({"jsonrpc": "2.0", "id": 1, "result":
+                    {"id": "9", "name": "www", "type": "A", "content": "192.0.2.1"}})
+                .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+
+        // The removal was confirmed, the add never started: only the removal is undone.
+        let change = pending(
+            vec![
+                remove("7", "www", "192.0.2.1"),
+                add("www", "192.0.2.2"),
+                add("www", "192.0.2.3"),
+            ],
+            vec![true, false, false],
+        );
+        roll_back(&change, &client_for(&server)).await.unwrap();
+        re_added.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn roll_back_removes_an_added_record() {
+        let mut server = mockito::Server::new_async().await;
+        mock_method(
+            &mut server,
+            "list-records",
+            json!({"records": [{"id": "8", "name": "www", "type": "A", "content": "192.0.2.2"}]}),
+        )
+        .await;
+        let removed = server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                json!({"method": "remove-record", "params": {"id": "8"}}),
+            ))
+            .with_body(json!({"jsonrpc": "2.0", "id": 1, "result": {}}).to_string())
+            .expect(1)
+            .create_async()
+            .await;
+
+        let change = pending(vec![add("www", "192.0.2.2")], vec![false]);
+        roll_back(&change, &client_for(&server)).await.unwrap();
+        removed.assert_async().await;
+    }
+}
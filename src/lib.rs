@@ -1,7 +1,24 @@
+pub mod auth;
 pub mod config;
+pub mod dedupe;
+pub mod doctor;
+pub mod drift;
 pub mod error;
+pub mod history;
+pub mod journal;
+pub mod metrics;
 pub mod njalla;
+pub mod output;
+pub mod periodic;
+pub mod planner;
+pub mod registry;
+pub mod retarget;
+pub mod secret;
+pub mod snapshot;
+pub mod sync;
+pub mod template;
 pub mod webhook;
+pub mod zonefile;
 
 pub use config::Config;
 pub use error::{Error, Result};
@@ -0,0 +1,313 @@
+use crate::config::{Config, ListenAddr};
+use crate::tls::{self, SharedAcceptor, TlsListener};
+use anyhow::{bail, Context, Result};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use axum::{serve, Router};
+use listenfd::ListenFd;
+use std::fmt;
+use std::net::SocketAddr;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::task::JoinSet;
+use tracing::info;
+
+/// Who is on the other end of a connection, captured once when it is accepted and attached to
+/// every request on it as `ConnectInfo<PeerInfo>`.
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub addr: PeerAddr,
+    /// Identity from the verified client certificate, when mutual TLS is on.
+    pub client_identity: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub enum PeerAddr {
+    Tcp(SocketAddr),
+    /// Unix socket peers are anonymous; the kernel still tells us who they run as.
+    Unix {
+        uid: Option<u32>,
+        pid: Option<i32>,
+    },
+}
+
+impl fmt::Display for PeerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix { uid, pid } => {
+                f.write_str("unix")?;
+                if let Some(uid) = uid {
+                    write!(f, " uid={uid}")?;
+                }
+                if let Some(pid) = pid {
+                    write!(f, " pid={pid}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TcpListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TcpListener>) -> Self {
+        Self {
+            addr: PeerAddr::Tcp(*stream.remote_addr()),
+            client_identity: None,
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, TlsListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, TlsListener>) -> Self {
+        Self {
+            addr: PeerAddr::Tcp(*stream.remote_addr()),
+            client_identity: tls::client_identity(stream.io()),
+        }
+    }
+}
+
+impl Connected<IncomingStream<'_, UnixListener>> for PeerInfo {
+    fn connect_info(stream: IncomingStream<'_, UnixListener>) -> Self {
+        let cred = stream.io().peer_cred().ok();
+        Self {
+            addr: PeerAddr::Unix {
+                uid: cred.map(|c| c.uid()),
+                pid: cred.and_then(|c| c.pid()),
+            },
+            client_identity: None,
+        }
+    }
+}
+
+/// A bound socket, ready to serve.
+pub enum Bound {
+    Tcp(TcpListener),
+    Unix(UnixListener, SocketFile),
+}
+
+/// The path of a bound Unix socket. A socket this process created is removed when it is
+/// dropped; one systemd passed in belongs to systemd and is left alone.
+pub struct SocketFile {
+    path: PathBuf,
+    owned: bool,
+}
+
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if self.owned {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Bind every entry of `listen` (or the default TCP address). Fails if any one can't be bound,
+/// so a typo doesn't leave the service half-reachable.
+pub async fn bind(config: &Config) -> Result<Vec<Bound>> {
+    let mode = config.unix_socket_mode()?;
+    let mut bound = Vec::new();
+    for addr in config.listen_addrs()? {
+        match addr {
+            ListenAddr::Tcp(addr) => bound.push(Bound::Tcp(
+                TcpListener::bind(addr)
+                    .await
+                    .with_context(|| format!("cannot listen on tcp:{addr}"))?,
+            )),
+            ListenAddr::Unix(path) => bound.push(bind_unix(&path, mode)?),
+            ListenAddr::Systemd => bound.extend(from_systemd()?),
+        }
+    }
+    Ok(bound)
+}
+
+/// Bind a Unix socket, replacing a stale socket file left by a previous run and applying
+/// `mode` so only the intended group can connect. The socket is bound in a private directory
+/// and only moved into place once it has its mode, so nobody can connect in between.
+fn bind_unix(path: &Path, mode: u32) -> Result<Bound> {
+    if let Ok(meta) = std::fs::symlink_metadata(path) {
+        if !meta.file_type().is_socket() {
+            bail!("cannot listen on unix:{}: not a socket", path.display());
+        }
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            bail!(
+                "cannot listen on unix:{}: another process is serving it",
+                path.display()
+            );
+        }
+        std::fs::remove_file(path)
+            .with_context(|| format!("cannot remove stale socket {}", path.display()))?;
+    }
+
+    let file_name = path
+        .file_name()
+        .with_context(|| format!("cannot listen on unix:{}: no file name", path.display()))?;
+    let mut private_name = std::ffi::OsString::from(".");
+    private_name.push(file_name);
+    private_name.push(format!(".{}", std::process::id()));
+    let private = path.with_file_name(private_name);
+    std::fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&private)
+        .with_context(|| format!("cannot create {}", private.display()))?;
+    let staged = private.join("sock");
+    let result = UnixListener::bind(&staged)
+        .with_context(|| format!("cannot listen on unix:{}", path.display()))
+        .and_then(|listener| {
+            std::fs::set_permissions(&staged, std::fs::Permissions::from_mode(mode))
+                .with_context(|| format!("cannot set permissions on {}", path.display()))?;
+            std::fs::rename(&staged, path)
+                .with_context(|| format!("cannot listen on unix:{}", path.display()))?;
+            Ok(listener)
+        });
+    let _ = std::fs::remove_file(&staged);
+    let _ = std::fs::remove_dir(&private);
+    let listener = result?;
+    Ok(Bound::Unix(
+        listener,
+        SocketFile {
+            path: path.to_path_buf(),
+            owned: true,
+        },
+    ))
+}
+
+/// Take over the stream sockets systemd passed via `LISTEN_FDS`.
+fn from_systemd() -> Result<Vec<Bound>> {
+    let mut fds = ListenFd::from_env();
+    if fds.len() == 0 {
+        bail!("listen: systemd socket activation requested but LISTEN_FDS passed no sockets");
+    }
+    let mut bound = Vec::new();
+    for idx in 0..fds.len() {
+        if let Ok(Some(listener)) = fds.take_tcp_listener(idx) {
+            listener.set_nonblocking(true)?;
+            bound.push(Bound::Tcp(TcpListener::from_std(listener)?));
+        } else if let Ok(Some(listener)) = fds.take_unix_listener(idx) {
+            listener.set_nonblocking(true)?;
+            let path = listener
+                .local_addr()?
+                .as_pathname()
+                .map(Path::to_path_buf)
+                .unwrap_or_default();
+            bound.push(Bound::Unix(
+                UnixListener::from_std(listener)?,
+                SocketFile { path, owned: false },
+            ));
+        } else {
+            bail!(
+                "listen: systemd socket #{} is not a TCP or Unix stream socket",
+                idx + 3
+            );
+        }
+    }
+    Ok(bound)
+}
+
+/// Serve `app` on every listener until one of them fails or the process is told to stop
+/// (SIGTERM or SIGINT). TCP listeners speak TLS when an acceptor is given; Unix sockets are
+/// protected by their file permissions instead, and removed again on the way out.
+pub async fn serve_all(
+    listeners: Vec<Bound>,
+    app: Router,
+    acceptor: Option<SharedAcceptor>,
+) -> Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut socket_files = Vec::new();
+    let mut servers = JoinSet::new();
+    for listener in listeners {
+        let app = app
+            .clone()
+            .into_make_service_with_connect_info::<PeerInfo>();
+        match listener {
+            Bound::Tcp(listener) => match &acceptor {
+                Some(acceptor) => {
+                    let listener = TlsListener::new(listener, acceptor.clone())?;
+                    info!("Server started on tcp:{} (TLS)", listener.local_addr()?);
+                    servers.spawn(async move { serve(listener, app).await });
+                }
+                None => {
+                    info!("Server started on tcp:{}", listener.local_addr()?);
+                    servers.spawn(async move { serve(listener, app).await });
+                }
+            },
+            Bound::Unix(listener, file) => {
+                info!("Server started on unix:{}", file.path.display());
+                socket_files.push(file);
+                servers.spawn(async move { serve(listener, app).await });
+            }
+        }
+    }
+
+    loop {
+        tokio::select! {
+            result = servers.join_next() => match result {
+                Some(result) => result.context("listener task panicked")??,
+                None => return Ok(()),
+            },
+            _ = terminate.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    info!("Shutting down");
+    servers.shutdown().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mode_of(path: &Path) -> u32 {
+        std::fs::metadata(path).unwrap().permissions().mode() & 0o777
+    }
+
+    #[tokio::test]
+    async fn unix_socket_gets_configured_mode() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webhook.sock");
+        let _bound = bind_unix(&path, 0o600).unwrap();
+        assert_eq!(mode_of(&path), 0o600);
+    }
+
+    #[tokio::test]
+    async fn unix_socket_is_removed_when_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webhook.sock");
+        let bound = bind_unix(&path, 0o600).unwrap();
+        // Only the socket is left in the directory; the staging directory is gone.
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        drop(bound);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn stale_unix_socket_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webhook.sock");
+        // A socket file nobody is listening on any more.
+        drop(std::os::unix::net::UnixListener::bind(&path).unwrap());
+
+        assert!(bind_unix(&path, 0o660).is_ok());
+    }
+
+    #[tokio::test]
+    async fn live_unix_socket_is_not_stolen() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("webhook.sock");
+        let _other = std::os::unix::net::UnixListener::bind(&path).unwrap();
+
+        let err = bind_unix(&path, 0o660).err().unwrap();
+        assert!(err.to_string().contains("another process"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn regular_file_is_not_replaced() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let err = bind_unix(file.path(), 0o660).err().unwrap();
+        assert!(err.to_string().contains("not a socket"), "{err}");
+        assert!(file.path().exists());
+    }
+}
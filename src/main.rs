@@ -1,61 +1,537 @@
+mod auth;
 mod config;
+mod dedupe;
+mod doctor;
+mod drift;
 mod error;
+mod history;
+mod journal;
+mod listener;
+mod metrics;
 mod middleware;
 mod njalla;
+mod output;
+mod periodic;
+mod planner;
+mod registry;
+mod reload;
+mod retarget;
+mod secret;
+mod snapshot;
+mod sync;
+mod template;
+mod tls;
 mod webhook;
+mod zonefile;
 
-use anyhow::Result;
-use axum::{middleware as axum_middleware, serve, Router};
-use std::net::SocketAddr;
-use tokio::net::TcpListener;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use axum::{extract::DefaultBodyLimit, middleware as axum_middleware, Json, Router};
+use clap::{Parser, Subcommand};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tower_http::trace::TraceLayer;
-use tracing::info;
+use tracing::{info, warn};
 use tracing_subscriber::{fmt, prelude::*, EnvFilter};
 
 use crate::config::Config;
+use crate::doctor::{Check, Status};
+use crate::history::History;
+use crate::journal::Journal;
+use crate::reload::Reloader;
+use crate::webhook::handlers::WebhookHandler;
 use crate::webhook::routes;
+use crate::webhook::types::{
+    ApplyChangesRequest, ApplyQuery, DedupeQuery, ImportQuery, MigrateQuery, RetargetQuery,
+};
+
+#[derive(Debug, Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Path to a TOML config file. Environment variables override its values.
+    #[arg(long, env = "CONFIG_FILE", global = true)]
+    config: Option<PathBuf>,
+
+    /// Print the effective configuration (API token redacted) and exit.
+    #[arg(long)]
+    print_config: bool,
+
+    /// Output of the one-shot commands; `export` always prints a zone file.
+    #[arg(long, short, value_enum, default_value_t, global = true)]
+    output: output::Format,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Run the webhook server (the default).
+    Serve,
+    /// List the Njalla domains this webhook may manage.
+    Domains,
+    /// List the records of a zone as Njalla stores them, ids included.
+    Records { zone: String },
+    /// Add one record, unless an identical one exists.
+    Add {
+        zone: String,
+        /// Relative to the zone; `@` for the apex.
+        name: String,
+        #[arg(value_name = "TYPE")]
+        record_type: String,
+        content: String,
+        /// Defaults to the zone's `default_ttl`, then 3600.
+        #[arg(long)]
+        ttl: Option<u32>,
+        /// For MX and SRV records.
+        #[arg(long)]
+        priority: Option<u32>,
+    },
+    /// Remove one record by its Njalla id (see `records`).
+    Rm { zone: String, id: String },
+    /// Print the operations an external-dns `POST /records` payload would make, without
+    /// making any.
+    Plan {
+        /// JSON file with the changes, as external-dns sends them.
+        changes: PathBuf,
+    },
+    /// Check the configuration, the API token and the zones, then exit.
+    Doctor,
+    /// Find duplicate records (same name, type and content) and optionally remove them.
+    Dedupe {
+        /// Only this zone; all managed zones by default.
+        #[arg(long)]
+        zone: Option<String>,
+        /// Remove the duplicates. Without it, only report them.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Move external-dns ownership records from the old `<prefix><name>` format to the
+    /// per-type `<prefix><type>-<name>` one.
+    MigrateRegistry {
+        /// Only this zone; all managed zones by default.
+        #[arg(long)]
+        zone: Option<String>,
+        /// Remove each old-format record once its replacements exist.
+        #[arg(long)]
+        remove_old: bool,
+        /// Apply the plan. Without it, only print it.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Point every A, AAAA and CNAME record at a new target: an address, a host name or a
+    /// network like `192.0.2.0/24` (addresses keep their offset in it).
+    Retarget {
+        /// The old target.
+        #[arg(long)]
+        from: String,
+        /// The new target, of the same kind as `--from`.
+        #[arg(long)]
+        to: String,
+        /// Only this zone; all managed zones by default.
+        #[arg(long)]
+        zone: Option<String>,
+        /// Edit the records. Without it, only print the plan.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Add the records of a config template that a zone lacks.
+    ApplyTemplate {
+        /// The zone to apply it to.
+        #[arg(long)]
+        zone: String,
+        /// Name of the template under `[templates]` in the config file.
+        #[arg(long)]
+        template: String,
+        /// Add the records. Without it, only print the plan.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Print the live records of a zone as a BIND zone file.
+    Export {
+        /// The zone to export.
+        #[arg(long)]
+        zone: String,
+    },
+    /// Make a zone hold the records of a BIND zone file.
+    Import {
+        /// The zone to import into.
+        #[arg(long)]
+        zone: String,
+        /// The zone file.
+        #[arg(long)]
+        file: PathBuf,
+        /// Also remove live records the file doesn't list.
+        #[arg(long)]
+        prune: bool,
+        /// Apply the plan. Without it, only print it.
+        #[arg(long)]
+        apply: bool,
+    },
+    /// Make the names a desired-state file manages match it, one file per zone (YAML or
+    /// TOML). Names external-dns owns are never touched.
+    Sync {
+        /// Desired-state files, `.yaml`, `.yml` or `.toml`.
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+        /// Apply the plans. Without it, only print them.
+        #[arg(long)]
+        apply: bool,
+    },
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let cli = Cli::parse();
+
+    if matches!(cli.command, Some(Command::Doctor)) {
+        return doctor(cli.config.as_deref(), cli.output).await;
+    }
+
     // Initialize configuration
-    let config = Config::from_env()?;
+    let config = Config::load(cli.config.as_deref())?;
+
+    if cli.print_config {
+        print!("{}", config.to_redacted_toml()?);
+        return Ok(());
+    }
+
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config, cli.config).await,
+        Command::Domains => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let domains = handler.zones().await?.0;
+            print!("{}", output::render(&domains, cli.output)?);
+            Ok(())
+        }
+        Command::Records { zone } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let records = handler.zone_records(&zone).await?.0;
+            print!("{}", output::render(&records, cli.output)?);
+            Ok(())
+        }
+        Command::Add {
+            zone,
+            name,
+            record_type,
+            content,
+            ttl,
+            priority,
+        } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let record = njalla::DnsRecord {
+                id: String::new(),
+                name,
+                record_type: record_type.to_ascii_uppercase(),
+                content,
+                ttl,
+                priority,
+            };
+            let ops = handler.add_record(&zone, record).await?;
+            print!("{}", output::render(&ops, cli.output)?);
+            Ok(())
+        }
+        Command::Rm { zone, id } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let op = handler.remove_record(&zone, &id).await?;
+            print!("{}", output::render(&[op], cli.output)?);
+            Ok(())
+        }
+        Command::Plan { changes } => {
+            init_tracing(false);
+            let json = std::fs::read_to_string(&changes)
+                .with_context(|| format!("cannot read {}", changes.display()))?;
+            let request: ApplyChangesRequest = serde_json::from_str(&json).with_context(|| {
+                format!(
+                    "{} is not an external-dns changes payload",
+                    changes.display()
+                )
+            })?;
+            let handler = one_shot_handler(&config).await?;
+            let ops = handler.plan_changes(&request.into_changes()).await?;
+            print!("{}", output::render(&ops, cli.output)?);
+            Ok(())
+        }
+        Command::Doctor => unreachable!("handled before the config is loaded"),
+        Command::Dedupe { zone, apply } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let reports = handler.dedupe(DedupeQuery { zone, apply }).await?;
+            print!("{}", output::render(&reports, cli.output)?);
+            Ok(())
+        }
+        Command::MigrateRegistry {
+            zone,
+            remove_old,
+            apply,
+        } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let query = MigrateQuery {
+                zone,
+                remove_old,
+                apply,
+            };
+            let reports = handler.migrate_registry(query).await?;
+            print!("{}", output::render(&reports, cli.output)?);
+            Ok(())
+        }
+        Command::Retarget {
+            from,
+            to,
+            zone,
+            apply,
+        } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let query = RetargetQuery {
+                from,
+                to,
+                zone,
+                apply,
+            };
+            let response = handler.retarget(query).await?;
+            let ops: Vec<_> = response
+                .plans
+                .iter()
+                .flat_map(|plan| plan.operations.iter().cloned())
+                .collect();
+            print!(
+                "{}",
+                output::render_operations(&response, &ops, cli.output)?
+            );
+            Ok(())
+        }
+        Command::ApplyTemplate {
+            zone,
+            template,
+            apply,
+        } => {
+            init_tracing(false);
+            let handler = one_shot_handler(&config).await?;
+            let Json(response) = handler
+                .apply_template(&zone, &template, ApplyQuery { apply })
+                .await?;
+            print!(
+                "{}",
+                output::render_operations(&response, &response.plan.operations, cli.output)?
+            );
+            Ok(())
+        }
+        Command::Export { zone } => {
+            init_tracing(false);
+            if cli.output != output::Format::Table {
+                anyhow::bail!("export always prints a zone file; --output doesn't apply");
+            }
+            let handler = one_shot_handler(&config).await?;
+            print!("{}", handler.export_zone(&zone).await?);
+            Ok(())
+        }
+        Command::Import {
+            zone,
+            file,
+            prune,
+            apply,
+        } => {
+            init_tracing(false);
+            let text = std::fs::read_to_string(&file)
+                .with_context(|| format!("cannot read {}", file.display()))?;
+            let handler = one_shot_handler(&config).await?;
+            let Json(response) = handler
+                .import_zone(&zone, &text, ImportQuery { prune, apply })
+                .await?;
+            print!(
+                "{}",
+                output::render_operations(&response, &response.plan.operations, cli.output)?
+            );
+            Ok(())
+        }
+        Command::Sync { files, apply } => {
+            init_tracing(false);
+            // Check every file before touching any zone.
+            let states = files
+                .iter()
+                .map(|path| sync::DesiredState::load(path))
+                .collect::<Result<Vec<_>>>()?;
+            let handler = one_shot_handler(&config).await?;
+            let mut failed = 0;
+            let mut responses = Vec::new();
+            for state in &states {
+                match handler.sync(state, apply).await {
+                    Ok(response) if cli.output == output::Format::Json => responses.push(response),
+                    Ok(response) => {
+                        print!("{}", response.plan);
+                        if let Some(batch) = response.batch {
+                            println!("Applied as batch {batch}");
+                        }
+                    }
+                    Err(e) => {
+                        tracing::error!("Failed to sync {}: {}", state.zone, e);
+                        failed += 1;
+                    }
+                }
+            }
+            if cli.output == output::Format::Json {
+                println!("{}", serde_json::to_string_pretty(&responses)?);
+            }
+            if failed > 0 {
+                anyhow::bail!("{failed} of {} zone(s) failed to sync", states.len());
+            }
+            Ok(())
+        }
+    }
+}
 
-    // Initialize tracing
+/// Log to stdout for the server; one-shot commands log to stderr and keep stdout for their
+/// output.
+fn init_tracing(to_stdout: bool) {
+    let layer = if to_stdout {
+        fmt::layer().boxed()
+    } else {
+        fmt::layer().with_writer(std::io::stderr).boxed()
+    };
     tracing_subscriber::registry()
-        .with(fmt::layer())
+        .with(layer)
         .with(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
         .init();
+}
+
+/// Run the doctor checks and print them. Fails when any check does, including loading the
+/// config.
+async fn doctor(config_file: Option<&Path>, format: output::Format) -> Result<()> {
+    init_tracing(false);
+    let mut checks = Vec::new();
+    match Config::load(config_file) {
+        Ok(config) => {
+            let source = match config_file {
+                Some(path) => format!("loaded from {} and the environment", path.display()),
+                None => "loaded from the environment".to_string(),
+            };
+            checks.push(Check::new("config", Status::Ok, source));
+            match njalla::Client::new(
+                &config.njalla_api_token,
+                config.njalla_max_retries,
+                std::time::Duration::from_millis(config.njalla_retry_base_ms),
+            ) {
+                Ok(client) => checks.extend(doctor::run(&config, &client).await),
+                Err(e) => checks.push(Check::new("njalla api", Status::Fail, e.to_string())),
+            }
+        }
+        Err(e) => checks.push(Check::new("config", Status::Fail, format!("{e:#}"))),
+    }
+    print!("{}", output::render(&checks, format)?);
+
+    let failed = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failed > 0 {
+        anyhow::bail!("{failed} check(s) failed");
+    }
+    Ok(())
+}
+
+/// A handler for a one-shot command. It records history, and journals to the CLI journal next
+/// to the server's, first recovering whatever an earlier command left unfinished there.
+async fn one_shot_handler(config: &Config) -> Result<WebhookHandler> {
+    let mut handler = reload::build_handler(config.clone())?;
+    if let Some(path) = &config.journal_file {
+        let journal = open_journal(&journal::cli_path(path), &handler, config).await?;
+        handler = handler.with_journal(Arc::new(journal));
+    }
+    if let Some(path) = &config.history_file {
+        handler = handler.with_history(Arc::new(History::open(path)?));
+    }
+    Ok(handler)
+}
+
+/// Open the journal at `path` and reconcile the changes a previous run left unfinished.
+async fn open_journal(path: &Path, handler: &WebhookHandler, config: &Config) -> Result<Journal> {
+    let (journal, pending) = Journal::open(path)?;
+    if !pending.is_empty() {
+        info!(
+            "Journal {} has {} unfinished change(s)",
+            journal.path().display(),
+            pending.len()
+        );
+    }
+    journal::recover(
+        &journal,
+        pending,
+        handler.njalla_client(),
+        config.journal_recovery,
+    )
+    .await?;
+    Ok(journal)
+}
+
+async fn serve(config: Config, config_file: Option<PathBuf>) -> Result<()> {
+    init_tracing(true);
 
     info!("Starting Njalla webhook provider");
-    info!(
-        "Listening on {}:{}",
-        config.webhook_host, config.webhook_port
-    );
-
-    // Create Njalla client
-    let njalla_client = njalla::Client::new(
-        &config.njalla_api_token,
-        config.njalla_max_retries,
-        std::time::Duration::from_millis(config.njalla_retry_base_ms),
-    )?;
+
+    // Create the handler (and Njalla client); reloads swap in a new one
+    let mut handler = reload::build_handler(config.clone())?;
+
+    // Reconcile changes a previous run left unfinished before accepting new ones
+    if let Some(path) = &config.journal_file {
+        let journal = open_journal(path, &handler, &config).await?;
+        handler = handler.with_journal(Arc::new(journal));
+
+        // A CLI command that died mid-change is recovered here too, unless one is running.
+        let cli = journal::cli_path(path);
+        if cli.exists() {
+            if let Err(e) = open_journal(&cli, &handler, &config).await {
+                warn!("Skipping the CLI journal: {:#}", e);
+            }
+        }
+    }
+    if let Some(path) = &config.history_file {
+        handler = handler.with_history(Arc::new(History::open(path)?));
+    }
+    let handler = Arc::new(ArcSwap::from_pointee(handler));
+    let acceptor = if config.tls.is_enabled() {
+        Some(Arc::new(ArcSwap::from_pointee(tls::build_acceptor(
+            &config.tls,
+        )?)))
+    } else {
+        None
+    };
+    let mut reloader = Reloader::new(config_file, handler.clone());
+    if let Some(acceptor) = &acceptor {
+        reloader = reloader.with_acceptor(acceptor.clone());
+    }
+    reloader.spawn()?;
+    snapshot::spawn(handler.clone());
+    drift::spawn(handler.clone());
+    dedupe::spawn(handler.clone());
 
     // Build the application
     let app = Router::new()
-        .merge(routes::create_routes(njalla_client, config.clone()))
+        .merge(routes::create_routes(handler.clone()))
+        .route_layer(axum_middleware::from_fn_with_state(
+            middleware::RequestLimits::new(handler.clone()),
+            middleware::limits_middleware,
+        ))
+        // Body size is enforced by `limits_middleware` from the (reloadable) config
+        .layer(DefaultBodyLimit::disable())
+        .layer(axum_middleware::from_fn_with_state(
+            handler.clone(),
+            auth::auth_middleware,
+        ))
         .layer(axum_middleware::from_fn(
             middleware::error_handling_middleware,
         ))
-        .layer(axum_middleware::from_fn(middleware::logging_middleware))
+        .layer(axum_middleware::from_fn_with_state(
+            handler,
+            middleware::logging_middleware,
+        ))
         .layer(TraceLayer::new_for_http());
 
-    // Create socket address
-    let addr = SocketAddr::new(config.webhook_host.parse()?, config.webhook_port);
-
-    // Start the server
-    let listener = TcpListener::bind(addr).await?;
-    info!("Server started on {}", addr);
-
-    serve(listener, app).await?;
+    // Bind every configured listener and serve until one fails
+    let listeners = listener::bind(&config).await?;
+    listener::serve_all(listeners, app, acceptor).await?;
 
     Ok(())
 }
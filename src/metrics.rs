@@ -0,0 +1,111 @@
+//! Prometheus text exposition for `GET /metrics`.
+
+use crate::drift::DriftReport;
+use crate::template::TemplateDrift;
+use std::fmt::Write;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Render the drift gauges of `reports`: differing records per zone and kind, and when each
+/// zone was last checked; then the missing records of each zone's templates.
+pub fn render(reports: &[DriftReport], templates: &[TemplateDrift]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "# HELP njalla_webhook_drift_records Records differing from the applied baseline."
+    );
+    let _ = writeln!(out, "# TYPE njalla_webhook_drift_records gauge");
+    for report in reports {
+        for (kind, count) in [
+            ("missing", report.missing.len()),
+            ("extra", report.extra.len()),
+            ("changed", report.changed.len()),
+        ] {
+            let _ = writeln!(
+                out,
+                "njalla_webhook_drift_records{{zone=\"{}\",kind=\"{kind}\"}} {count}",
+                escape(&report.zone)
+            );
+        }
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP njalla_webhook_drift_last_check_timestamp_seconds When the zone was last checked for drift."
+    );
+    let _ = writeln!(
+        out,
+        "# TYPE njalla_webhook_drift_last_check_timestamp_seconds gauge"
+    );
+    for report in reports {
+        let _ = writeln!(
+            out,
+            "njalla_webhook_drift_last_check_timestamp_seconds{{zone=\"{}\"}} {}",
+            escape(&report.zone),
+            report.checked_at.timestamp()
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP njalla_webhook_template_missing_records Template records missing from a tagged zone."
+    );
+    let _ = writeln!(out, "# TYPE njalla_webhook_template_missing_records gauge");
+    for report in templates {
+        let _ = writeln!(
+            out,
+            "njalla_webhook_template_missing_records{{zone=\"{}\",template=\"{}\"}} {}",
+            escape(&report.zone),
+            escape(&report.template),
+            report.missing.len()
+        );
+    }
+    out
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drift;
+    use crate::njalla::DnsRecord;
+
+    #[test]
+    fn renders_drift_gauges_per_zone() {
+        let record = DnsRecord {
+            id: "1".to_string(),
+            name: "www".to_string(),
+            record_type: "A".to_string(),
+            content: "192.0.2.1".to_string(),
+            ttl: Some(300),
+            priority: None,
+        };
+        let report = drift::compare("example.com", std::slice::from_ref(&record), &[]);
+        let template = TemplateDrift {
+            zone: "example.com".to_string(),
+            template: "mail".to_string(),
+            checked_at: report.checked_at,
+            missing: vec![record.clone()],
+        };
+        let text = render(&[report], &[template]);
+        assert!(text.contains("# TYPE njalla_webhook_drift_records gauge\n"));
+        assert!(text
+            .contains("njalla_webhook_drift_records{zone=\"example.com\",kind=\"missing\"} 1\n"));
+        assert!(
+            text.contains("njalla_webhook_drift_records{zone=\"example.com\",kind=\"extra\"} 0\n")
+        );
+        assert!(text
+            .contains("njalla_webhook_drift_last_check_timestamp_seconds{zone=\"example.com\"} "));
+        assert!(text.contains(
+            "njalla_webhook_template_missing_records{zone=\"example.com\",template=\"mail\"} 1\n"
+        ));
+    }
+}
@@ -1,88 +1,86 @@
+use crate::config::{Config, PayloadLogMode};
+use crate::error::{error_response, Error};
+use crate::listener::PeerInfo;
+use crate::webhook::routes::SharedHandler;
+use crate::webhook::types::{ApplyChangesRequest, Changes, Endpoint};
 use axum::{
     body::Body,
-    extract::Request,
-    http::StatusCode,
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    http::{header, StatusCode},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use std::time::Instant;
-use tracing::{error, info, warn};
+use http_body_util::{LengthLimitError, Limited};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error, info, warn, Level};
 
-pub async fn logging_middleware(request: Request, next: Next) -> Response {
+pub async fn logging_middleware(
+    State(handler): State<SharedHandler>,
+    request: Request,
+    next: Next,
+) -> Response {
     let start = Instant::now();
     let method = request.method().clone();
     let uri = request.uri().clone();
     let path = uri.path().to_string();
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<PeerInfo>>()
+        .map(|ConnectInfo(peer)| peer.clone());
 
     // Log request
     info!(
         method = %method,
         path = %path,
         query = ?uri.query(),
+        peer = %peer.as_ref().map_or_else(|| "-".to_string(), |p| p.addr.to_string()),
+        client = peer.as_ref().and_then(|p| p.client_identity.as_deref()),
         "Incoming request"
     );
 
-    // Extract and log body for POST requests to /records
-    if method == "POST" && path == "/records" {
+    // Log the POST /records payload according to the configured mode; only buffer the body
+    // when it will actually be logged
+    let handler = handler.load_full();
+    let config = handler.config();
+    let log_body = method == "POST"
+        && path == "/records"
+        && config.payload_log != PayloadLogMode::Off
+        && tracing::enabled!(Level::DEBUG);
+    let request = if log_body {
         let (parts, body) = request.into_parts();
 
-        // Read the body
-        let bytes = match axum::body::to_bytes(body, usize::MAX).await {
+        // Read the body, bounded like every other body read
+        let bytes = match axum::body::to_bytes(body, config.max_body_bytes).await {
             Ok(bytes) => bytes,
             Err(err) => {
                 error!("Failed to read request body: {}", err);
-                return (StatusCode::BAD_REQUEST, "Failed to read request body").into_response();
+                return body_error(&err, config.max_body_bytes).into_response();
             }
         };
 
-        // Log the raw body
-        let body_str = String::from_utf8_lossy(&bytes);
-        info!("Raw request body for /records POST: {}", body_str);
-
-        // Try to parse as JSON to debug structure
-        match serde_json::from_slice::<serde_json::Value>(&bytes) {
-            Ok(json) => {
-                info!(
-                    "Parsed JSON structure: {}",
-                    serde_json::to_string_pretty(&json).unwrap_or_default()
-                );
-            }
-            Err(e) => {
-                warn!("Failed to parse body as JSON: {}", e);
-            }
-        }
+        log_payload(config, &bytes);
 
         // Reconstruct the request with the body
-        let request = Request::from_parts(parts, Body::from(bytes));
-
-        let response = next.run(request).await;
-        let duration = start.elapsed();
-        let status = response.status();
-
-        if status.is_client_error() || status.is_server_error() {
-            error!(
-                method = %method,
-                path = %path,
-                status = %status,
-                duration_ms = %duration.as_millis(),
-                "Request failed"
-            );
-        } else {
-            info!(
-                method = %method,
-                path = %path,
-                status = %status,
-                duration_ms = %duration.as_millis(),
-                "Request completed"
-            );
-        }
-
-        response
+        Request::from_parts(parts, Body::from(bytes))
     } else {
-        let response = next.run(request).await;
-        let duration = start.elapsed();
-        let status = response.status();
+        request
+    };
+
+    let response = next.run(request).await;
+    let duration = start.elapsed();
+    let status = response.status();
 
+    if status.is_client_error() || status.is_server_error() {
+        error!(
+            method = %method,
+            path = %path,
+            status = %status,
+            duration_ms = %duration.as_millis(),
+            "Request failed"
+        );
+    } else {
         info!(
             method = %method,
             path = %path,
@@ -90,18 +88,484 @@ pub async fn logging_middleware(request: Request, next: Next) -> Response {
             duration_ms = %duration.as_millis(),
             "Request completed"
         );
+    }
 
-        response
+    response
+}
+
+/// The error for a request body that couldn't be read: 413 if it was over `limit`, 400 if the
+/// client broke off or sent a malformed body.
+fn body_error(err: &axum::Error, limit: usize) -> Error {
+    let mut source: Option<&(dyn std::error::Error + 'static)> = Some(err);
+    while let Some(e) = source {
+        if e.is::<LengthLimitError>() {
+            return Error::PayloadTooLarge(format!("request body exceeds {limit} bytes"));
+        }
+        source = e.source();
+    }
+    Error::InvalidRequest(format!("failed to read request body: {err}"))
+}
+
+/// Log an apply-changes payload at DEBUG. Summary mode logs change counts and dnsNames only;
+/// full mode logs the body with targets of redacted record types masked, truncated to
+/// `payload_log_max_bytes`.
+fn log_payload(config: &Config, bytes: &[u8]) {
+    match config.payload_log {
+        PayloadLogMode::Off => {}
+        PayloadLogMode::Summary => match serde_json::from_slice::<ApplyChangesRequest>(bytes) {
+            Ok(request) => {
+                debug!(
+                    "Payload for /records POST: {}",
+                    summarize_changes(&request.into_changes())
+                );
+            }
+            Err(e) => warn!(
+                "Failed to parse /records payload ({} bytes): {}",
+                bytes.len(),
+                e
+            ),
+        },
+        PayloadLogMode::Full => match serde_json::from_slice::<serde_json::Value>(bytes) {
+            Ok(mut json) => {
+                redact_targets(&mut json, config);
+                let rendered = json.to_string();
+                debug!(
+                    "Payload for /records POST: {}",
+                    truncate(&rendered, config.payload_log_max_bytes)
+                );
+            }
+            // The raw body can't be redacted, so only its size is logged.
+            Err(e) => warn!(
+                "Failed to parse /records payload ({} bytes): {}",
+                bytes.len(),
+                e
+            ),
+        },
     }
 }
 
+fn summarize_changes(changes: &Changes) -> String {
+    let names = |endpoints: &[Endpoint]| {
+        endpoints
+            .iter()
+            .map(|e| e.dns_name.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    format!(
+        "{} creates [{}], {} updates [{}], {} deletes [{}]",
+        changes.create.len(),
+        names(&changes.create),
+        changes.update_new.len(),
+        names(&changes.update_new),
+        changes.delete.len(),
+        names(&changes.delete),
+    )
+}
+
+/// Mask the targets of every endpoint-shaped object (`recordType` + `targets`) whose record
+/// type is configured for redaction, wherever it sits in the payload.
+fn redact_targets(value: &mut serde_json::Value, config: &Config) {
+    match value {
+        serde_json::Value::Object(map) => {
+            let redacted = map
+                .get("recordType")
+                .and_then(|t| t.as_str())
+                .is_some_and(|t| config.is_payload_redacted(t));
+            if redacted {
+                if let Some(serde_json::Value::Array(targets)) = map.get_mut("targets") {
+                    for target in targets.iter_mut() {
+                        *target = serde_json::Value::from("[REDACTED]");
+                    }
+                }
+            }
+            for child in map.values_mut() {
+                redact_targets(child, config);
+            }
+        }
+        serde_json::Value::Array(items) => {
+            for item in items {
+                redact_targets(item, config);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Truncate to at most `max` bytes on a char boundary, noting how much was cut.
+fn truncate(s: &str, max: usize) -> String {
+    if s.len() <= max {
+        return s.to_string();
+    }
+    let mut end = max;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}... ({} bytes truncated)", &s[..end], s.len() - end)
+}
+
+/// Tracks in-flight requests per route so each route can be capped independently.
+pub struct RequestLimits {
+    handler: SharedHandler,
+    in_flight: Mutex<HashMap<String, usize>>,
+}
+
+impl RequestLimits {
+    pub fn new(handler: SharedHandler) -> Arc<Self> {
+        Arc::new(Self {
+            handler,
+            in_flight: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn try_acquire(self: &Arc<Self>, route: String, max: usize) -> Option<InFlightGuard> {
+        let mut in_flight = self.in_flight.lock().unwrap_or_else(|e| e.into_inner());
+        let count = in_flight.entry(route.clone()).or_default();
+        if max != 0 && *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(InFlightGuard {
+            limits: self.clone(),
+            route,
+        })
+    }
+}
+
+/// Releases a route's in-flight slot when the request finishes (or is dropped on timeout).
+struct InFlightGuard {
+    limits: Arc<RequestLimits>,
+    route: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        let mut in_flight = self
+            .limits
+            .in_flight
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        if let Some(count) = in_flight.get_mut(&self.route) {
+            *count = count.saturating_sub(1);
+        }
+    }
+}
+
+/// Enforce the configured body size, handler timeout and per-route concurrency cap. Limits
+/// are read from the current config snapshot, so they follow reloads. The admin API is
+/// exempt from the timeout: restores, dedupes and migrations legitimately run for minutes,
+/// and cutting one off would leave a zone half-changed.
+pub async fn limits_middleware(
+    State(limits): State<Arc<RequestLimits>>,
+    matched_path: Option<MatchedPath>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let handler = limits.handler.load_full();
+    let config = handler.config();
+
+    let route = format!(
+        "{} {}",
+        request.method(),
+        matched_path
+            .as_ref()
+            .map(|p| p.as_str())
+            .unwrap_or_else(|| request.uri().path())
+    );
+
+    // Reject early when the declared length is already over the limit
+    let declared_len = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if declared_len.is_some_and(|len| len > config.max_body_bytes) {
+        warn!(route = %route, "Rejecting request body over {} bytes", config.max_body_bytes);
+        return Error::PayloadTooLarge(format!(
+            "request body exceeds {} bytes",
+            config.max_body_bytes
+        ))
+        .into_response();
+    }
+
+    let Some(_guard) = limits.try_acquire(route.clone(), config.max_concurrent_requests) else {
+        warn!(route = %route, "Rejecting request: concurrency limit reached");
+        return Error::Overloaded(format!(
+            "{} requests already in flight for {}",
+            config.max_concurrent_requests, route
+        ))
+        .into_response();
+    };
+
+    // Bodies without (or lying about) Content-Length are capped while being read; extractors
+    // turn the length-limit error into a 413.
+    let (parts, body) = request.into_parts();
+    let request = Request::from_parts(parts, Body::new(Limited::new(body, config.max_body_bytes)));
+
+    let is_admin = matched_path
+        .as_ref()
+        .is_some_and(|p| p.as_str().starts_with("/admin/"));
+    if config.request_timeout_seconds == 0 || is_admin {
+        return next.run(request).await;
+    }
+
+    let timeout = Duration::from_secs(config.request_timeout_seconds);
+    match tokio::time::timeout(timeout, next.run(request)).await {
+        Ok(response) => response,
+        Err(_) => {
+            warn!(route = %route, "Request timed out after {:?}", timeout);
+            Error::Timeout(format!("{} did not finish within {:?}", route, timeout)).into_response()
+        }
+    }
+}
+
+/// Upper bound on how much of a non-JSON error body is copied into the JSON error message.
+const MAX_ERROR_BODY: usize = 4096;
+
 pub async fn error_handling_middleware(request: Request, next: Next) -> Response {
     let response = next.run(request).await;
+    let status = response.status();
 
     // If we get a 422, log more details
-    if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+    if status == StatusCode::UNPROCESSABLE_ENTITY {
         error!("422 Unprocessable Entity - likely JSON deserialization issue");
     }
 
-    response
+    // Errors produced outside `Error::into_response` (extractor rejections such as a body
+    // over the limit, or an unparseable payload) are plain text; rewrite them into the same
+    // JSON shape so clients see one error format.
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+    if !(status.is_client_error() || status.is_server_error()) || is_json {
+        return response;
+    }
+
+    let message = match axum::body::to_bytes(response.into_body(), MAX_ERROR_BODY).await {
+        Ok(bytes) if !bytes.is_empty() => String::from_utf8_lossy(&bytes).into_owned(),
+        _ => status
+            .canonical_reason()
+            .unwrap_or("request failed")
+            .to_string(),
+    };
+    error_response(status, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn payload() -> serde_json::Value {
+        json!({
+            "create": [
+                {"dnsName": "app.example.com", "targets": ["192.0.2.1"], "recordType": "A"},
+                {"dnsName": "_externaldns.app.example.com", "targets": ["\"heritage=external-dns\""], "recordType": "TXT"}
+            ],
+            "delete": [
+                {"dnsName": "old.example.com", "targets": ["192.0.2.2"], "recordType": "A"}
+            ]
+        })
+    }
+
+    #[test]
+    fn redacts_only_configured_types() {
+        let config = Config::default();
+        let mut json = payload();
+        redact_targets(&mut json, &config);
+        assert_eq!(json["create"][0]["targets"][0], "192.0.2.1");
+        assert_eq!(json["create"][1]["targets"][0], "[REDACTED]");
+    }
+
+    #[test]
+    fn wildcard_redacts_every_type() {
+        let config = Config {
+            payload_redact_types: vec!["*".to_string()],
+            ..Config::default()
+        };
+        let mut json = json!({"changes": payload()});
+        redact_targets(&mut json, &config);
+        assert_eq!(json["changes"]["create"][0]["targets"][0], "[REDACTED]");
+        assert_eq!(json["changes"]["delete"][0]["targets"][0], "[REDACTED]");
+    }
+
+    #[test]
+    fn summary_lists_counts_and_names_without_targets() {
+        let request: ApplyChangesRequest = serde_json::from_value(payload()).unwrap();
+        let summary = summarize_changes(&request.into_changes());
+        assert!(summary.contains("2 creates"), "{summary}");
+        assert!(summary.contains("old.example.com"), "{summary}");
+        assert!(!summary.contains("192.0.2.1"), "{summary}");
+    }
+
+    #[tokio::test]
+    async fn only_oversized_bodies_are_too_large() {
+        let err = axum::body::to_bytes(Body::from("far more than eight bytes"), 8)
+            .await
+            .unwrap_err();
+        assert!(matches!(body_error(&err, 8), Error::PayloadTooLarge(_)));
+
+        // Also when the cap comes from the Limited body installed by limits_middleware.
+        let limited = Body::new(Limited::new(Body::from("far more than eight bytes"), 8));
+        let err = axum::body::to_bytes(limited, 1024).await.unwrap_err();
+        assert!(matches!(body_error(&err, 8), Error::PayloadTooLarge(_)));
+
+        let stream = futures_util::stream::iter(vec![Err::<&str, _>(std::io::Error::from(
+            std::io::ErrorKind::ConnectionReset,
+        ))]);
+        let err = axum::body::to_bytes(Body::from_stream(stream), 8)
+            .await
+            .unwrap_err();
+        assert!(matches!(body_error(&err, 8), Error::InvalidRequest(_)));
+    }
+
+    #[test]
+    fn truncate_respects_char_boundaries() {
+        assert_eq!(truncate("short", 10), "short");
+        let truncated = truncate("héllo world", 2);
+        assert!(truncated.starts_with('h'), "{truncated}");
+        assert!(truncated.contains("bytes truncated"), "{truncated}");
+    }
+
+    mod limits {
+        use super::super::*;
+        use crate::webhook::testing;
+        use arc_swap::ArcSwap;
+        use axum::{routing::post, Router};
+        use tower::ServiceExt;
+
+        fn app(config: Config) -> Router {
+            let handler: SharedHandler =
+                Arc::new(ArcSwap::from_pointee(testing::offline_handler(config)));
+
+            Router::new()
+                .route("/echo", post(|body: String| async move { body }))
+                .route("/slow", post(slow))
+                .route("/admin/slow", post(slow))
+                .route_layer(axum::middleware::from_fn_with_state(
+                    RequestLimits::new(handler),
+                    limits_middleware,
+                ))
+                .layer(axum::extract::DefaultBodyLimit::disable())
+                .layer(axum::middleware::from_fn(error_handling_middleware))
+        }
+
+        async fn slow() -> &'static str {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            "done"
+        }
+
+        async fn json_body(response: Response) -> serde_json::Value {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice(&bytes).expect("error body should be JSON")
+        }
+
+        fn post_request(path: &str, body: &'static str) -> Request {
+            Request::builder()
+                .method("POST")
+                .uri(path)
+                .body(Body::from(body))
+                .unwrap()
+        }
+
+        #[tokio::test]
+        async fn oversized_body_gets_json_413() {
+            let config = Config {
+                max_body_bytes: 8,
+                ..Config::default()
+            };
+            let response = app(config)
+                .oneshot(post_request("/echo", "far more than eight bytes"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(json_body(response).await["status"], 413);
+        }
+
+        #[tokio::test]
+        async fn oversized_streamed_body_gets_json_413() {
+            let config = Config {
+                max_body_bytes: 8,
+                ..Config::default()
+            };
+            // No Content-Length: the limit must still hold while the body is read.
+            let stream = futures_util::stream::iter(vec![Ok::<_, std::io::Error>(
+                "far more than eight bytes",
+            )]);
+            let request = Request::builder()
+                .method("POST")
+                .uri("/echo")
+                .body(Body::from_stream(stream))
+                .unwrap();
+            let response = app(config).oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+            assert_eq!(json_body(response).await["status"], 413);
+        }
+
+        #[tokio::test]
+        async fn small_body_passes() {
+            let response = app(Config::default())
+                .oneshot(post_request("/echo", "hi"))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn slow_handler_gets_json_504() {
+            let config = Config {
+                request_timeout_seconds: 1,
+                ..Config::default()
+            };
+            let response = app(config)
+                .oneshot(post_request("/slow", ""))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+            assert_eq!(json_body(response).await["status"], 504);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn admin_routes_are_not_timed_out() {
+            let config = Config {
+                request_timeout_seconds: 1,
+                ..Config::default()
+            };
+            let response = app(config)
+                .oneshot(post_request("/admin/slow", ""))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        #[tokio::test(start_paused = true)]
+        async fn concurrency_cap_gets_json_503() {
+            let config = Config {
+                max_concurrent_requests: 1,
+                ..Config::default()
+            };
+            let app = app(config);
+            let first = tokio::spawn(app.clone().oneshot(post_request("/slow", "")));
+            // Let the first request reach the handler and take the only slot.
+            tokio::task::yield_now().await;
+
+            let response = app
+                .clone()
+                .oneshot(post_request("/slow", ""))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(json_body(response).await["status"], 503);
+
+            // Other routes have their own budget.
+            let response = app.oneshot(post_request("/echo", "hi")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            assert_eq!(first.await.unwrap().unwrap().status(), StatusCode::OK);
+        }
+    }
 }
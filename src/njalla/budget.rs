@@ -0,0 +1,93 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How much time and how many retries a unit of work (one `POST /records` batch) may spend on
+/// Njalla calls. Every call made for the batch takes the same budget, so retries stop once the
+/// batch as a whole has used up its allowance instead of each call retrying independently, and
+/// nothing sleeps past the point where external-dns has given up on the request.
+#[derive(Debug)]
+pub struct Budget {
+    deadline: Option<Instant>,
+    /// `None` means only each call's own `max_retries` applies.
+    retries_left: Option<AtomicU32>,
+}
+
+impl Budget {
+    /// No deadline and no shared retry limit, for work nobody is waiting on with a timeout.
+    pub fn unlimited() -> Self {
+        Self {
+            deadline: None,
+            retries_left: None,
+        }
+    }
+
+    /// A budget ending `timeout` from now and allowing `retries` retries in total. `None`
+    /// leaves that dimension unlimited.
+    pub fn new(timeout: Option<Duration>, retries: Option<u32>) -> Self {
+        Self {
+            deadline: timeout.map(|t| Instant::now() + t),
+            retries_left: retries.map(AtomicU32::new),
+        }
+    }
+
+    /// Time left before the deadline; `None` without a deadline.
+    pub fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_some_and(|left| left.is_zero())
+    }
+
+    /// Whether something starting after `delay` would still start before the deadline.
+    pub fn can_wait(&self, delay: Duration) -> bool {
+        self.remaining().is_none_or(|left| left > delay)
+    }
+
+    /// Claim one retry that starts after `delay`. Refused when the shared retry allowance is
+    /// spent or the retry couldn't even start before the deadline; nothing is consumed then.
+    pub fn try_retry(&self, delay: Duration) -> bool {
+        if !self.can_wait(delay) {
+            return false;
+        }
+        match &self.retries_left {
+            None => true,
+            Some(left) => left
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                .is_ok(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_always_allows_retries() {
+        let budget = Budget::unlimited();
+        assert!(!budget.is_expired());
+        assert!((0..100).all(|_| budget.try_retry(Duration::from_secs(60))));
+    }
+
+    #[test]
+    fn retry_allowance_is_shared() {
+        let budget = Budget::new(None, Some(2));
+        assert!(budget.try_retry(Duration::ZERO));
+        assert!(budget.try_retry(Duration::ZERO));
+        assert!(!budget.try_retry(Duration::ZERO));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn retries_that_would_outlive_the_deadline_are_refused() {
+        let budget = Budget::new(Some(Duration::from_secs(5)), Some(10));
+        assert!(budget.try_retry(Duration::from_secs(1)));
+        assert!(!budget.try_retry(Duration::from_secs(5)));
+
+        tokio::time::advance(Duration::from_secs(5)).await;
+        assert!(budget.is_expired());
+        assert!(!budget.try_retry(Duration::ZERO));
+    }
+}
@@ -1,5 +1,7 @@
+use super::budget::Budget;
 use super::types::*;
 use crate::error::{Error, Result};
+use crate::secret::SecretString;
 use reqwest::{header, Client as HttpClient, StatusCode};
 use serde_json::json;
 use std::time::Duration;
@@ -19,6 +21,9 @@ fn njalla_record_name(name: &str) -> &str {
     }
 }
 
+/// Per-attempt HTTP timeout; shortened to what is left of a [`Budget`]'s deadline.
+const HTTP_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// Upper bound on the exponential backoff delay between retries.
 const MAX_BACKOFF: Duration = Duration::from_secs(10);
 
@@ -29,6 +34,28 @@ struct AttemptError {
     retryable: bool,
 }
 
+impl AttemptError {
+    /// The error to surface once we stop retrying. A transient failure that outlasted our
+    /// retries is still transient, so it becomes [`Error::Unavailable`] (or
+    /// [`Error::Timeout`] when the batch ran out of time) and the caller — ultimately
+    /// external-dns — can retry later.
+    fn into_error(self, method: &str, attempts: u32, out_of_time: bool) -> Error {
+        if self.retryable && out_of_time {
+            Error::Timeout(format!(
+                "deadline reached during Njalla API '{}' after {} attempt(s): {}",
+                method, attempts, self.error
+            ))
+        } else if self.retryable {
+            Error::Unavailable(format!(
+                "Njalla API '{}' still failing after {} attempt(s): {}",
+                method, attempts, self.error
+            ))
+        } else {
+            self.error
+        }
+    }
+}
+
 /// A non-2xx HTTP status from Njalla is retryable when it is a rate-limit
 /// (429) or a transient server-side error (5xx). All other statuses (4xx)
 /// reflect a deterministic problem with the request and are not retried.
@@ -36,6 +63,27 @@ fn is_retryable_status(status: StatusCode) -> bool {
     status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error()
 }
 
+/// Whole messages (compared case-insensitively, ignoring a trailing period) with which Njalla
+/// reports database contention. Matching the full text keeps an unrelated validation error
+/// that merely mentions "timeout" or "locked" — a TXT value, a locked domain — permanent.
+const TRANSIENT_API_MESSAGES: &[&str] = &[
+    "lock wait timeout exceeded; try restarting transaction",
+    "deadlock found when trying to get lock; try restarting transaction",
+    "temporarily unavailable, try again later",
+];
+
+/// JSON-RPC errors are normally deterministic, but Njalla also reports throttling (code 429,
+/// mirroring the HTTP status) and contention this way; those go away on their own.
+fn is_transient_api_error(code: i32, message: &str) -> bool {
+    if code == 429 {
+        return true;
+    }
+    let message = message.trim().trim_end_matches('.');
+    TRANSIENT_API_MESSAGES
+        .iter()
+        .any(|known| message.eq_ignore_ascii_case(known))
+}
+
 /// Exponential backoff: `base * 2^(retry - 1)`, capped at `MAX_BACKOFF`.
 /// `retry` is 1-based (the delay before the first retry uses `retry == 1`).
 fn backoff_delay(base: Duration, retry: u32) -> Duration {
@@ -53,22 +101,24 @@ pub struct Client {
 }
 
 impl Client {
-    pub fn new(api_token: &str, max_retries: u32, retry_base: Duration) -> Result<Self> {
+    pub fn new(api_token: &SecretString, max_retries: u32, retry_base: Duration) -> Result<Self> {
         Self::with_api_url(api_token, max_retries, retry_base, NJALLA_API_URL)
     }
 
-    fn with_api_url(
-        api_token: &str,
+    pub(crate) fn with_api_url(
+        api_token: &SecretString,
         max_retries: u32,
         retry_base: Duration,
         api_url: &str,
     ) -> Result<Self> {
+        let auth_value = zeroize::Zeroizing::new(format!("Njalla {}", api_token.expose()));
+        let mut auth = header::HeaderValue::from_str(&auth_value)
+            .map_err(|e| Error::Configuration(format!("Invalid API token: {}", e)))?;
+        // Keeps the token out of reqwest's Debug output of the request/headers.
+        auth.set_sensitive(true);
+
         let mut headers = header::HeaderMap::new();
-        headers.insert(
-            header::AUTHORIZATION,
-            header::HeaderValue::from_str(&format!("Njalla {}", api_token))
-                .map_err(|e| Error::Configuration(format!("Invalid API token: {}", e)))?,
-        );
+        headers.insert(header::AUTHORIZATION, auth);
         headers.insert(
             header::CONTENT_TYPE,
             header::HeaderValue::from_static("application/json"),
@@ -76,7 +126,7 @@ impl Client {
 
         let http_client = HttpClient::builder()
             .default_headers(headers)
-            .timeout(Duration::from_secs(30))
+            .timeout(HTTP_TIMEOUT)
             .build()
             .map_err(|e| Error::Configuration(format!("Failed to create HTTP client: {}", e)))?;
 
@@ -98,18 +148,20 @@ impl Client {
     /// own retry loop that re-checks existence before re-sending (see [`Self::add_record`]).
     /// `remove-record` and the read methods do use this path: reads are pure, and removing an
     /// already-removed id is harmless, so a retry after an ambiguous failure can't duplicate state.
-    async fn call_api<T>(&self, request: JsonRpcRequest) -> Result<T>
+    ///
+    /// Retries also stop when `budget` runs out of retries or time.
+    async fn call_api<T>(&self, request: JsonRpcRequest, budget: &Budget) -> Result<T>
     where
         T: for<'de> serde::Deserialize<'de>,
     {
         let mut retries = 0u32;
         loop {
-            match self.attempt_call_api::<T>(&request).await {
+            match self.attempt_call_api::<T>(&request, budget).await {
                 Ok(value) => return Ok(value),
                 Err(AttemptError { error, retryable }) => {
-                    if retryable && retries < self.max_retries {
+                    let delay = backoff_delay(self.retry_base, retries + 1);
+                    if retryable && retries < self.max_retries && budget.try_retry(delay) {
                         retries += 1;
-                        let delay = backoff_delay(self.retry_base, retries);
                         warn!(
                             "Njalla API '{}' failed (attempt {}/{}): {} — retrying in {:?}",
                             request.method,
@@ -121,7 +173,11 @@ impl Client {
                         tokio::time::sleep(delay).await;
                         continue;
                     }
-                    return Err(error);
+                    return Err(AttemptError { error, retryable }.into_error(
+                        &request.method,
+                        retries + 1,
+                        !budget.can_wait(delay),
+                    ));
                 }
             }
         }
@@ -132,19 +188,30 @@ impl Client {
     async fn attempt_call_api<T>(
         &self,
         request: &JsonRpcRequest,
+        budget: &Budget,
     ) -> std::result::Result<T, AttemptError>
     where
         T: for<'de> serde::Deserialize<'de>,
     {
+        // Don't start work nobody will wait for. Not retryable here: retrying can't add time.
+        if budget.is_expired() {
+            return Err(AttemptError {
+                retryable: false,
+                error: Error::Timeout(format!(
+                    "deadline reached before Njalla API '{}'",
+                    request.method
+                )),
+            });
+        }
+
         debug!("Calling Njalla API: method={}", request.method);
 
-        let response = match self
-            .http_client
-            .post(&self.api_url)
-            .json(request)
-            .send()
-            .await
-        {
+        let mut http_request = self.http_client.post(&self.api_url).json(request);
+        if let Some(remaining) = budget.remaining() {
+            http_request = http_request.timeout(remaining.min(HTTP_TIMEOUT));
+        }
+
+        let response = match http_request.send().await {
             Ok(response) => response,
             // Connect/timeout/transport errors are transient.
             Err(e) => {
@@ -178,9 +245,10 @@ impl Client {
         };
 
         if let Some(error) = json_response.error {
-            // JSON-RPC application errors are deterministic; do not retry.
+            // JSON-RPC application errors are deterministic (do not retry), apart from
+            // contention and throttling.
             return Err(AttemptError {
-                retryable: false,
+                retryable: is_transient_api_error(error.code, &error.message),
                 error: Error::NjallaApi(format!("API error {}: {}", error.code, error.message)),
             });
         }
@@ -191,9 +259,9 @@ impl Client {
         })
     }
 
-    pub async fn list_domains(&self) -> Result<Vec<Domain>> {
+    pub async fn list_domains(&self, budget: &Budget) -> Result<Vec<Domain>> {
         let request = JsonRpcRequest::new("list-domains", json!({}));
-        let response: serde_json::Value = self.call_api(request).await?;
+        let response: serde_json::Value = self.call_api(request, budget).await?;
 
         let domains = response["domains"]
             .as_array()
@@ -206,7 +274,7 @@ impl Client {
         Ok(domains)
     }
 
-    pub async fn list_records(&self, domain: &str) -> Result<Vec<DnsRecord>> {
+    pub async fn list_records(&self, domain: &str, budget: &Budget) -> Result<Vec<DnsRecord>> {
         let request = JsonRpcRequest::new(
             "list-records",
             json!({
@@ -214,7 +282,7 @@ impl Client {
             }),
         );
 
-        let response: serde_json::Value = self.call_api(request).await?;
+        let response: serde_json::Value = self.call_api(request, budget).await?;
 
         let records = response["records"]
             .as_array()
@@ -227,7 +295,11 @@ impl Client {
         Ok(records)
     }
 
-    pub async fn add_record(&self, request: AddRecordRequest) -> Result<DnsRecord> {
+    pub async fn add_record(
+        &self,
+        request: AddRecordRequest,
+        budget: &Budget,
+    ) -> Result<DnsRecord> {
         let name = njalla_record_name(&request.name);
         let params = json!({
             "domain": request.domain,
@@ -246,7 +318,10 @@ impl Client {
         // already present, treat the create as done — avoiding a duplicate record.
         let mut retries = 0u32;
         loop {
-            match self.attempt_call_api::<DnsRecord>(&rpc_request).await {
+            match self
+                .attempt_call_api::<DnsRecord>(&rpc_request, budget)
+                .await
+            {
                 Ok(record) => {
                     info!(
                         "Added {} record {} -> {} for domain {}",
@@ -255,8 +330,11 @@ impl Client {
                     return Ok(record);
                 }
                 Err(AttemptError { error, retryable }) => {
-                    if retryable && retries < self.max_retries {
-                        if let Some(existing) = self.find_matching_record(&request, name).await {
+                    let delay = backoff_delay(self.retry_base, retries + 1);
+                    if retryable && retries < self.max_retries && budget.try_retry(delay) {
+                        if let Some(existing) =
+                            self.find_matching_record(&request, name, budget).await
+                        {
                             info!(
                                 "add-record for {} retried; matching {} record already exists — treating create as done",
                                 request.domain, request.record_type
@@ -264,7 +342,6 @@ impl Client {
                             return Ok(existing);
                         }
                         retries += 1;
-                        let delay = backoff_delay(self.retry_base, retries);
                         warn!(
                             "Njalla 'add-record' failed (attempt {}/{}): {} — retrying in {:?}",
                             retries,
@@ -275,7 +352,11 @@ impl Client {
                         tokio::time::sleep(delay).await;
                         continue;
                     }
-                    return Err(error);
+                    return Err(AttemptError { error, retryable }.into_error(
+                        &rpc_request.method,
+                        retries + 1,
+                        !budget.can_wait(delay),
+                    ));
                 }
             }
         }
@@ -289,8 +370,9 @@ impl Client {
         &self,
         request: &AddRecordRequest,
         sent_name: &str,
+        budget: &Budget,
     ) -> Option<DnsRecord> {
-        let records = self.list_records(&request.domain).await.ok()?;
+        let records = self.list_records(&request.domain, budget).await.ok()?;
         records.into_iter().find(|r| {
             r.name == sent_name
                 && r.record_type == request.record_type
@@ -298,8 +380,11 @@ impl Client {
         })
     }
 
-    #[allow(dead_code)]
-    pub async fn update_record(&self, request: UpdateRecordRequest) -> Result<DnsRecord> {
+    pub async fn update_record(
+        &self,
+        request: UpdateRecordRequest,
+        budget: &Budget,
+    ) -> Result<DnsRecord> {
         let params = json!({
             "domain": request.domain,
             "id": request.id,
@@ -308,7 +393,7 @@ impl Client {
         });
 
         let rpc_request = JsonRpcRequest::new("edit-record", params);
-        let record: DnsRecord = self.call_api(rpc_request).await?;
+        let record: DnsRecord = self.call_api(rpc_request, budget).await?;
 
         info!(
             "Updated record {} for domain {}",
@@ -317,14 +402,14 @@ impl Client {
         Ok(record)
     }
 
-    pub async fn remove_record(&self, request: RemoveRecordRequest) -> Result<()> {
+    pub async fn remove_record(&self, request: RemoveRecordRequest, budget: &Budget) -> Result<()> {
         let params = json!({
             "domain": request.domain,
             "id": request.id,
         });
 
         let rpc_request = JsonRpcRequest::new("remove-record", params);
-        let _: serde_json::Value = self.call_api(rpc_request).await?;
+        let _: serde_json::Value = self.call_api(rpc_request, budget).await?;
 
         info!(
             "Removed record {} from domain {}",
@@ -387,7 +472,7 @@ mod tests {
     fn test_client(server: &mockito::Server, max_retries: u32) -> Client {
         // Near-zero backoff keeps the tests fast.
         Client::with_api_url(
-            "token",
+            &SecretString::from("token"),
             max_retries,
             Duration::from_millis(1),
             &server.url(),
@@ -418,7 +503,7 @@ mod tests {
             .await;
 
         let client = test_client(&server, 3);
-        let result = client.list_domains().await;
+        let result = client.list_domains(&Budget::unlimited()).await;
 
         assert!(result.is_ok(), "expected success after retry: {result:?}");
         rate_limited.assert_async().await;
@@ -438,12 +523,113 @@ mod tests {
             .await;
 
         let client = test_client(&server, 2);
-        let result = client.list_domains().await;
+        let result = client.list_domains(&Budget::unlimited()).await;
 
         assert!(result.is_err(), "expected failure after exhausting retries");
         always_500.assert_async().await;
     }
 
+    #[test]
+    fn contention_and_throttling_api_errors_are_transient() {
+        assert!(is_transient_api_error(
+            500,
+            "Lock wait timeout exceeded; try restarting transaction"
+        ));
+        assert!(is_transient_api_error(
+            500,
+            "Deadlock found when trying to get lock; try restarting transaction."
+        ));
+        assert!(is_transient_api_error(429, "Too many requests"));
+        assert!(!is_transient_api_error(400, "Invalid record content"));
+        assert!(!is_transient_api_error(403, "forbidden"));
+        // Messages that merely mention a transient-sounding word stay permanent.
+        assert!(!is_transient_api_error(
+            400,
+            "Invalid TXT content: 'timeout=30'"
+        ));
+        assert!(!is_transient_api_error(403, "Domain is locked"));
+    }
+
+    #[tokio::test]
+    async fn exhausted_transient_failure_is_unavailable() {
+        let mut server = mockito::Server::new_async().await;
+        let _rate_limited = server
+            .mock("POST", "/")
+            .with_status(429)
+            .with_body("slow down")
+            .create_async()
+            .await;
+
+        let client = test_client(&server, 1);
+        let err = client.list_domains(&Budget::unlimited()).await.unwrap_err();
+        assert!(
+            matches!(err, Error::Unavailable(_)),
+            "expected Error::Unavailable, got: {err:?}"
+        );
+        assert!(err.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn stops_retrying_when_the_next_attempt_would_miss_the_deadline() {
+        let mut server = mockito::Server::new_async().await;
+        let unavailable = server
+            .mock("POST", "/")
+            .with_status(503)
+            .with_body("unavailable")
+            .expect(1)
+            .create_async()
+            .await;
+
+        // Plenty of retries, but the first backoff (1s) doesn't fit in the 200ms budget.
+        let client = Client::with_api_url(
+            &SecretString::from("token"),
+            5,
+            Duration::from_secs(1),
+            &server.url(),
+        )
+        .expect("client should build");
+        let budget = Budget::new(Some(Duration::from_millis(200)), None);
+        let err = client.list_domains(&budget).await.unwrap_err();
+
+        assert!(
+            matches!(err, Error::Timeout(_)),
+            "expected Error::Timeout, got: {err:?}"
+        );
+        unavailable.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn shared_retry_budget_caps_retries_across_calls() {
+        let mut server = mockito::Server::new_async().await;
+        // Two calls with max_retries = 3 each would make 8 attempts; the shared budget of 2
+        // retries allows 2 first attempts + 2 retries.
+        let unavailable = server
+            .mock("POST", "/")
+            .with_status(503)
+            .with_body("unavailable")
+            .expect(4)
+            .create_async()
+            .await;
+
+        let client = test_client(&server, 3);
+        let budget = Budget::new(None, Some(2));
+        assert!(client.list_domains(&budget).await.is_err());
+        assert!(client.list_domains(&budget).await.is_err());
+        unavailable.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn expired_budget_makes_no_request() {
+        let mut server = mockito::Server::new_async().await;
+        let never = server.mock("POST", "/").expect(0).create_async().await;
+
+        let client = test_client(&server, 3);
+        let budget = Budget::new(Some(Duration::ZERO), None);
+        let err = client.list_domains(&budget).await.unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)), "{err:?}");
+        never.assert_async().await;
+    }
+
     #[tokio::test]
     async fn does_not_retry_on_client_error() {
         let mut server = mockito::Server::new_async().await;
@@ -457,7 +643,7 @@ mod tests {
             .await;
 
         let client = test_client(&server, 3);
-        let result = client.list_domains().await;
+        let result = client.list_domains(&Budget::unlimited()).await;
 
         assert!(result.is_err(), "client error must surface");
         bad_request.assert_async().await;
@@ -476,9 +662,13 @@ mod tests {
             .await;
 
         let client = test_client(&server, 3);
-        let result = client.list_domains().await;
+        let result = client.list_domains(&Budget::unlimited()).await;
 
-        assert!(result.is_err(), "application error must surface");
+        let err = result.expect_err("application error must surface");
+        assert!(
+            !err.is_retryable(),
+            "application errors are permanent: {err:?}"
+        );
         app_error.assert_async().await;
     }
 
@@ -520,7 +710,7 @@ mod tests {
             priority: None,
         };
         let record = client
-            .add_record(req)
+            .add_record(req, &Budget::unlimited())
             .await
             .expect("idempotent create should resolve to the existing record");
 
@@ -1,6 +1,8 @@
+pub mod budget;
 pub mod client;
 pub mod types;
 
+pub use budget::Budget;
 pub use client::Client;
 pub use types::*;
 
@@ -8,12 +10,12 @@ use crate::error::Result;
 
 #[async_trait::async_trait]
 pub trait DomainLister: Send + Sync {
-    async fn list_domains(&self) -> Result<Vec<Domain>>;
+    async fn list_domains(&self, budget: &Budget) -> Result<Vec<Domain>>;
 }
 
 #[async_trait::async_trait]
 impl DomainLister for Client {
-    async fn list_domains(&self) -> Result<Vec<Domain>> {
-        Client::list_domains(self).await
+    async fn list_domains(&self, budget: &Budget) -> Result<Vec<Domain>> {
+        Client::list_domains(self, budget).await
     }
 }
@@ -35,7 +35,7 @@ pub struct Domain {
     pub expiry: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct DnsRecord {
     pub id: String,
     pub name: String,
@@ -46,7 +46,7 @@ pub struct DnsRecord {
     pub priority: Option<u32>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct AddRecordRequest {
     pub domain: String,
     pub name: String,
@@ -57,7 +57,6 @@ pub struct AddRecordRequest {
     pub priority: Option<u32>,
 }
 
-#[allow(dead_code)]
 #[derive(Debug, Serialize)]
 pub struct UpdateRecordRequest {
     pub domain: String,
@@ -0,0 +1,243 @@
+//! Output of the one-shot commands: aligned text tables for people, JSON for scripts.
+
+use crate::dedupe::DedupeReport;
+use crate::journal::Operation;
+use crate::njalla::{DnsRecord, Domain};
+use crate::planner::fqdn;
+use crate::webhook::types::MigrationReport;
+use serde::Serialize;
+
+/// How a command prints its result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    #[default]
+    Table,
+    Json,
+}
+
+/// Something a command can print either way.
+pub trait Tabular: Serialize {
+    fn headers() -> &'static [&'static str];
+    fn row(&self) -> Vec<String>;
+}
+
+/// `items` as a table or as pretty JSON, ending in a newline.
+pub fn render<T: Tabular>(items: &[T], format: Format) -> serde_json::Result<String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(items).map(|json| json + "\n"),
+        Format::Table => Ok(table(
+            T::headers(),
+            &items.iter().map(Tabular::row).collect::<Vec<_>>(),
+        )),
+    }
+}
+
+/// A command result that carries out a plan: the whole `response` as JSON, or a table of just
+/// its `operations`.
+pub fn render_operations<T: Serialize>(
+    response: &T,
+    operations: &[Operation],
+    format: Format,
+) -> serde_json::Result<String> {
+    match format {
+        Format::Json => serde_json::to_string_pretty(response).map(|json| json + "\n"),
+        Format::Table => render(operations, format),
+    }
+}
+
+/// Columns padded to their widest cell, two spaces apart; the last column isn't padded.
+fn table(headers: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let headers: Vec<String> = headers.iter().map(|h| h.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(&headers).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        for (i, (cell, width)) in row.iter().zip(&widths).enumerate() {
+            if i == last {
+                out.push_str(cell);
+            } else {
+                out.push_str(&format!("{cell:<width$}  "));
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn or_dash(value: Option<impl ToString>) -> String {
+    value.map_or_else(|| "-".to_string(), |v| v.to_string())
+}
+
+impl Tabular for Domain {
+    fn headers() -> &'static [&'static str] {
+        &["NAME", "STATUS", "EXPIRY"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.name.clone(),
+            self.status.clone(),
+            or_dash(self.expiry.as_ref()),
+        ]
+    }
+}
+
+impl Tabular for DnsRecord {
+    fn headers() -> &'static [&'static str] {
+        &["ID", "NAME", "TYPE", "TTL", "PRIORITY", "CONTENT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        let name = match self.name.as_str() {
+            "" => "@",
+            name => name,
+        };
+        vec![
+            self.id.clone(),
+            name.to_string(),
+            self.record_type.clone(),
+            or_dash(self.ttl),
+            or_dash(self.priority),
+            self.content.clone(),
+        ]
+    }
+}
+
+impl Tabular for Operation {
+    fn headers() -> &'static [&'static str] {
+        &["OP", "NAME", "TYPE", "TTL", "ID", "CONTENT"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        match self {
+            Operation::Add(request) => vec![
+                "add".to_string(),
+                fqdn(&request.name, &request.domain),
+                request.record_type.clone(),
+                request.ttl.to_string(),
+                "-".to_string(),
+                request.content.clone(),
+            ],
+            Operation::Remove { domain, record } => vec![
+                "remove".to_string(),
+                fqdn(&record.name, domain),
+                record.record_type.clone(),
+                or_dash(record.ttl),
+                record.id.clone(),
+                record.content.clone(),
+            ],
+            Operation::Edit {
+                domain,
+                record,
+                content,
+            } => vec![
+                "edit".to_string(),
+                fqdn(&record.name, domain),
+                record.record_type.clone(),
+                or_dash(record.ttl),
+                record.id.clone(),
+                format!("{} -> {content}", record.content),
+            ],
+        }
+    }
+}
+
+impl Tabular for DedupeReport {
+    fn headers() -> &'static [&'static str] {
+        &["ZONE", "DUPLICATES", "REMOVED", "REMAINING"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.zone.clone(),
+            self.duplicates.to_string(),
+            self.removed.to_string(),
+            self.remaining.to_string(),
+        ]
+    }
+}
+
+impl Tabular for MigrationReport {
+    fn headers() -> &'static [&'static str] {
+        &["ZONE", "PLAN", "BATCH", "ERROR"]
+    }
+
+    fn row(&self) -> Vec<String> {
+        vec![
+            self.plan.zone.clone(),
+            self.plan.summary(),
+            or_dash(self.batch),
+            or_dash(self.error.as_ref()),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tables_align_columns() {
+        let records = [
+            DnsRecord {
+                id: "12".to_string(),
+                name: String::new(),
+                record_type: "MX".to_string(),
+                content: "mail.example.com".to_string(),
+                ttl: Some(3600),
+                priority: Some(10),
+            },
+            DnsRecord {
+                id: "3".to_string(),
+                name: "www".to_string(),
+                record_type: "A".to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: None,
+                priority: None,
+            },
+        ];
+        assert_eq!(
+            render(&records, Format::Table).unwrap(),
+            "ID  NAME  TYPE  TTL   PRIORITY  CONTENT\n\
+             12  @     MX    3600  10        mail.example.com\n\
+             3   www   A     -     -         192.0.2.1\n"
+        );
+        assert!(render(&records, Format::Json)
+            .unwrap()
+            .contains("\"type\": \"MX\""));
+        assert_eq!(
+            render::<Domain>(&[], Format::Table).unwrap(),
+            "NAME  STATUS  EXPIRY\n"
+        );
+    }
+
+    #[test]
+    fn plan_results_print_their_operations_as_a_table() {
+        let ops = [Operation::Remove {
+            domain: "example.com".to_string(),
+            record: DnsRecord {
+                id: "7".to_string(),
+                name: "www".to_string(),
+                record_type: "A".to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: None,
+                priority: None,
+            },
+        }];
+        let response = serde_json::json!({"applied": false, "operations": ops});
+
+        assert_eq!(
+            render_operations(&response, &ops, Format::Table).unwrap(),
+            "OP      NAME             TYPE  TTL  ID  CONTENT\n\
+             remove  www.example.com  A     -    7   192.0.2.1\n"
+        );
+        assert!(render_operations(&response, &ops, Format::Json)
+            .unwrap()
+            .contains("\"applied\": false"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Background tasks that repeat on an interval from the configuration.
+//!
+//! Snapshots, drift checks and dedupe all run on an interval that a reload can change, or set
+//! to `0` to disable the task. [`spawn_periodic`] reads the interval from the current handler
+//! before every round, so such changes take effect without a restart.
+
+use crate::config::Config;
+use crate::webhook::handlers::WebhookHandler;
+use crate::webhook::routes::SharedHandler;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often a disabled task checks whether a reload enabled it.
+const IDLE_CHECK: Duration = Duration::from_secs(60);
+
+/// When the first round of a periodic task runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Start {
+    /// Right away, then every interval.
+    Now,
+    /// One interval after the task is spawned.
+    AfterInterval,
+}
+
+/// Run `task` with the current handler every `interval(config)` seconds; `0` disables it. The
+/// next round is scheduled once the previous one has finished, so slow rounds never overlap.
+pub fn spawn_periodic<F, Fut>(
+    handler: SharedHandler,
+    start: Start,
+    interval: fn(&Config) -> u64,
+    task: F,
+) -> tokio::task::JoinHandle<()>
+where
+    F: Fn(Arc<WebhookHandler>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    tokio::spawn(async move {
+        let mut due = start == Start::Now;
+        loop {
+            let seconds = interval(handler.load().config());
+            if seconds > 0 && due {
+                task(handler.load_full()).await;
+            }
+            due = true;
+            let wait = if seconds > 0 {
+                Duration::from_secs(seconds)
+            } else {
+                IDLE_CHECK
+            };
+            tokio::time::sleep(wait).await;
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing;
+    use arc_swap::ArcSwap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn shared_handler(config: Config) -> SharedHandler {
+        Arc::new(ArcSwap::from_pointee(testing::offline_handler(config)))
+    }
+
+    /// Spawn a task on the dedupe interval that counts its rounds.
+    fn counting(handler: &SharedHandler, start: Start) -> Arc<AtomicUsize> {
+        let rounds = Arc::new(AtomicUsize::new(0));
+        let counter = rounds.clone();
+        spawn_periodic(
+            handler.clone(),
+            start,
+            |config| config.dedupe_interval_seconds,
+            move |_| {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+        );
+        rounds
+    }
+
+    /// Let the spawned task run up to its next sleep after moving the clock forward.
+    async fn advance(seconds: u64) {
+        tokio::time::advance(Duration::from_secs(seconds)).await;
+        tokio::task::yield_now().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn runs_on_the_interval_from_the_chosen_start() {
+        let handler = shared_handler(Config {
+            dedupe_interval_seconds: 10,
+            ..Config::default()
+        });
+        let now = counting(&handler, Start::Now);
+        let later = counting(&handler, Start::AfterInterval);
+
+        advance(0).await;
+        assert_eq!(now.load(Ordering::SeqCst), 1);
+        assert_eq!(later.load(Ordering::SeqCst), 0);
+
+        advance(10).await;
+        assert_eq!(now.load(Ordering::SeqCst), 2);
+        assert_eq!(later.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_reload_enables_a_disabled_task() {
+        let handler = shared_handler(Config::default());
+        let rounds = counting(&handler, Start::Now);
+
+        advance(0).await;
+        assert_eq!(rounds.load(Ordering::SeqCst), 0);
+
+        let enabled = Config {
+            dedupe_interval_seconds: 10,
+            ..Config::default()
+        };
+        handler.store(Arc::new(testing::offline_handler(enabled)));
+        advance(IDLE_CHECK.as_secs()).await;
+        assert_eq!(rounds.load(Ordering::SeqCst), 1);
+    }
+}
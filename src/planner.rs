@@ -0,0 +1,318 @@
+//! Diffing a desired set of records against a live zone.
+//!
+//! Records are compared by name, type and content. A desired record with a live twin is kept
+//! as is unless its TTL or priority differ, in which case the live one is replaced; desired
+//! records without a twin are added and live records without one are removed. Removals come
+//! first so a replaced CNAME never coexists with its successor.
+
+use crate::journal::Operation;
+use crate::njalla::{AddRecordRequest, DnsRecord};
+use serde::Serialize;
+use std::fmt;
+
+/// TTL for added records that don't specify one.
+pub const DEFAULT_TTL: u32 = 3600;
+
+/// The operations that turn a live zone into the desired one.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Plan {
+    pub zone: String,
+    pub operations: Vec<Operation>,
+    /// Differences left alone, with the reason.
+    pub skipped: Vec<String>,
+}
+
+impl Plan {
+    pub fn is_empty(&self) -> bool {
+        self.operations.is_empty()
+    }
+
+    /// `N to add, N to remove`, with `N to edit` in between when the plan edits records.
+    pub fn summary(&self) -> String {
+        Counts::of(&self.operations).to_string()
+    }
+}
+
+/// How many operations of each kind a list holds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Counts {
+    pub adds: usize,
+    pub edits: usize,
+    pub removes: usize,
+}
+
+impl Counts {
+    pub fn of(operations: &[Operation]) -> Self {
+        let count = |f: fn(&Operation) -> bool| operations.iter().filter(|op| f(op)).count();
+        Self {
+            adds: count(|op| matches!(op, Operation::Add(_))),
+            edits: count(|op| matches!(op, Operation::Edit { .. })),
+            removes: count(|op| matches!(op, Operation::Remove { .. })),
+        }
+    }
+}
+
+/// "N to add, N to remove", with "N to edit" in between when there are edits.
+impl fmt::Display for Counts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self {
+            adds,
+            edits,
+            removes,
+        } = self;
+        if *edits > 0 {
+            write!(f, "{adds} to add, {edits} to edit, {removes} to remove")
+        } else {
+            write!(f, "{adds} to add, {removes} to remove")
+        }
+    }
+}
+
+/// Terraform-style: the summary, then one line per operation (`+` add, `~` edit, `-` remove)
+/// and per skipped difference (`!`).
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}: {}", self.zone, self.summary())?;
+        for op in &self.operations {
+            match op {
+                Operation::Add(request) => writeln!(
+                    f,
+                    "  + {} {} {} (ttl {})",
+                    fqdn(&request.name, &self.zone),
+                    request.record_type,
+                    request.content,
+                    request.ttl
+                )?,
+                Operation::Edit {
+                    record, content, ..
+                } => writeln!(
+                    f,
+                    "  ~ {} {} {} -> {} (id {})",
+                    fqdn(&record.name, &self.zone),
+                    record.record_type,
+                    record.content,
+                    content,
+                    record.id
+                )?,
+                Operation::Remove { record, .. } => writeln!(
+                    f,
+                    "  - {} {} {} (id {})",
+                    fqdn(&record.name, &self.zone),
+                    record.record_type,
+                    record.content,
+                    record.id
+                )?,
+            }
+        }
+        for skipped in &self.skipped {
+            writeln!(f, "  ! {skipped}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Name as Njalla requests take it: relative to the zone, empty for the apex.
+pub fn relative_name(name: &str) -> &str {
+    if name == "@" {
+        ""
+    } else {
+        name
+    }
+}
+
+/// Full DNS name of a record named `name` in `zone`.
+pub fn fqdn(name: &str, zone: &str) -> String {
+    match relative_name(name) {
+        "" => zone.to_string(),
+        name => format!("{name}.{zone}"),
+    }
+}
+
+/// Whether `existing` already holds a record with this name, type and content, whatever its
+/// TTL or priority. Names and types compare like in [`diff`].
+pub fn exists(existing: &[DnsRecord], name: &str, record_type: &str, content: &str) -> bool {
+    existing.iter().any(|record| {
+        relative_name(&record.name).eq_ignore_ascii_case(relative_name(name))
+            && record.record_type.eq_ignore_ascii_case(record_type)
+            && record.content == content
+    })
+}
+
+fn same_record(a: &DnsRecord, b: &DnsRecord) -> bool {
+    relative_name(&a.name).eq_ignore_ascii_case(relative_name(&b.name))
+        && a.record_type.eq_ignore_ascii_case(&b.record_type)
+        && a.content == b.content
+}
+
+/// Plan the changes that make `live` match `desired` in `zone`. The ids of desired records are
+/// ignored. Only live records for which `in_scope` returns true may be removed; records whose
+/// type `allowed` rejects are neither added nor removed and end up in `skipped`.
+pub fn diff(
+    zone: &str,
+    desired: &[DnsRecord],
+    live: &[DnsRecord],
+    in_scope: impl Fn(&DnsRecord) -> bool,
+    allowed: impl Fn(&str) -> bool,
+) -> Plan {
+    let mut plan = Plan {
+        zone: zone.to_string(),
+        ..Plan::default()
+    };
+    let mut matched = vec![false; live.len()];
+    let mut removes = Vec::new();
+    let mut adds = Vec::new();
+
+    for want in desired {
+        let twin = live
+            .iter()
+            .enumerate()
+            .position(|(i, have)| !matched[i] && same_record(want, have));
+        match twin {
+            Some(i) => {
+                matched[i] = true;
+                let have = &live[i];
+                let ttl_differs = want.ttl.is_some_and(|ttl| have.ttl != Some(ttl));
+                let priority_differs = want.priority.is_some() && want.priority != have.priority;
+                if ttl_differs || priority_differs {
+                    removes.push(have);
+                    adds.push(want);
+                }
+            }
+            None => adds.push(want),
+        }
+    }
+    removes.extend(
+        live.iter()
+            .zip(&matched)
+            .filter(|(have, matched)| !**matched && in_scope(have))
+            .map(|(have, _)| have),
+    );
+
+    for have in removes {
+        let op = Operation::Remove {
+            domain: zone.to_string(),
+            record: have.clone(),
+        };
+        if allowed(&have.record_type) {
+            plan.operations.push(op);
+        } else {
+            plan.skipped
+                .push(format!("{op}: record type not allowed in zone"));
+        }
+    }
+    for want in adds {
+        let op = Operation::Add(AddRecordRequest {
+            domain: zone.to_string(),
+            name: relative_name(&want.name).to_string(),
+            record_type: want.record_type.to_ascii_uppercase(),
+            content: want.content.clone(),
+            ttl: want.ttl.unwrap_or(DEFAULT_TTL),
+            priority: want.priority,
+        });
+        if allowed(&want.record_type) {
+            plan.operations.push(op);
+        } else {
+            plan.skipped
+                .push(format!("{op}: record type not allowed in zone"));
+        }
+    }
+    plan
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(id: &str, name: &str, record_type: &str, content: &str, ttl: u32) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: Some(ttl),
+            priority: None,
+        }
+    }
+
+    fn everything(_: &DnsRecord) -> bool {
+        true
+    }
+
+    fn any_type(_: &str) -> bool {
+        true
+    }
+
+    #[test]
+    fn identical_zones_need_nothing() {
+        let live = [record("1", "@", "A", "192.0.2.1", 300)];
+        let desired = [record("", "", "A", "192.0.2.1", 300)];
+        let plan = diff("example.com", &desired, &live, everything, any_type);
+        assert!(plan.is_empty());
+    }
+
+    #[test]
+    fn missing_extra_and_changed_records() {
+        let live = [
+            record("1", "www", "A", "192.0.2.1", 300),
+            record("2", "old", "A", "192.0.2.2", 300),
+            record("3", "mail", "A", "192.0.2.3", 300),
+        ];
+        let desired = [
+            record("", "www", "A", "192.0.2.1", 300),
+            record("", "new", "A", "192.0.2.4", 300),
+            record("", "mail", "A", "192.0.2.3", 60),
+        ];
+        let plan = diff("example.com", &desired, &live, everything, any_type);
+
+        let described: Vec<String> = plan.operations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            described,
+            [
+                "remove A 'mail' -> 192.0.2.3 (id 3) from example.com",
+                "remove A 'old' -> 192.0.2.2 (id 2) from example.com",
+                "add A 'new' -> 192.0.2.4 in example.com",
+                "add A 'mail' -> 192.0.2.3 in example.com",
+            ]
+        );
+        assert_eq!(plan.summary(), "2 to add, 2 to remove");
+        assert_eq!(
+            plan.to_string().lines().nth(1),
+            Some("  - mail.example.com A 192.0.2.3 (id 3)")
+        );
+    }
+
+    #[test]
+    fn live_duplicates_are_removed() {
+        let live = [
+            record("1", "www", "A", "192.0.2.1", 300),
+            record("2", "www", "A", "192.0.2.1", 300),
+        ];
+        let desired = [record("", "www", "A", "192.0.2.1", 300)];
+        let plan = diff("example.com", &desired, &live, everything, any_type);
+        assert_eq!(plan.operations.len(), 1);
+        assert!(
+            matches!(&plan.operations[0], Operation::Remove { record, .. } if record.id == "2")
+        );
+    }
+
+    #[test]
+    fn out_of_scope_and_disallowed_records_are_left_alone() {
+        let live = [
+            record("1", "@", "NS", "ns1.njal.la", 3600),
+            record("2", "www", "TXT", "hello", 300),
+        ];
+        let desired = [record("", "api", "MX", "mail.example.com", 300)];
+        let plan = diff(
+            "example.com",
+            &desired,
+            &live,
+            |r| r.record_type != "NS",
+            |t| t != "MX",
+        );
+        assert_eq!(plan.operations.len(), 1);
+        assert!(
+            matches!(&plan.operations[0], Operation::Remove { record, .. } if record.id == "2")
+        );
+        assert_eq!(plan.skipped.len(), 1);
+    }
+}
@@ -0,0 +1,707 @@
+//! external-dns's TXT registry: the ownership records it keeps next to every record it manages.
+//!
+//! With `--txt-prefix=_externaldns.` external-dns names the ownership record of `www.example.com`
+//! either `_externaldns.www.example.com` (the old format, one record for every type at the
+//! name) or `_externaldns.a-www.example.com` (the current format, one per type). At the apex
+//! the type marker fuses onto the zone's first label: `_externaldns.a-example.com`. Both
+//! formats hold the same `heritage=external-dns,external-dns/owner=...` content.
+
+use crate::error::{Error, Result};
+use crate::journal::Operation;
+use crate::njalla::{AddRecordRequest, Budget, DnsRecord};
+use crate::planner::{self, relative_name, Plan, DEFAULT_TTL};
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::{Endpoint, MigrateQuery, MigrationReport, OrphanQuery, OrphanReport};
+use serde::Serialize;
+use tracing::{error, info};
+
+/// Record types external-dns writes as `<type>-` markers in registry names.
+pub const AFFIX_TYPES: &[&str] = &[
+    "a", "aaaa", "cname", "txt", "ns", "ptr", "srv", "mx", "naptr", "soa", "caa",
+];
+
+/// Whether external-dns may manage records of `record_type`, and so keeps an ownership record
+/// for them: every type it has a registry marker for.
+fn is_managed_type(record_type: &str) -> bool {
+    AFFIX_TYPES
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(record_type))
+}
+
+/// True when `name` is external-dns's affixed registry TXT for the APEX of `zone` — i.e.
+/// `<prefix><type>-<zone>` (e.g. `_externaldns.a-whathefolk.com` for zone `whathefolk.com`). The
+/// `<type>-` record-type marker fuses onto the zone's leftmost label, so the normal `.{zone}`
+/// suffix check misses it. The match is constrained to a real DNS record-type marker so a sibling
+/// domain like `api-example.com` is NOT misclassified as belonging to `example.com`.
+pub fn is_affixed_apex_of(name: &str, zone: &str) -> bool {
+    // Strip the `-<zone>` tail; the remainder must be `<labels>.<type>` — i.e. the `<type>-`
+    // marker is preceded by at least one label (the registry prefix, e.g. `_externaldns.`).
+    // Requiring a non-empty prefix-before-the-marker is what distinguishes a real affix
+    // (`_externaldns.a-<zone>`) from a real sibling host (`a-example.com`) or a real subdomain
+    // (`cname-foo.example.com`), where the type-looking token IS the leftmost label.
+    match name.strip_suffix(&format!("-{zone}")) {
+        Some(prefix) => match prefix.rsplit_once('.') {
+            Some((before, marker)) => !before.is_empty() && AFFIX_TYPES.contains(&marker),
+            None => false,
+        },
+        None => false,
+    }
+}
+
+/// True for the TXT records external-dns writes to record ownership.
+pub fn is_ownership_record(record: &DnsRecord) -> bool {
+    record.record_type.eq_ignore_ascii_case("TXT")
+        && record.content.contains("heritage=external-dns")
+}
+
+/// The owner id in an ownership record's content, if any.
+pub fn owner_of(content: &str) -> Option<&str> {
+    content
+        .trim_matches('"')
+        .split(',')
+        .find_map(|field| field.strip_prefix("external-dns/owner="))
+}
+
+/// Which naming scheme an ownership record uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    /// `<prefix><name>`, covering every type at the name.
+    Old,
+    /// `<prefix><type>-<name>`, covering one type.
+    Affixed,
+}
+
+/// A record an ownership record may stand for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Owned {
+    /// Fully qualified name, as external-dns sees it.
+    pub dns_name: String,
+    /// Upper-case type for the affixed format; `None` (any type) for the old one.
+    pub record_type: Option<String>,
+    pub format: Format,
+}
+
+/// What the ownership record named `dns_name` in `zone` may stand for. A name like
+/// `_externaldns.a-b.example.com` reads as the affixed record of `b.example.com`'s A record and
+/// as the old-format record of `a-b.example.com`, so both are returned; names outside the
+/// registry (not starting with `prefix`) yield nothing.
+pub fn owned_by(dns_name: &str, zone: &str, prefix: &str) -> Vec<Owned> {
+    let name = dns_name
+        .strip_suffix('.')
+        .unwrap_or(dns_name)
+        .to_ascii_lowercase();
+    let Some(rest) = name.strip_prefix(&prefix.to_ascii_lowercase()) else {
+        return Vec::new();
+    };
+    if rest.is_empty() {
+        return Vec::new();
+    }
+
+    let mut owned = Vec::new();
+    let affixed = |marker: &str, dns_name: String| Owned {
+        dns_name,
+        record_type: Some(marker.to_ascii_uppercase()),
+        format: Format::Affixed,
+    };
+    if is_affixed_apex_of(&name, zone) {
+        let marker = &rest[..rest.len() - zone.len() - 1];
+        owned.push(affixed(marker, zone.to_string()));
+    } else {
+        let (first, tail) = match rest.split_once('.') {
+            Some((first, tail)) => (first, Some(tail)),
+            None => (rest, None),
+        };
+        if let Some((marker, label)) = first.split_once('-') {
+            if AFFIX_TYPES.contains(&marker) && !label.is_empty() {
+                let dns_name = match tail {
+                    Some(tail) => format!("{label}.{tail}"),
+                    None => label.to_string(),
+                };
+                owned.push(affixed(marker, dns_name));
+            }
+        }
+    }
+    owned.push(Owned {
+        dns_name: rest.to_string(),
+        record_type: None,
+        format: Format::Old,
+    });
+    owned
+}
+
+/// The affixed-format name of the ownership record for `record_type` records at `dns_name`.
+pub fn affixed_name(dns_name: &str, record_type: &str, prefix: &str) -> String {
+    format!("{prefix}{}-{dns_name}", record_type.to_ascii_lowercase())
+}
+
+/// An ownership record found in a zone.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RegistryRecord {
+    /// Fully qualified name, as external-dns sees it.
+    pub dns_name: String,
+    pub format: Format,
+    pub owner: Option<String>,
+    pub record: DnsRecord,
+}
+
+/// Ownership records without a record to own (`orphaned`), and records that look managed but
+/// have no ownership record (`unowned`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ownership {
+    pub orphaned: Vec<RegistryRecord>,
+    pub unowned: Vec<DnsRecord>,
+}
+
+/// Match the ownership records of `zone` against the records they stand for. `relative` turns a
+/// fully qualified name into the zone-relative one Njalla stores (the webhook's
+/// `extract_record_name`).
+pub fn analyze(
+    zone: &str,
+    records: &[DnsRecord],
+    prefix: &str,
+    relative: impl Fn(&str) -> String,
+) -> Ownership {
+    let registry: Vec<(&DnsRecord, String, Vec<Owned>)> = records
+        .iter()
+        .filter(|r| is_ownership_record(r))
+        .filter_map(|r| {
+            let dns_name = Endpoint::from_njalla_record(r, zone).dns_name;
+            let owned = owned_by(&dns_name, zone, prefix);
+            (!owned.is_empty()).then_some((r, dns_name, owned))
+        })
+        .collect();
+    let managed: Vec<&DnsRecord> = records
+        .iter()
+        .filter(|r| is_managed_type(&r.record_type))
+        .filter(|r| !is_ownership_record(r))
+        .collect();
+
+    let covers = |owned: &Owned, record: &DnsRecord| {
+        relative(&owned.dns_name) == relative_name(&record.name).to_ascii_lowercase()
+            && owned
+                .record_type
+                .as_deref()
+                .is_none_or(|t| record.record_type.eq_ignore_ascii_case(t))
+    };
+
+    let mut ownership = Ownership::default();
+    for (record, dns_name, owned) in &registry {
+        let in_use = owned
+            .iter()
+            .any(|o| managed.iter().any(|record| covers(o, record)));
+        if !in_use {
+            ownership.orphaned.push(RegistryRecord {
+                dns_name: dns_name.clone(),
+                format: owned[0].format,
+                owner: owner_of(&record.content).map(str::to_string),
+                record: (*record).clone(),
+            });
+        }
+    }
+    ownership.unowned = managed
+        .into_iter()
+        .filter(|record| {
+            !registry
+                .iter()
+                .any(|(_, _, owned)| owned.iter().any(|o| covers(o, record)))
+        })
+        .cloned()
+        .collect();
+    ownership
+}
+
+/// Plan moving `zone` to the affixed format: for every old-format ownership record, add an
+/// affixed record with the same content for each type at its name that has none yet, then, with
+/// `remove_old`, remove the old record. Adds come first, so an old record only goes once its
+/// replacements exist. Old records with nothing to own are left to the orphan cleanup.
+pub fn plan_migration(
+    zone: &str,
+    records: &[DnsRecord],
+    prefix: &str,
+    relative: impl Fn(&str) -> String,
+    remove_old: bool,
+) -> Plan {
+    let mut plan = Plan {
+        zone: zone.to_string(),
+        ..Plan::default()
+    };
+    let registry_names: Vec<String> = records
+        .iter()
+        .filter(|r| is_ownership_record(r))
+        .map(|r| relative_name(&r.name).to_ascii_lowercase())
+        .collect();
+    let managed: Vec<&DnsRecord> = records
+        .iter()
+        .filter(|r| is_managed_type(&r.record_type))
+        .filter(|r| !is_ownership_record(r))
+        .collect();
+    let types_at = |dns_name: &str| {
+        let name = relative(dns_name);
+        let mut types: Vec<String> = managed
+            .iter()
+            .filter(|r| relative_name(&r.name).eq_ignore_ascii_case(&name))
+            .map(|r| r.record_type.to_ascii_uppercase())
+            .collect();
+        types.sort();
+        types.dedup();
+        types
+    };
+
+    let mut adds = Vec::new();
+    let mut removes = Vec::new();
+    for record in records.iter().filter(|r| is_ownership_record(r)) {
+        let dns_name = Endpoint::from_njalla_record(record, zone).dns_name;
+        let owned = owned_by(&dns_name, zone, prefix);
+        // An affixed reading that owns something means the record is already in the new format.
+        let is_affixed = owned.iter().any(|o| {
+            o.format == Format::Affixed
+                && o.record_type
+                    .as_ref()
+                    .is_some_and(|t| types_at(&o.dns_name).contains(t))
+        });
+        let Some(old) = owned.iter().find(|o| o.format == Format::Old) else {
+            continue;
+        };
+        if is_affixed {
+            continue;
+        }
+        let types = types_at(&old.dns_name);
+        if types.is_empty() {
+            plan.skipped.push(format!(
+                "{dns_name}: no record to own (see /admin/registry/orphans)"
+            ));
+            continue;
+        }
+
+        for record_type in types {
+            let name = relative(&affixed_name(&old.dns_name, &record_type, prefix));
+            if registry_names.contains(&name) {
+                continue;
+            }
+            adds.push(Operation::Add(AddRecordRequest {
+                domain: zone.to_string(),
+                name,
+                record_type: "TXT".to_string(),
+                content: record.content.clone(),
+                ttl: record.ttl.unwrap_or(DEFAULT_TTL),
+                priority: None,
+            }));
+        }
+        if remove_old {
+            removes.push(Operation::Remove {
+                domain: zone.to_string(),
+                record: record.clone(),
+            });
+        }
+    }
+    plan.operations = adds.into_iter().chain(removes).collect();
+    plan
+}
+
+// The registry admin endpoints.
+impl WebhookHandler {
+    /// Find ownership TXT records whose record is gone and records with no ownership record.
+    /// With `apply`, delete the orphans (of `owner` only, when given) as one batch per zone,
+    /// under the same deadline, record type policy and dry-run rules as `POST /records`.
+    pub async fn registry_orphans(&self, query: OrphanQuery) -> Result<Vec<OrphanReport>> {
+        let zones = self
+            .selected_zones(query.zone.as_deref(), &Budget::unlimited())
+            .await?;
+        let prefix = &self.config.registry_txt_prefix;
+
+        let mut reports = Vec::new();
+        for zone in zones {
+            let batch = Batch::new(self.batch_budget(), None);
+            let mut report = OrphanReport {
+                zone: zone.clone(),
+                orphaned: Vec::new(),
+                unowned: Vec::new(),
+                removed: 0,
+                error: None,
+            };
+            let result = async {
+                let records = self
+                    .njalla_client
+                    .list_records(&zone, &batch.budget)
+                    .await?;
+                let ownership = analyze(&zone, &records, prefix, |name| {
+                    self.extract_record_name(name, &zone)
+                });
+                report.orphaned = ownership.orphaned;
+                report.unowned = ownership.unowned;
+
+                let ops: Vec<Operation> = report
+                    .orphaned
+                    .iter()
+                    .filter(|o| {
+                        query
+                            .owner
+                            .as_deref()
+                            .is_none_or(|owner| o.owner.as_deref() == Some(owner))
+                    })
+                    .map(|o| Operation::Remove {
+                        domain: zone.clone(),
+                        record: o.record.clone(),
+                    })
+                    .collect();
+                if query.apply && !ops.is_empty() {
+                    self.ensure_record_type_allowed(&zone, "TXT")?;
+                    self.run_change(
+                        &format!("Remove orphaned ownership records in {zone}"),
+                        &ops,
+                        &batch,
+                    )
+                    .await?;
+                    if !self.config.dry_run {
+                        report.removed = ops.len();
+                    }
+                }
+                Ok::<_, Error>(())
+            }
+            .await;
+
+            match result {
+                Ok(()) => info!(
+                    "Registry of {}: {} orphaned ownership record(s), {} unowned record(s), {} removed",
+                    zone,
+                    report.orphaned.len(),
+                    report.unowned.len(),
+                    report.removed
+                ),
+                Err(e) => {
+                    error!("Registry cleanup of {} failed: {}", zone, e);
+                    report.error = Some(e.to_string());
+                }
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Plan moving old-format ownership records (`_externaldns.<name>`) to the affixed format
+    /// (`_externaldns.<type>-<name>`), zone by zone. With `apply`, each zone's plan runs as one
+    /// batch; a zone that fails is reported and the rest carry on.
+    pub async fn migrate_registry(&self, query: MigrateQuery) -> Result<Vec<MigrationReport>> {
+        let zones = self
+            .selected_zones(query.zone.as_deref(), &Budget::unlimited())
+            .await?;
+        let prefix = &self.config.registry_txt_prefix;
+
+        let mut reports = Vec::new();
+        for (i, zone) in zones.iter().enumerate() {
+            // Migrations aren't bound by external-dns's request timeout.
+            let batch = Batch::new(Budget::unlimited(), None);
+            let mut report = MigrationReport {
+                applied: false,
+                batch: None,
+                error: None,
+                plan: planner::Plan {
+                    zone: zone.clone(),
+                    ..planner::Plan::default()
+                },
+            };
+            let result = async {
+                let records = self.njalla_client.list_records(zone, &batch.budget).await?;
+                report.plan = plan_migration(
+                    zone,
+                    &records,
+                    prefix,
+                    |name| self.extract_record_name(name, zone),
+                    query.remove_old,
+                );
+                if query.apply && !report.plan.is_empty() {
+                    self.ensure_record_type_allowed(zone, "TXT")?;
+                    self.run_change(
+                        &format!("Migrate registry records in {zone}"),
+                        &report.plan.operations,
+                        &batch,
+                    )
+                    .await?;
+                    report.applied = !self.config.dry_run;
+                    report.batch = report.applied.then_some(batch.id);
+                }
+                Ok::<_, Error>(())
+            }
+            .await;
+
+            match result {
+                Ok(()) => info!(
+                    "Registry migration {}/{}: {}: {}{}",
+                    i + 1,
+                    zones.len(),
+                    zone,
+                    report.plan.summary(),
+                    if report.applied { ", applied" } else { "" }
+                ),
+                Err(e) => {
+                    error!(
+                        "Registry migration {}/{}: {} failed: {}",
+                        i + 1,
+                        zones.len(),
+                        zone,
+                        e
+                    );
+                    report.error = Some(e.to_string());
+                }
+            }
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use serde_json::json;
+
+    #[test]
+    fn affixed_apex_is_recognised_only_with_a_prefix_and_type_marker() {
+        assert!(is_affixed_apex_of(
+            "_externaldns.a-example.com",
+            "example.com"
+        ));
+        assert!(is_affixed_apex_of(
+            "_externaldns.cname-example.com",
+            "example.com"
+        ));
+        assert!(!is_affixed_apex_of("a-example.com", "example.com"));
+        assert!(!is_affixed_apex_of(
+            "_externaldns.api-example.com",
+            "example.com"
+        ));
+    }
+
+    #[test]
+    fn registry_names_map_to_the_records_they_own() {
+        let prefix = "_externaldns.";
+        assert_eq!(
+            owned_by("_externaldns.a-www.example.com", "example.com", prefix),
+            [
+                Owned {
+                    dns_name: "www.example.com".to_string(),
+                    record_type: Some("A".to_string()),
+                    format: Format::Affixed,
+                },
+                Owned {
+                    dns_name: "a-www.example.com".to_string(),
+                    record_type: None,
+                    format: Format::Old,
+                },
+            ]
+        );
+        assert_eq!(
+            owned_by("_externaldns.cname-example.com", "example.com", prefix)[0],
+            Owned {
+                dns_name: "example.com".to_string(),
+                record_type: Some("CNAME".to_string()),
+                format: Format::Affixed,
+            }
+        );
+        assert_eq!(
+            owned_by("_externaldns.www.example.com", "example.com", prefix),
+            [Owned {
+                dns_name: "www.example.com".to_string(),
+                record_type: None,
+                format: Format::Old,
+            }]
+        );
+        assert!(owned_by("www.example.com", "example.com", prefix).is_empty());
+    }
+
+    fn record(id: &str, name: &str, record_type: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        }
+    }
+
+    /// The handler's `extract_record_name` for `example.com`.
+    fn relative(name: &str) -> String {
+        match name.strip_suffix(".example.com") {
+            Some(name) => name.to_string(),
+            None if name == "example.com" => String::new(),
+            None => name.to_string(),
+        }
+    }
+
+    #[test]
+    fn finds_orphaned_and_unowned_records() {
+        let heritage = "\"heritage=external-dns,external-dns/owner=prod\"";
+        let records = [
+            record("1", "www", "A", "192.0.2.1"),
+            record("2", "_externaldns.a-www", "TXT", heritage),
+            record("3", "_externaldns.www", "TXT", heritage),
+            // The apex A record is owned through the affixed apex name.
+            record("4", "", "A", "192.0.2.2"),
+            record("5", "_externaldns.a-example.com", "TXT", heritage),
+            // Its record is gone.
+            record("6", "_externaldns.cname-old", "TXT", heritage),
+            // Managed-looking, but nobody owns it.
+            record("7", "manual", "A", "192.0.2.7"),
+            // A delegation external-dns manages, owned like any other type.
+            record("8", "sub", "NS", "ns1.example.net"),
+            record("9", "_externaldns.ns-sub", "TXT", heritage),
+        ];
+        let ownership = analyze("example.com", &records, "_externaldns.", relative);
+
+        let orphaned: Vec<&str> = ownership
+            .orphaned
+            .iter()
+            .map(|o| o.record.id.as_str())
+            .collect();
+        assert_eq!(orphaned, ["6"]);
+        assert_eq!(
+            ownership.orphaned[0].dns_name,
+            "_externaldns.cname-old.example.com"
+        );
+        assert_eq!(ownership.orphaned[0].format, Format::Affixed);
+        assert_eq!(ownership.orphaned[0].owner.as_deref(), Some("prod"));
+        assert_eq!(ownership.unowned, [record("7", "manual", "A", "192.0.2.7")]);
+    }
+
+    #[test]
+    fn migration_adds_affixed_records_before_removing_old_ones() {
+        let heritage = "\"heritage=external-dns,external-dns/owner=prod\"";
+        let records = [
+            record("1", "www", "A", "192.0.2.1"),
+            record("2", "www", "AAAA", "2001:db8::1"),
+            record("3", "_externaldns.www", "TXT", heritage),
+            // Already migrated in part.
+            record("4", "_externaldns.aaaa-www", "TXT", heritage),
+            record("5", "", "CNAME", "lb.example.net"),
+            record("6", "_externaldns", "TXT", heritage),
+            // Nothing left to own.
+            record("7", "_externaldns.gone", "TXT", heritage),
+        ];
+
+        let plan = plan_migration("example.com", &records, "_externaldns.", relative, true);
+        let described: Vec<String> = plan.operations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            described,
+            [
+                format!("add TXT '_externaldns.a-www' -> {heritage} in example.com"),
+                format!("add TXT '_externaldns.cname-example.com' -> {heritage} in example.com"),
+                format!("remove TXT '_externaldns.www' -> {heritage} (id 3) from example.com"),
+                format!("remove TXT '_externaldns' -> {heritage} (id 6) from example.com"),
+            ]
+        );
+        assert_eq!(plan.skipped.len(), 1);
+
+        let kept = plan_migration("example.com", &records, "_externaldns.", relative, false);
+        assert_eq!(kept.summary(), "2 to add, 0 to remove");
+    }
+
+    #[test]
+    fn owner_is_read_from_the_content() {
+        assert_eq!(
+            owner_of("\"heritage=external-dns,external-dns/owner=prod,external-dns/resource=x\""),
+            Some("prod")
+        );
+        assert_eq!(owner_of("heritage=external-dns"), None);
+    }
+
+    #[tokio::test]
+    async fn orphaned_ownership_records_of_one_owner_are_deleted() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 300},
+                {"id": "2", "name": "_externaldns.a-www", "type": "TXT",
+                    "content": "\"heritage=external-dns,external-dns/owner=prod\"", "ttl": 300},
+                {"id": "3", "name": "_externaldns.a-gone", "type": "TXT",
+                    "content": "\"heritage=external-dns,external-dns/owner=prod\"", "ttl": 300},
+                {"id": "4", "name": "_externaldns.a-left", "type": "TXT",
+                    "content": "\"heritage=external-dns,external-dns/owner=staging\"", "ttl": 300}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "3"}), json!({}), 1)
+            .await;
+
+        let handler = njalla.handler(testing::config());
+
+        let reports = handler
+            .registry_orphans(OrphanQuery {
+                zone: None,
+                owner: Some("prod".to_string()),
+                apply: true,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reports[0].orphaned.len(), 2);
+        assert!(reports[0].unowned.is_empty());
+        assert_eq!(reports[0].removed, 1);
+        removed.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn registry_orphans_reports_a_zone_it_cannot_read() {
+        let mut njalla = MockNjalla::new().await;
+        njalla.fail(500).await;
+        let handler = njalla.handler(testing::config());
+        let reports = handler
+            .registry_orphans(OrphanQuery::default())
+            .await
+            .expect("a failing zone doesn't fail the run");
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].error.is_some());
+        assert!(reports[0].orphaned.is_empty());
+    }
+
+    #[tokio::test]
+    async fn registry_migration_adds_new_records_then_removes_old_ones() {
+        let mut njalla = MockNjalla::new().await;
+        let heritage = "\"heritage=external-dns,external-dns/owner=prod\"";
+        njalla
+            .records(json!([
+                {"id": "1", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 300},
+                {"id": "2", "name": "_externaldns.www", "type": "TXT", "content": heritage, "ttl": 300}
+            ]))
+            .await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"name": "_externaldns.a-www", "type": "TXT", "content": heritage}),
+                json!({"id": "3", "name": "_externaldns.a-www", "type": "TXT", "content": heritage, "ttl": 300}),
+                1,
+            )
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "2"}), json!({}), 1)
+            .await;
+
+        let handler = njalla.handler(testing::config());
+
+        let plan = handler
+            .migrate_registry(MigrateQuery {
+                zone: None,
+                remove_old: true,
+                apply: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(plan[0].plan.summary(), "1 to add, 1 to remove");
+        assert!(!plan[0].applied);
+        assert!(!added.matched_async().await, "planning must not write");
+
+        let migrated = handler
+            .migrate_registry(MigrateQuery {
+                zone: Some("example.com".to_string()),
+                remove_old: true,
+                apply: true,
+            })
+            .await
+            .unwrap();
+        assert!(migrated[0].applied);
+        assert!(migrated[0].error.is_none());
+        added.assert_async().await;
+        removed.assert_async().await;
+    }
+}
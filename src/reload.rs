@@ -0,0 +1,224 @@
+use crate::config::Config;
+use crate::njalla;
+use crate::tls::{self, SharedAcceptor};
+use crate::webhook::handlers::WebhookHandler;
+use crate::webhook::routes::SharedHandler;
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+/// Build a handler (and its Njalla client, which carries the auth header) for `config`.
+pub fn build_handler(config: Config) -> Result<WebhookHandler> {
+    let njalla_client = njalla::Client::new(
+        &config.njalla_api_token,
+        config.njalla_max_retries,
+        Duration::from_millis(config.njalla_retry_base_ms),
+    )?;
+    Ok(WebhookHandler::new(Arc::new(njalla_client), config))
+}
+
+/// Reloads the configuration and API token on SIGHUP or when a watched file changes, swapping
+/// a freshly built handler into the router. A config that fails to load or validate is logged
+/// and discarded; the previous handler stays active. When serving TLS, renewed certificates are
+/// swapped in the same way.
+pub struct Reloader {
+    config_path: Option<PathBuf>,
+    /// How the configuration is read; [`Config::load`] outside of tests.
+    load: fn(Option<&Path>) -> Result<Config>,
+    handler: SharedHandler,
+    acceptor: Option<SharedAcceptor>,
+    fingerprint: u64,
+}
+
+impl Reloader {
+    pub fn new(config_path: Option<PathBuf>, handler: SharedHandler) -> Self {
+        let mut reloader = Self {
+            config_path,
+            load: Config::load,
+            handler,
+            acceptor: None,
+            fingerprint: 0,
+        };
+        reloader.fingerprint = reloader.current_fingerprint();
+        reloader
+    }
+
+    /// Also rebuild the TLS acceptor of the listener on reload.
+    pub fn with_acceptor(mut self, acceptor: SharedAcceptor) -> Self {
+        self.acceptor = Some(acceptor);
+        self
+    }
+
+    /// Load, validate and swap in a new configuration.
+    pub fn reload(&mut self) -> Result<()> {
+        let config = (self.load)(self.config_path.as_deref())?;
+        let current = self.handler.load();
+        let old = current.config();
+        if old.webhook_host != config.webhook_host
+            || old.webhook_port != config.webhook_port
+            || old.listen != config.listen
+            || old.unix_socket_mode != config.unix_socket_mode
+        {
+            warn!("Listener address changes only take effect after a restart");
+        }
+        if old.tls.is_enabled() != config.tls.is_enabled() {
+            warn!("Enabling or disabling TLS only takes effect after a restart");
+        }
+        if old.journal_file != config.journal_file || old.history_file != config.history_file {
+            warn!("Journal and history file changes only take effect after a restart");
+        }
+        let token_changed = old.njalla_api_token != config.njalla_api_token;
+
+        // Build everything before swapping anything, so a bad certificate rejects the whole
+        // reload rather than leaving the config and certificate out of step.
+        let acceptor = match &self.acceptor {
+            Some(_) if config.tls.is_enabled() => Some(tls::build_acceptor(&config.tls)?),
+            _ => None,
+        };
+        let mut handler =
+            build_handler(config)?.with_drift_tracker(current.drift_tracker().clone());
+        if let Some(journal) = current.journal() {
+            handler = handler.with_journal(journal.clone());
+        }
+        if let Some(history) = current.history_store() {
+            handler = handler.with_history(history.clone());
+        }
+
+        if let (Some(shared), Some(acceptor)) = (&self.acceptor, acceptor) {
+            shared.store(Arc::new(acceptor));
+        }
+        self.handler.store(Arc::new(handler));
+        self.fingerprint = self.current_fingerprint();
+        info!(token_changed, "Configuration reloaded");
+        Ok(())
+    }
+
+    /// Run until the process exits: reload on SIGHUP, and poll watched files every
+    /// `reload_interval_seconds` (if non-zero).
+    pub fn spawn(mut self) -> Result<tokio::task::JoinHandle<()>> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            loop {
+                let interval = self.handler.load().config().reload_interval_seconds;
+                let poll = async {
+                    if interval == 0 {
+                        std::future::pending::<()>().await;
+                    }
+                    tokio::time::sleep(Duration::from_secs(interval)).await;
+                };
+
+                tokio::select! {
+                    _ = hangup.recv() => {
+                        info!("SIGHUP received, reloading configuration");
+                        self.try_reload();
+                    }
+                    _ = poll => {
+                        if self.current_fingerprint() != self.fingerprint {
+                            info!("Watched config file changed, reloading configuration");
+                            self.try_reload();
+                        }
+                    }
+                }
+            }
+        }))
+    }
+
+    fn try_reload(&mut self) {
+        if let Err(e) = self.reload() {
+            // Remember the bad contents so a broken file isn't re-reported every poll.
+            self.fingerprint = self.current_fingerprint();
+            error!(
+                "Rejected new configuration, keeping the previous one: {:#}",
+                e
+            );
+        }
+    }
+
+    /// Hash of the watched files' contents. Comparing contents rather than mtimes also catches
+    /// Kubernetes secret updates, which swap a symlink instead of rewriting the file.
+    fn current_fingerprint(&self) -> u64 {
+        let config = self.handler.load();
+        let mut hasher = DefaultHasher::new();
+        for path in config.config().watched_files(self.config_path.as_deref()) {
+            path.hash(&mut hasher);
+            std::fs::read(&path).ok().hash(&mut hasher);
+        }
+        hasher.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arc_swap::ArcSwap;
+    use std::io::Write;
+
+    fn write(file: &mut tempfile::NamedTempFile, contents: &str) {
+        file.as_file().set_len(0).unwrap();
+        let mut f = file.reopen().unwrap();
+        f.write_all(contents.as_bytes()).unwrap();
+    }
+
+    /// Read only the config file, so neither the process environment nor a `.env` file in the
+    /// working directory can leak into the tests.
+    fn load_without_env(path: Option<&Path>) -> Result<Config> {
+        Config::load_with_env(path, |_| None)
+    }
+
+    fn setup(token: &str) -> (tempfile::NamedTempFile, tempfile::NamedTempFile, Reloader) {
+        let mut token_file = tempfile::NamedTempFile::new().unwrap();
+        write(&mut token_file, token);
+        let mut config_file = tempfile::Builder::new().suffix(".toml").tempfile().unwrap();
+        write(
+            &mut config_file,
+            &format!(
+                "njalla_api_token_file = {:?}\ndomain_filter = [\"example.com\"]\n",
+                token_file.path()
+            ),
+        );
+
+        let config = load_without_env(Some(config_file.path())).unwrap();
+        let handler = Arc::new(ArcSwap::from_pointee(build_handler(config).unwrap()));
+        let mut reloader = Reloader::new(Some(config_file.path().to_path_buf()), handler);
+        reloader.load = load_without_env;
+        (config_file, token_file, reloader)
+    }
+
+    #[test]
+    fn reload_picks_up_rotated_token() {
+        let (_config_file, mut token_file, mut reloader) = setup("old-token");
+        let before = reloader.fingerprint;
+        let snapshot = reloader.handler.load_full();
+
+        write(&mut token_file, "new-token");
+        assert_ne!(reloader.current_fingerprint(), before);
+        reloader.reload().unwrap();
+
+        assert_eq!(
+            reloader.handler.load().config().njalla_api_token.expose(),
+            "new-token"
+        );
+        // A request that loaded the handler before the swap keeps the old snapshot.
+        assert_eq!(snapshot.config().njalla_api_token.expose(), "old-token");
+    }
+
+    #[test]
+    fn invalid_config_is_rejected_and_old_one_kept() {
+        let (mut config_file, _token_file, mut reloader) = setup("token");
+
+        write(&mut config_file, "webhook_port = \"nope\"\n");
+        assert!(reloader.reload().is_err());
+
+        let config = reloader.handler.load();
+        assert_eq!(config.config().njalla_api_token.expose(), "token");
+        assert_eq!(
+            config.config().domain_filter,
+            Some(vec!["example.com".to_string()])
+        );
+    }
+}
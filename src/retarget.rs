@@ -0,0 +1,473 @@
+//! Pointing records at a new target, across every managed zone.
+//!
+//! Renumbering a load balancer means finding every A, AAAA and CNAME record that points at the
+//! old address or host and changing it. A [`Mapping`] is either one exact target to another, or
+//! one network to another of the same size, in which case each address keeps its offset in the
+//! network. Matching records are edited in place with `edit-record`, so they keep their Njalla
+//! ids and TTLs. Runs are capped by `retarget_max_edits` and paced by `retarget_edit_delay_ms`.
+
+use crate::error::{self, Error};
+use crate::journal::Operation;
+use crate::njalla::{Budget, DnsRecord};
+use crate::planner::Plan;
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::{RetargetQuery, RetargetResponse};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// An address block, `<address>/<prefix length>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Network {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Network {
+    fn parse(s: &str) -> Option<Self> {
+        let (addr, prefix) = s.split_once('/')?;
+        let addr: IpAddr = addr.parse().ok()?;
+        let prefix: u8 = prefix.parse().ok()?;
+        (prefix <= Self::bits(addr)).then_some(Self { addr, prefix })
+    }
+
+    fn bits(addr: IpAddr) -> u8 {
+        match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        }
+    }
+
+    /// Host bits set, network bits clear.
+    fn host_mask(self) -> u128 {
+        let host_bits = u32::from(Self::bits(self.addr) - self.prefix);
+        1u128.checked_shl(host_bits).map_or(u128::MAX, |v| v - 1)
+    }
+
+    /// Offset of `addr` in this network, if it lies in it.
+    fn offset(self, addr: IpAddr) -> Option<u128> {
+        let (net, addr) = match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => {
+                (u128::from(net.to_bits()), u128::from(addr.to_bits()))
+            }
+            (IpAddr::V6(net), IpAddr::V6(addr)) => (net.to_bits(), addr.to_bits()),
+            _ => return None,
+        };
+        let mask = self.host_mask();
+        (net & !mask == addr & !mask).then_some(addr & mask)
+    }
+
+    /// The address at `offset` in this network.
+    fn at(self, offset: u128) -> IpAddr {
+        let mask = self.host_mask();
+        match self.addr {
+            IpAddr::V4(net) => {
+                let bits = (u128::from(net.to_bits()) & !mask) | offset;
+                IpAddr::V4(Ipv4Addr::from_bits(bits as u32))
+            }
+            IpAddr::V6(net) => IpAddr::V6(Ipv6Addr::from_bits((net.to_bits() & !mask) | offset)),
+        }
+    }
+}
+
+impl fmt::Display for Network {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Which targets move where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mapping {
+    /// One address to another (A and AAAA records).
+    Address { from: IpAddr, to: IpAddr },
+    /// One host name to another (CNAME records).
+    Host { from: String, to: String },
+    /// Every address in `from` to the same offset in `to`.
+    Network { from: Network, to: Network },
+}
+
+impl Mapping {
+    /// Parse `from` and `to`: two addresses of the same family, two host names, or two
+    /// networks of the same family and size.
+    pub fn parse(from: &str, to: &str) -> Result<Self, String> {
+        let (from, to) = (from.trim(), to.trim());
+        if from.is_empty() || to.is_empty() {
+            return Err("both from and to are required".to_string());
+        }
+        if from.contains('/') || to.contains('/') {
+            let (Some(from_net), Some(to_net)) = (Network::parse(from), Network::parse(to)) else {
+                return Err(format!(
+                    "'{from}' -> '{to}': expected two networks like 192.0.2.0/24"
+                ));
+            };
+            if Network::bits(from_net.addr) != Network::bits(to_net.addr)
+                || from_net.prefix != to_net.prefix
+            {
+                return Err(format!(
+                    "'{from}' -> '{to}': networks must be of the same family and size"
+                ));
+            }
+            return Ok(Self::Network {
+                from: from_net,
+                to: to_net,
+            });
+        }
+        match (from.parse::<IpAddr>(), to.parse::<IpAddr>()) {
+            (Ok(from), Ok(to)) if from.is_ipv4() == to.is_ipv4() => Ok(Self::Address { from, to }),
+            (Ok(_), Ok(_)) => Err(format!(
+                "'{from}' -> '{to}': addresses must be of the same family"
+            )),
+            (Err(_), Err(_)) => Ok(Self::Host {
+                from: host(from),
+                to: to.to_string(),
+            }),
+            _ => Err(format!(
+                "'{from}' -> '{to}': can't map between an address and a host name"
+            )),
+        }
+    }
+
+    /// The new content of `record`, if the mapping moves it.
+    pub fn retarget(&self, record: &DnsRecord) -> Option<String> {
+        match (self, record.record_type.to_ascii_uppercase().as_str()) {
+            (Self::Address { from, to }, "A" | "AAAA") => {
+                (record.content.parse::<IpAddr>().ok()? == *from).then(|| to.to_string())
+            }
+            (Self::Network { from, to }, "A" | "AAAA") => {
+                let offset = from.offset(record.content.parse().ok()?)?;
+                Some(to.at(offset).to_string())
+            }
+            (Self::Host { from, to }, "CNAME") => {
+                if host(&record.content) != *from {
+                    return None;
+                }
+                // Keep the record's own style of (not) writing the root dot.
+                let to = to.strip_suffix('.').unwrap_or(to);
+                Some(if record.content.ends_with('.') {
+                    format!("{to}.")
+                } else {
+                    to.to_string()
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Address { from, to } => write!(f, "{from} -> {to}"),
+            Self::Host { from, to } => write!(f, "{from} -> {to}"),
+            Self::Network { from, to } => write!(f, "{from} -> {to}"),
+        }
+    }
+}
+
+/// A host name compared case-insensitively and without the root dot.
+fn host(name: &str) -> String {
+    name.strip_suffix('.').unwrap_or(name).to_ascii_lowercase()
+}
+
+/// Plan the edits `mapping` makes to `records` of `zone`. Records whose type `allowed` rejects
+/// end up in `skipped`.
+pub fn plan(
+    zone: &str,
+    records: &[DnsRecord],
+    mapping: &Mapping,
+    allowed: impl Fn(&str) -> bool,
+) -> Plan {
+    let mut plan = Plan {
+        zone: zone.to_string(),
+        ..Plan::default()
+    };
+    for record in records {
+        let Some(content) = mapping.retarget(record) else {
+            continue;
+        };
+        if content == record.content {
+            continue;
+        }
+        let op = Operation::Edit {
+            domain: zone.to_string(),
+            record: record.clone(),
+            content,
+        };
+        if allowed(&record.record_type) {
+            plan.operations.push(op);
+        } else {
+            plan.skipped
+                .push(format!("{op}: record type not allowed in zone"));
+        }
+    }
+    plan
+}
+
+// The retarget admin endpoint.
+impl WebhookHandler {
+    /// Plan pointing every A, AAAA and CNAME record at the target of `query.from` to
+    /// `query.to` instead, in one zone or every managed zone. With `apply`, edit the records in
+    /// place as one batch, one call at a time with `retarget_edit_delay_ms` between calls. A
+    /// plan of more than `retarget_max_edits` edits, or one missing a zone that couldn't be
+    /// read, isn't applied at all.
+    pub async fn retarget(&self, query: RetargetQuery) -> error::Result<RetargetResponse> {
+        let mapping = Mapping::parse(&query.from, &query.to).map_err(Error::InvalidRequest)?;
+        // Retargets aren't bound by external-dns's request timeout.
+        let batch = Batch::new(Budget::unlimited(), None);
+        let zones = self
+            .selected_zones(query.zone.as_deref(), &batch.budget)
+            .await?;
+        let apply = query.apply && !self.config.dry_run;
+
+        let mut plans = Vec::new();
+        let mut last_error = None;
+        let mut read = 0;
+        for zone in &zones {
+            let records = match self.njalla_client.list_records(zone, &batch.budget).await {
+                Ok(records) => records,
+                Err(e) if apply => return Err(e),
+                Err(e) => {
+                    warn!("Failed to read zone {} for retarget: {}", zone, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            read += 1;
+            let plan = plan(zone, &records, &mapping, |record_type| {
+                self.config.is_record_type_allowed(zone, record_type)
+            });
+            if !plan.is_empty() || !plan.skipped.is_empty() {
+                info!("Retarget {} in {}: {}", mapping, zone, plan.summary());
+                plans.push(plan);
+            }
+        }
+        if let Some(e) = last_error.filter(|_| read == 0) {
+            return Err(e);
+        }
+
+        let mut response = RetargetResponse {
+            mapping: mapping.to_string(),
+            applied: false,
+            batch: None,
+            edited: 0,
+            error: None,
+            plans,
+        };
+        let ops: Vec<&Operation> = response.plans.iter().flat_map(|p| &p.operations).collect();
+        if !apply || ops.is_empty() {
+            return Ok(response);
+        }
+        if ops.len() > self.config.retarget_max_edits {
+            return Err(Error::InvalidRequest(format!(
+                "{} edits exceed retarget_max_edits ({}); narrow the run with a zone",
+                ops.len(),
+                self.config.retarget_max_edits
+            )));
+        }
+
+        let delay = Duration::from_millis(self.config.retarget_edit_delay_ms);
+        for (i, op) in ops.iter().enumerate() {
+            if i > 0 {
+                tokio::time::sleep(delay).await;
+            }
+            let description = format!("Retarget {mapping}");
+            if let Err(e) = self
+                .run_change(&description, std::slice::from_ref(*op), &batch)
+                .await
+            {
+                error!("Retarget {} stopped at {}: {}", mapping, op, e);
+                response.error = Some(e.to_string());
+                break;
+            }
+            response.edited += 1;
+        }
+        response.applied = response.edited > 0;
+        response.batch = response.applied.then_some(batch.id);
+        info!(
+            "Retarget {}: {} of {} record(s) edited",
+            mapping,
+            response.edited,
+            ops.len()
+        );
+        Ok(response)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::history::{Action, History, HistoryFilter};
+    use crate::webhook::testing::{self, MockNjalla};
+    use axum::http::StatusCode;
+    use axum::Json;
+    use serde_json::json;
+    use std::sync::Arc;
+
+    fn record(id: &str, record_type: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: "www".to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn networks_keep_each_address_offset() {
+        let mapping = Mapping::parse("192.0.2.0/24", "198.51.100.0/24").unwrap();
+        let records = [
+            record("1", "A", "192.0.2.17"),
+            record("2", "A", "192.0.3.17"),
+            record("3", "AAAA", "2001:db8::17"),
+            record("4", "TXT", "192.0.2.17"),
+        ];
+        let plan = plan("example.com", &records, &mapping, |_| true);
+        assert_eq!(plan.summary(), "0 to add, 1 to edit, 0 to remove");
+        assert!(matches!(
+            &plan.operations[0],
+            Operation::Edit { record, content, .. } if record.id == "1" && content == "198.51.100.17"
+        ));
+
+        let v6 = Mapping::parse("2001:db8:1::/48", "2001:db8:2::/48").unwrap();
+        assert_eq!(
+            v6.retarget(&record("5", "AAAA", "2001:db8:1:ff::1"))
+                .as_deref(),
+            Some("2001:db8:2:ff::1")
+        );
+        assert_eq!(
+            Mapping::parse("0.0.0.0/0", "0.0.0.0/0")
+                .unwrap()
+                .retarget(&record("6", "A", "203.0.113.9"))
+                .as_deref(),
+            Some("203.0.113.9")
+        );
+    }
+
+    #[test]
+    fn exact_targets_match_addresses_and_host_names() {
+        let address = Mapping::parse("192.0.2.1", "192.0.2.2").unwrap();
+        assert_eq!(
+            address.retarget(&record("1", "A", "192.0.2.1")).as_deref(),
+            Some("192.0.2.2")
+        );
+        assert_eq!(address.retarget(&record("2", "A", "192.0.2.10")), None);
+
+        let host = Mapping::parse("lb-old.example.net", "lb-new.example.net").unwrap();
+        assert_eq!(
+            host.retarget(&record("3", "CNAME", "LB-OLD.example.net."))
+                .as_deref(),
+            Some("lb-new.example.net.")
+        );
+        assert_eq!(
+            host.retarget(&record("4", "TXT", "lb-old.example.net")),
+            None
+        );
+
+        let plan = plan(
+            "example.com",
+            &[record("5", "CNAME", "lb-old.example.net")],
+            &host,
+            |t| t != "CNAME",
+        );
+        assert!(plan.is_empty());
+        assert_eq!(plan.skipped.len(), 1);
+    }
+
+    #[test]
+    fn mismatched_mappings_are_rejected() {
+        for (from, to) in [
+            ("192.0.2.0/24", "198.51.100.0/25"),
+            ("192.0.2.0/24", "2001:db8::/24"),
+            ("192.0.2.0/33", "198.51.100.0/33"),
+            ("192.0.2.1", "2001:db8::1"),
+            ("192.0.2.1", "lb.example.net"),
+            ("192.0.2.1", ""),
+        ] {
+            assert!(Mapping::parse(from, to).is_err(), "{from} -> {to}");
+        }
+    }
+
+    #[tokio::test]
+    async fn retarget_edits_matching_records_in_place() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "www", "type": "A", "content": "192.0.2.10", "ttl": 300},
+                {"id": "2", "name": "api", "type": "A", "content": "192.0.2.11", "ttl": 60},
+                {"id": "3", "name": "mail", "type": "A", "content": "203.0.113.1", "ttl": 300},
+                {"id": "4", "name": "txt", "type": "TXT", "content": "192.0.2.10", "ttl": 300}
+            ]))
+            .await;
+        let edited = njalla
+            .expect(
+                "edit-record",
+                json!({"id": "2", "content": "198.51.100.11", "ttl": 60}),
+                json!({"id": "2", "name": "api", "type": "A", "content": "198.51.100.11", "ttl": 60}),
+                1,
+            )
+            .await;
+        njalla
+            .answer(
+                "edit-record",
+                json!({"id": "1"}),
+                json!({"id": "1", "name": "www", "type": "A", "content": "198.51.100.10", "ttl": 300}),
+            )
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let history = Arc::new(History::open(&dir.path().join("history.jsonl")).unwrap());
+        let config = Config {
+            retarget_max_edits: 1,
+            retarget_edit_delay_ms: 0,
+            ..testing::config()
+        };
+        let mut handler = njalla.handler(config).with_history(history);
+        let query = |apply| RetargetQuery {
+            from: "192.0.2.0/24".to_string(),
+            to: "198.51.100.0/24".to_string(),
+            zone: None,
+            apply,
+        };
+
+        let report = handler.retarget(query(false)).await.unwrap();
+        assert_eq!(report.mapping, "192.0.2.0/24 -> 198.51.100.0/24");
+        assert_eq!(report.plans.len(), 1);
+        assert_eq!(
+            report.plans[0].summary(),
+            "0 to add, 2 to edit, 0 to remove"
+        );
+        assert!(!edited.matched_async().await, "a report must not write");
+
+        let err = handler.retarget(query(true)).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+        assert!(err.to_string().contains("retarget_max_edits"), "{err}");
+
+        handler.config.retarget_max_edits = 2;
+        let applied = handler.retarget(query(true)).await.unwrap();
+        assert!(applied.applied);
+        assert_eq!(applied.edited, 2);
+        edited.assert_async().await;
+
+        let batch = applied.batch.unwrap();
+        let Json(entries) = handler
+            .history(HistoryFilter {
+                batch: Some(batch),
+                ..HistoryFilter::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.action == Action::Edit));
+        assert_eq!(entries[0].before.as_ref().unwrap().content, "192.0.2.11");
+
+        // The mocked zone still shows the old addresses, so there is nothing to revert.
+        let Json(undo) = handler.undo_batch(batch).await.unwrap();
+        assert!(undo.applied.is_empty());
+        assert_eq!(undo.skipped.len(), 2);
+    }
+}
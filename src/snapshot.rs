@@ -0,0 +1,449 @@
+//! Zone snapshots on disk.
+//!
+//! Each snapshot of a zone is stored twice under `<dir>/<zone>/`: `<id>.json` holds the raw
+//! Njalla records (what a restore reads) and `<id>.zone` the same records as a BIND master file.
+//! Ids are UTC timestamps (`20240501T120000Z`), so they sort chronologically. A background task
+//! takes a snapshot of every managed zone each `snapshot_interval_seconds` and keeps the newest
+//! `snapshot_retention` per zone.
+
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::njalla::{Budget, DnsRecord};
+use crate::periodic::{spawn_periodic, Start};
+use crate::planner;
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::routes::SharedHandler;
+use crate::webhook::types::{ApplyQuery, RestoreResponse, SnapshotQuery};
+use crate::zonefile;
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tracing::{debug, error, info};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub zone: String,
+    pub taken_at: DateTime<Utc>,
+    pub records: Vec<DnsRecord>,
+}
+
+impl Snapshot {
+    pub fn new(zone: &str, records: Vec<DnsRecord>) -> Self {
+        Self {
+            zone: zone.to_string(),
+            taken_at: Utc::now(),
+            records,
+        }
+    }
+
+    pub fn id(&self) -> String {
+        self.taken_at.format("%Y%m%dT%H%M%SZ").to_string()
+    }
+}
+
+/// A stored snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct SnapshotInfo {
+    pub zone: String,
+    pub id: String,
+}
+
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+impl SnapshotStore {
+    pub fn new(dir: &Path) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    /// Write both files of `snapshot`, each through a temporary file so a reader never sees
+    /// half a snapshot.
+    pub fn save(&self, snapshot: &Snapshot) -> Result<SnapshotInfo> {
+        let zone_dir = self.zone_dir(&snapshot.zone)?;
+        std::fs::create_dir_all(&zone_dir).map_err(|e| self.io_error(e))?;
+        let id = snapshot.id();
+        let comment = format!(
+            "Njalla zone {} as of {}",
+            snapshot.zone,
+            snapshot.taken_at.to_rfc3339()
+        );
+        let files = [
+            ("json", serde_json::to_string_pretty(snapshot)?),
+            (
+                "zone",
+                zonefile::render(&snapshot.zone, &snapshot.records, &comment),
+            ),
+        ];
+        for (extension, contents) in files {
+            let path = zone_dir.join(format!("{id}.{extension}"));
+            let tmp = zone_dir.join(format!(".{id}.{extension}.tmp"));
+            std::fs::write(&tmp, contents)
+                .and_then(|()| std::fs::rename(&tmp, &path))
+                .map_err(|e| self.io_error(e))?;
+        }
+        Ok(SnapshotInfo {
+            zone: snapshot.zone.clone(),
+            id,
+        })
+    }
+
+    /// Stored snapshots, of one zone or all, newest first.
+    pub fn list(&self, zone: Option<&str>) -> Result<Vec<SnapshotInfo>> {
+        let zones = match zone {
+            Some(zone) => vec![zone.to_string()],
+            None => match std::fs::read_dir(&self.dir) {
+                Ok(entries) => entries
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().is_dir())
+                    .filter_map(|e| e.file_name().into_string().ok())
+                    .collect(),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => return Err(self.io_error(e)),
+            },
+        };
+
+        let mut snapshots = Vec::new();
+        for zone in zones {
+            let entries = match std::fs::read_dir(self.zone_dir(&zone)?) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(self.io_error(e)),
+            };
+            snapshots.extend(
+                entries
+                    .filter_map(|e| e.ok())
+                    .filter_map(|e| e.file_name().into_string().ok())
+                    .filter_map(|name| name.strip_suffix(".json").map(str::to_string))
+                    .filter(|id| !id.starts_with('.'))
+                    .map(|id| SnapshotInfo {
+                        zone: zone.clone(),
+                        id,
+                    }),
+            );
+        }
+        snapshots.sort_by(|a, b| b.id.cmp(&a.id).then_with(|| a.zone.cmp(&b.zone)));
+        Ok(snapshots)
+    }
+
+    pub fn load(&self, zone: &str, id: &str) -> Result<Snapshot> {
+        if !is_safe_component(id) {
+            return Err(Error::InvalidRequest(format!("invalid snapshot id '{id}'")));
+        }
+        let path = self.zone_dir(zone)?.join(format!("{id}.json"));
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::NotFound(format!("no snapshot {id} of zone {zone}")))
+            }
+            Err(e) => return Err(self.io_error(e)),
+        };
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Delete all but the newest `keep` snapshots of `zone`; 0 keeps everything. Returns how
+    /// many were deleted.
+    pub fn prune(&self, zone: &str, keep: usize) -> Result<usize> {
+        if keep == 0 {
+            return Ok(0);
+        }
+        let zone_dir = self.zone_dir(zone)?;
+        let expired = self.list(Some(zone))?.into_iter().skip(keep);
+        let mut deleted = 0;
+        for snapshot in expired {
+            for extension in ["json", "zone"] {
+                let path = zone_dir.join(format!("{}.{extension}", snapshot.id));
+                match std::fs::remove_file(&path) {
+                    Ok(()) => {}
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(self.io_error(e)),
+                }
+            }
+            deleted += 1;
+        }
+        Ok(deleted)
+    }
+
+    /// The directory of `zone`, refusing names that could escape the snapshot directory.
+    fn zone_dir(&self, zone: &str) -> Result<PathBuf> {
+        if is_safe_component(zone) {
+            Ok(self.dir.join(zone))
+        } else {
+            Err(Error::InvalidRequest(format!("invalid zone name '{zone}'")))
+        }
+    }
+
+    fn io_error(&self, e: std::io::Error) -> Error {
+        Error::Internal(format!("snapshots {}: {e}", self.dir.display()))
+    }
+}
+
+fn is_safe_component(s: &str) -> bool {
+    !s.is_empty()
+        && !s.starts_with('.')
+        && s.chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// Snapshot every managed zone on the configured interval. Settings are read from the current
+/// handler each round, so reloads can enable, disable or retime snapshots.
+pub fn spawn(handler: SharedHandler) -> tokio::task::JoinHandle<()> {
+    let interval = |config: &Config| {
+        if config.snapshot_dir.is_some() {
+            config.snapshot_interval_seconds
+        } else {
+            0
+        }
+    };
+    spawn_periodic(handler, Start::Now, interval, |handler| async move {
+        match handler.take_snapshots().await {
+            Ok(taken) => info!("Took {} zone snapshot(s)", taken.len()),
+            Err(e) => error!("Zone snapshots failed: {}", e),
+        }
+    })
+}
+
+// The snapshot admin endpoints.
+impl WebhookHandler {
+    /// Snapshot every managed zone now and apply the retention limit. A zone that can't be
+    /// read is logged and skipped; the call fails only if no zone could be snapshotted.
+    pub async fn take_snapshots(&self) -> Result<Vec<SnapshotInfo>> {
+        let store = self.snapshot_store()?;
+        let budget = Budget::unlimited();
+        let mut taken = Vec::new();
+        let mut last_error = None;
+        for zone in self.managed_zones(&budget).await? {
+            let result = match self.njalla_client.list_records(&zone, &budget).await {
+                Ok(records) => store.save(&Snapshot::new(&zone, records)),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(info) => {
+                    let pruned = store.prune(&zone, self.config.snapshot_retention)?;
+                    debug!(
+                        "Snapshot {} of {} saved, {} expired pruned",
+                        info.id, zone, pruned
+                    );
+                    taken.push(info);
+                }
+                Err(e) => {
+                    error!("Failed to snapshot zone {}: {}", zone, e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        match last_error {
+            Some(e) if taken.is_empty() => Err(e),
+            _ => Ok(taken),
+        }
+    }
+
+    pub async fn list_snapshots(&self, query: SnapshotQuery) -> Result<Json<Vec<SnapshotInfo>>> {
+        let zone = query.zone.as_deref().map(Config::normalize_domain);
+        Ok(Json(self.snapshot_store()?.list(zone.as_deref())?))
+    }
+
+    /// Diff a snapshot against the live zone. With `apply`, carry the plan out as one batch;
+    /// otherwise only report it.
+    pub async fn restore_snapshot(
+        &self,
+        zone: &str,
+        id: &str,
+        query: ApplyQuery,
+    ) -> Result<Json<RestoreResponse>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let snapshot = self.snapshot_store()?.load(&zone, id)?;
+
+        // Restores aren't bound by external-dns's request timeout.
+        let batch = Batch::new(Budget::unlimited(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        let plan = planner::diff(
+            &zone,
+            &snapshot.records,
+            &live,
+            |_| true,
+            |record_type| self.config.is_record_type_allowed(&zone, record_type),
+        );
+        info!(
+            "Restore of {} from snapshot {}: {}",
+            zone,
+            id,
+            plan.summary()
+        );
+
+        let applied = query.apply && !self.config.dry_run && !plan.is_empty();
+        if query.apply {
+            self.run_change(
+                &format!("Restore {zone} from snapshot {id}"),
+                &plan.operations,
+                &batch,
+            )
+            .await?;
+        }
+
+        Ok(Json(RestoreResponse {
+            snapshot: id.to_string(),
+            applied,
+            batch: applied.then_some(batch.id),
+            plan,
+        }))
+    }
+
+    fn snapshot_store(&self) -> Result<SnapshotStore> {
+        self.config
+            .snapshot_dir
+            .as_deref()
+            .map(SnapshotStore::new)
+            .ok_or_else(|| {
+                Error::NotFound("snapshots are not enabled (set SNAPSHOT_DIR)".to_string())
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use serde_json::json;
+
+    fn snapshot(zone: &str, taken_at: &str) -> Snapshot {
+        Snapshot {
+            zone: zone.to_string(),
+            taken_at: taken_at.parse().unwrap(),
+            records: vec![DnsRecord {
+                id: "1".to_string(),
+                name: "www".to_string(),
+                record_type: "A".to_string(),
+                content: "192.0.2.1".to_string(),
+                ttl: Some(300),
+                priority: None,
+            }],
+        }
+    }
+
+    #[test]
+    fn saves_lists_loads_and_prunes() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        for t in [
+            "2024-05-01T10:00:00Z",
+            "2024-05-01T11:00:00Z",
+            "2024-05-01T12:00:00Z",
+        ] {
+            store.save(&snapshot("example.com", t)).unwrap();
+        }
+        store
+            .save(&snapshot("example.org", "2024-05-01T10:30:00Z"))
+            .unwrap();
+
+        let ids: Vec<String> = store
+            .list(Some("example.com"))
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect();
+        assert_eq!(
+            ids,
+            ["20240501T120000Z", "20240501T110000Z", "20240501T100000Z"]
+        );
+        assert_eq!(store.list(None).unwrap().len(), 4);
+
+        let zone_file = dir.path().join("example.com/20240501T120000Z.zone");
+        assert!(std::fs::read_to_string(zone_file)
+            .unwrap()
+            .contains("www\t300\tIN\tA\t192.0.2.1"));
+        let loaded = store.load("example.com", "20240501T110000Z").unwrap();
+        assert_eq!(loaded, snapshot("example.com", "2024-05-01T11:00:00Z"));
+
+        assert_eq!(store.prune("example.com", 1).unwrap(), 2);
+        assert_eq!(store.list(Some("example.com")).unwrap().len(), 1);
+        assert_eq!(store.list(Some("example.org")).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = SnapshotStore::new(dir.path());
+        assert!(matches!(
+            store.load("../etc", "x"),
+            Err(Error::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            store.load("example.com", "../../passwd"),
+            Err(Error::InvalidRequest(_))
+        ));
+        assert!(matches!(
+            store.load("example.com", "20240501T120000Z"),
+            Err(Error::NotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn restore_plans_first_and_applies_on_request() {
+        let mut njalla = MockNjalla::new().await;
+        // The zone lost its www record since the snapshot.
+        njalla
+            .records(json!([
+                {"id": "3", "name": "mail", "type": "A", "content": "192.0.2.3", "ttl": 300}
+            ]))
+            .await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"name": "www", "content": "192.0.2.1", "ttl": 300}),
+                json!({"id": "9", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 300}),
+                1,
+            )
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let record = |id: &str, name: &str, content: &str| DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority: None,
+        };
+        let info = SnapshotStore::new(dir.path())
+            .save(&Snapshot::new(
+                "example.com",
+                vec![
+                    record("1", "www", "192.0.2.1"),
+                    record("3", "mail", "192.0.2.3"),
+                ],
+            ))
+            .unwrap();
+
+        let config = Config {
+            snapshot_dir: Some(dir.path().to_path_buf()),
+            ..testing::config()
+        };
+        let handler = njalla.handler(config);
+
+        let Json(plan) = handler
+            .restore_snapshot("example.com", &info.id, ApplyQuery { apply: false })
+            .await
+            .unwrap();
+        assert!(!plan.applied);
+        assert_eq!(plan.plan.summary(), "1 to add, 0 to remove");
+        assert!(!added.matched_async().await, "planning must not write");
+
+        let Json(restored) = handler
+            .restore_snapshot("example.com", &info.id, ApplyQuery { apply: true })
+            .await
+            .unwrap();
+        assert!(restored.applied);
+        assert!(restored.batch.is_some());
+        added.assert_async().await;
+    }
+}
@@ -0,0 +1,392 @@
+//! Declarative sync of records no Kubernetes resource owns: mail, verification, legacy hosts.
+//!
+//! A desired-state file (YAML or TOML, by extension) names one zone, the name patterns this
+//! sync manages and the records those names should have. The zone is diffed with the same
+//! planner as `POST /records`, and only live records whose name matches a pattern may be
+//! removed. Names external-dns holds ownership records for, and the ownership records
+//! themselves, are never touched even when a pattern matches them.
+
+use crate::config::{Config, KNOWN_RECORD_TYPES};
+use crate::error::{self, Error};
+use crate::njalla::DnsRecord;
+use crate::planner::{self, Plan};
+use crate::registry;
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::SyncResponse;
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+use tracing::info;
+
+/// The desired state of one zone.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredState {
+    pub zone: String,
+    /// Name patterns this sync manages, relative to the zone: `@` for the apex, `*` for any
+    /// run of characters and `?` for one, e.g. `_dmarc`, `mail*`, `legacy-?`.
+    pub manage: Vec<String>,
+    #[serde(default)]
+    pub records: Vec<DesiredRecord>,
+}
+
+/// One record of a [`DesiredState`].
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DesiredRecord {
+    /// Relative to the zone; `@` or empty for the apex.
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub record_type: String,
+    pub content: String,
+    /// Defaults to the zone's `default_ttl`, then 3600.
+    pub ttl: Option<u32>,
+    pub priority: Option<u32>,
+}
+
+impl DesiredState {
+    /// Read and check a desired-state file. `.yaml` and `.yml` files are YAML, `.toml` files
+    /// TOML.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("sync: cannot read {}", path.display()))?;
+        let state: Self = match path.extension().and_then(|e| e.to_str()) {
+            Some("yaml" | "yml") => serde_yaml_ng::from_str(&contents)
+                .with_context(|| format!("sync: {} is not valid YAML", path.display()))?,
+            Some("toml") => toml::from_str(&contents)
+                .with_context(|| format!("sync: {} is not valid TOML", path.display()))?,
+            _ => bail!(
+                "sync: {}: expected a .yaml, .yml or .toml file",
+                path.display()
+            ),
+        };
+        state
+            .validate()
+            .with_context(|| format!("sync: {}", path.display()))?;
+        Ok(state)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.zone.trim().is_empty() {
+            bail!("zone: must not be empty");
+        }
+        if self.manage.is_empty() {
+            bail!("manage: at least one name pattern is required");
+        }
+        for record in &self.records {
+            if !KNOWN_RECORD_TYPES.contains(&record.record_type.to_ascii_uppercase().as_str()) {
+                bail!("records: unknown record type '{}'", record.record_type);
+            }
+            if record.ttl == Some(0) {
+                bail!("records: ttl must be greater than 0");
+            }
+            if !self.manages(&record.name) {
+                bail!(
+                    "records: '{}' doesn't match any manage pattern",
+                    display_name(&record.name)
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the record name `name` (relative, empty or `@` for the apex) is in scope.
+    pub fn manages(&self, name: &str) -> bool {
+        let name = display_name(name);
+        self.manage.iter().any(|pattern| glob(pattern, name))
+    }
+
+    /// The desired records, with relative names. Records without a TTL get `default_ttl`.
+    pub fn records(&self, default_ttl: u32) -> Vec<DnsRecord> {
+        self.records
+            .iter()
+            .map(|record| DnsRecord {
+                id: String::new(),
+                name: planner::relative_name(&record.name).to_string(),
+                record_type: record.record_type.to_ascii_uppercase(),
+                content: record.content.clone(),
+                ttl: Some(record.ttl.unwrap_or(default_ttl)),
+                priority: record.priority,
+            })
+            .collect()
+    }
+}
+
+/// `@` for the apex, the name itself otherwise.
+fn display_name(name: &str) -> &str {
+    match planner::relative_name(name) {
+        "" => "@",
+        name => name,
+    }
+}
+
+/// Case-insensitive match of `name` against `pattern` with `*` and `?` wildcards.
+fn glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_ascii_lowercase().chars().collect();
+    let name: Vec<char> = name.to_ascii_lowercase().chars().collect();
+    let (mut p, mut n) = (0, 0);
+    // Where the last `*` was, and how much of `name` it has swallowed so far.
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Fully qualified names of `zone` that external-dns holds ownership records for.
+fn external_dns_names(zone: &str, live: &[DnsRecord], prefix: &str) -> BTreeSet<String> {
+    live.iter()
+        .filter(|r| registry::is_ownership_record(r))
+        .flat_map(|r| registry::owned_by(&planner::fqdn(&r.name, zone), zone, prefix))
+        .map(|owned| owned.dns_name)
+        .collect()
+}
+
+/// Plan the changes that make the managed names of `state`'s zone match it. Records whose type
+/// `allowed` rejects, and desired records at names external-dns owns, end up in `skipped`.
+pub fn plan(
+    state: &DesiredState,
+    live: &[DnsRecord],
+    prefix: &str,
+    default_ttl: u32,
+    allowed: impl Fn(&str) -> bool,
+) -> Plan {
+    let zone = state.zone.as_str();
+    let owned = external_dns_names(zone, live, prefix);
+    let is_owned = |name: &str| owned.contains(&planner::fqdn(name, zone).to_ascii_lowercase());
+
+    let (desired, taken): (Vec<DnsRecord>, Vec<DnsRecord>) = state
+        .records(default_ttl)
+        .into_iter()
+        .partition(|r| !is_owned(&r.name));
+    let mut plan = planner::diff(
+        zone,
+        &desired,
+        live,
+        |r| state.manages(&r.name) && !registry::is_ownership_record(r) && !is_owned(&r.name),
+        allowed,
+    );
+    plan.skipped.extend(taken.iter().map(|r| {
+        format!(
+            "{} {} -> {}: name owned by external-dns",
+            r.record_type,
+            planner::fqdn(&r.name, zone),
+            r.content
+        )
+    }));
+    plan
+}
+
+// The sync admin endpoint.
+impl WebhookHandler {
+    /// Plan making the managed names of `state`'s zone match it. With `apply`, carry the plan
+    /// out as one batch.
+    pub async fn sync(&self, state: &DesiredState, apply: bool) -> error::Result<SyncResponse> {
+        let zone = Config::normalize_domain(&state.zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let state = DesiredState {
+            zone: zone.clone(),
+            ..state.clone()
+        };
+
+        let batch = Batch::new(self.batch_budget(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        let plan = plan(
+            &state,
+            &live,
+            &self.config.registry_txt_prefix,
+            self.default_ttl(&zone),
+            |record_type| self.config.is_record_type_allowed(&zone, record_type),
+        );
+        info!("Sync of {}: {}", zone, plan.summary());
+
+        let mut applied = false;
+        if apply && !plan.is_empty() {
+            self.run_change(&format!("Sync {zone}"), &plan.operations, &batch)
+                .await?;
+            applied = !self.config.dry_run;
+        }
+        Ok(SyncResponse {
+            applied,
+            batch: applied.then_some(batch.id),
+            plan,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    fn record(id: &str, name: &str, record_type: &str, content: &str) -> DnsRecord {
+        DnsRecord {
+            id: id.to_string(),
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: Some(3600),
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn glob_patterns() {
+        assert!(glob("@", "@"));
+        assert!(glob("mail*", "mail"));
+        assert!(glob("mail*", "MAIL-2"));
+        assert!(glob("*.legacy", "a.b.legacy"));
+        assert!(glob("legacy-?", "legacy-1"));
+        assert!(!glob("legacy-?", "legacy-10"));
+        assert!(!glob("mail", "mail2"));
+        assert!(glob("*", ""));
+    }
+
+    #[test]
+    fn loads_yaml_and_toml() {
+        let dir = tempfile::tempdir().unwrap();
+        let yaml = dir.path().join("example.com.yaml");
+        std::fs::write(
+            &yaml,
+            "zone: example.com\nmanage: ['@', _dmarc]\nrecords:\n  - {name: _dmarc, type: txt, content: v=DMARC1}\n",
+        )
+        .unwrap();
+        let state = DesiredState::load(&yaml).unwrap();
+        assert_eq!(state.records(300)[0].record_type, "TXT");
+
+        let toml = dir.path().join("example.org.toml");
+        std::fs::write(
+            &toml,
+            "zone = \"example.org\"\nmanage = [\"www\"]\n[[records]]\nname = \"mail\"\ntype = \"A\"\ncontent = \"192.0.2.1\"\n",
+        )
+        .unwrap();
+        let err = DesiredState::load(&toml).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("'mail' doesn't match"),
+            "{err:#}"
+        );
+    }
+
+    #[test]
+    fn leaves_unmanaged_and_external_dns_names_alone() {
+        let state = DesiredState {
+            zone: "example.com".to_string(),
+            manage: vec!["@".to_string(), "legacy*".to_string(), "app".to_string()],
+            records: vec![
+                DesiredRecord {
+                    name: "@".to_string(),
+                    record_type: "TXT".to_string(),
+                    content: "v=spf1 -all".to_string(),
+                    ttl: None,
+                    priority: None,
+                },
+                DesiredRecord {
+                    name: "app".to_string(),
+                    record_type: "A".to_string(),
+                    content: "192.0.2.9".to_string(),
+                    ttl: None,
+                    priority: None,
+                },
+            ],
+        };
+        let live = [
+            record("1", "legacy-ftp", "A", "192.0.2.1"),
+            record("2", "www", "A", "192.0.2.2"),
+            record("3", "app", "A", "192.0.2.3"),
+            record(
+                "4",
+                "_externaldns.a-app",
+                "TXT",
+                "heritage=external-dns,external-dns/owner=prod",
+            ),
+        ];
+        let plan = plan(&state, &live, "_externaldns.", 3600, |_| true);
+
+        let described: Vec<String> = plan.operations.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            described,
+            [
+                "remove A 'legacy-ftp' -> 192.0.2.1 (id 1) from example.com",
+                "add TXT '' -> v=spf1 -all in example.com",
+            ]
+        );
+        assert_eq!(plan.skipped.len(), 1);
+        assert!(plan.skipped[0].contains("app.example.com"));
+    }
+
+    #[tokio::test]
+    async fn sync_removes_only_managed_records() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "legacy-ftp", "type": "A", "content": "192.0.2.1", "ttl": 3600},
+                {"id": "2", "name": "www", "type": "A", "content": "192.0.2.2", "ttl": 3600},
+                {"id": "3", "name": "_dmarc", "type": "TXT", "content": "v=DMARC1; p=none", "ttl": 3600}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "1"}), json!({}), 1)
+            .await;
+
+        let handler = njalla.handler(testing::config());
+        let state = DesiredState {
+            zone: "Example.com.".to_string(),
+            manage: vec!["legacy-*".to_string(), "_dmarc".to_string()],
+            records: vec![DesiredRecord {
+                name: "_dmarc".to_string(),
+                record_type: "TXT".to_string(),
+                content: "v=DMARC1; p=none".to_string(),
+                ttl: None,
+                priority: None,
+            }],
+        };
+
+        let plan = handler.sync(&state, false).await.unwrap();
+        assert_eq!(plan.plan.zone, "example.com");
+        assert_eq!(plan.plan.summary(), "0 to add, 1 to remove");
+        assert!(!removed.matched_async().await, "a plan must not write");
+
+        let applied = handler.sync(&state, true).await.unwrap();
+        assert!(applied.applied);
+        assert!(applied.batch.is_some());
+        removed.assert_async().await;
+
+        let err = handler
+            .sync(
+                &DesiredState {
+                    zone: "example.org".to_string(),
+                    ..state
+                },
+                false,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,345 @@
+//! Standard record sets applied to zones from `[templates]` in the config.
+//!
+//! A template lists the records every zone using it should have — MX, SPF, DMARC, CAA, a
+//! verification TXT — with `{zone}` standing for the zone name. Applying one adds the records a
+//! zone lacks and leaves the rest alone, whatever their TTL or priority, using the same
+//! [`planner::exists`] check as creates from external-dns, so it can be repeated safely. Zones
+//! tagged with templates in `[zones."<zone>"]` are checked alongside the drift check for
+//! template records gone missing.
+
+use crate::config::{Config, Template};
+use crate::error::{Error, Result};
+use crate::journal::Operation;
+use crate::njalla::{AddRecordRequest, Budget, DnsRecord};
+use crate::planner::{self, Plan, DEFAULT_TTL};
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::{ApplyQuery, TemplateResponse};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use tracing::{error, info, warn};
+
+/// The placeholder replaced by the zone name.
+const ZONE_PLACEHOLDER: &str = "{zone}";
+
+/// Records of a template that a tagged zone lacks.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TemplateDrift {
+    pub zone: String,
+    pub template: String,
+    pub checked_at: DateTime<Utc>,
+    pub missing: Vec<DnsRecord>,
+}
+
+/// The records of `template` for `zone`, with relative names and without ids. Records without
+/// a TTL get `default_ttl`.
+pub fn render(template: &Template, zone: &str, default_ttl: u32) -> Vec<DnsRecord> {
+    template
+        .records
+        .iter()
+        .map(|record| {
+            let name = record.name.replace(ZONE_PLACEHOLDER, zone);
+            let name = if name.eq_ignore_ascii_case(zone) {
+                String::new()
+            } else {
+                name.strip_suffix(&format!(".{zone}"))
+                    .unwrap_or(&name)
+                    .to_string()
+            };
+            DnsRecord {
+                id: String::new(),
+                name: planner::relative_name(&name).to_string(),
+                record_type: record.record_type.to_ascii_uppercase(),
+                content: record.content.replace(ZONE_PLACEHOLDER, zone),
+                ttl: Some(record.ttl.unwrap_or(default_ttl)),
+                priority: record.priority,
+            }
+        })
+        .collect()
+}
+
+/// The records of `rendered` that `live` doesn't have.
+pub fn missing(rendered: &[DnsRecord], live: &[DnsRecord]) -> Vec<DnsRecord> {
+    rendered
+        .iter()
+        .filter(|r| !planner::exists(live, &r.name, &r.record_type, &r.content))
+        .cloned()
+        .collect()
+}
+
+/// Plan adding the records of `rendered` that `live` lacks to `zone`. Records whose type
+/// `allowed` rejects end up in `skipped`.
+pub fn plan(
+    zone: &str,
+    rendered: &[DnsRecord],
+    live: &[DnsRecord],
+    allowed: impl Fn(&str) -> bool,
+) -> Plan {
+    let mut plan = Plan {
+        zone: zone.to_string(),
+        ..Plan::default()
+    };
+    for record in missing(rendered, live) {
+        let is_allowed = allowed(&record.record_type);
+        let op = Operation::Add(AddRecordRequest {
+            domain: zone.to_string(),
+            name: record.name,
+            record_type: record.record_type,
+            content: record.content,
+            ttl: record.ttl.unwrap_or(DEFAULT_TTL),
+            priority: record.priority,
+        });
+        if is_allowed {
+            plan.operations.push(op);
+        } else {
+            plan.skipped
+                .push(format!("{op}: record type not allowed in zone"));
+        }
+    }
+    plan
+}
+
+// The template admin endpoints.
+impl WebhookHandler {
+    /// Add the records of `template_name` that `zone` lacks. Without `apply`, only plan it.
+    pub async fn apply_template(
+        &self,
+        zone: &str,
+        template_name: &str,
+        query: ApplyQuery,
+    ) -> Result<Json<TemplateResponse>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let template = self.config.templates.get(template_name).ok_or_else(|| {
+            Error::NotFound(format!("no template named {template_name} in the config"))
+        })?;
+
+        let batch = Batch::new(self.batch_budget(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        let rendered = render(template, &zone, self.default_ttl(&zone));
+        let plan = plan(&zone, &rendered, &live, |record_type| {
+            self.config.is_record_type_allowed(&zone, record_type)
+        });
+        info!(
+            "Template {} for {}: {}",
+            template_name,
+            zone,
+            plan.summary()
+        );
+
+        let mut applied = false;
+        if query.apply && !plan.is_empty() {
+            self.run_change(
+                &format!("Apply template {template_name} to {zone}"),
+                &plan.operations,
+                &batch,
+            )
+            .await?;
+            applied = !self.config.dry_run;
+        }
+        Ok(Json(TemplateResponse {
+            template: template_name.to_string(),
+            applied,
+            batch: applied.then_some(batch.id),
+            plan,
+        }))
+    }
+
+    /// Check every zone tagged with templates for missing template records. A zone that can't
+    /// be read is logged and skipped.
+    pub async fn check_templates(&self) -> Result<Vec<TemplateDrift>> {
+        let budget = Budget::unlimited();
+        let mut reports = Vec::new();
+        let mut last_error = None;
+        let mut read = 0;
+        for (zone, options) in &self.config.zones {
+            if options.templates.is_empty() || !self.config.is_domain_allowed(zone) {
+                continue;
+            }
+            let live = match self.njalla_client.list_records(zone, &budget).await {
+                Ok(live) => live,
+                Err(e) => {
+                    error!("Failed to check zone {} for its templates: {}", zone, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            read += 1;
+            for name in &options.templates {
+                let Some(template) = self.config.templates.get(name) else {
+                    continue;
+                };
+                let rendered = render(template, zone, self.default_ttl(zone));
+                let report = TemplateDrift {
+                    zone: zone.clone(),
+                    template: name.clone(),
+                    checked_at: chrono::Utc::now(),
+                    missing: missing(&rendered, &live),
+                };
+                if !report.missing.is_empty() {
+                    warn!(
+                        zone = %zone,
+                        template = %name,
+                        missing = report.missing.len(),
+                        "Template records missing"
+                    );
+                }
+                reports.push(report);
+            }
+        }
+        if let Some(e) = last_error.filter(|_| read == 0) {
+            return Err(e);
+        }
+        self.drift.record_template_reports(reports.clone());
+        Ok(reports)
+    }
+
+    /// The latest template report of every tagged zone.
+    pub async fn template_reports(&self) -> Result<Json<Vec<TemplateDrift>>> {
+        Ok(Json(self.drift.template_reports()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::TemplateRecord;
+    use crate::webhook::testing::{self, MockNjalla};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    fn template() -> Template {
+        let record = |name: &str, record_type: &str, content: &str| TemplateRecord {
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: None,
+            priority: None,
+        };
+        Template {
+            records: vec![
+                TemplateRecord {
+                    priority: Some(10),
+                    ..record("@", "MX", "mx.example.net")
+                },
+                record("_dmarc.{zone}", "TXT", "v=DMARC1; rua=mailto:dmarc@{zone}"),
+                record("", "CAA", "0 issue \"letsencrypt.org\""),
+            ],
+        }
+    }
+
+    #[test]
+    fn renders_placeholders_and_relative_names() {
+        let records = render(&template(), "example.com", 300);
+        assert_eq!(records[0].name, "");
+        assert_eq!(records[0].priority, Some(10));
+        assert_eq!(records[1].name, "_dmarc");
+        assert_eq!(records[1].content, "v=DMARC1; rua=mailto:dmarc@example.com");
+        assert_eq!(records[2].ttl, Some(300));
+    }
+
+    #[test]
+    fn applying_twice_adds_nothing_the_second_time() {
+        let rendered = render(&template(), "example.com", 300);
+        let live = vec![DnsRecord {
+            id: "1".to_string(),
+            name: "@".to_string(),
+            ..rendered[0].clone()
+        }];
+        let plan = plan("example.com", &rendered, &live, |t| t != "CAA");
+        assert_eq!(plan.summary(), "1 to add, 0 to remove");
+        assert_eq!(plan.skipped.len(), 1);
+
+        let applied: Vec<DnsRecord> = rendered
+            .iter()
+            .enumerate()
+            .map(|(i, r)| DnsRecord {
+                id: i.to_string(),
+                ..r.clone()
+            })
+            .collect();
+        assert!(missing(&rendered, &applied).is_empty());
+    }
+
+    #[tokio::test]
+    async fn templates_add_only_missing_records_and_report_the_rest() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "@", "type": "MX", "content": "mx.example.net",
+                 "ttl": 3600, "priority": 10}
+            ]))
+            .await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"name": "_dmarc", "content": "v=DMARC1; rua=mailto:dmarc@example.com"}),
+                json!({"id": "2", "name": "_dmarc",
+                    "type": "TXT", "content": "v=DMARC1; rua=mailto:dmarc@example.com"}),
+                1,
+            )
+            .await;
+
+        let record = |name: &str, record_type: &str, content: &str| crate::config::TemplateRecord {
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: None,
+            priority: None,
+        };
+        let mut config = testing::config();
+        config.templates.insert(
+            "mail".to_string(),
+            crate::config::Template {
+                records: vec![
+                    crate::config::TemplateRecord {
+                        priority: Some(10),
+                        ..record("@", "MX", "mx.example.net")
+                    },
+                    record("_dmarc", "TXT", "v=DMARC1; rua=mailto:dmarc@{zone}"),
+                ],
+            },
+        );
+        config.zones.insert(
+            "example.com".to_string(),
+            crate::config::ZoneConfig {
+                templates: vec!["mail".to_string()],
+                ..Default::default()
+            },
+        );
+        let handler = njalla.handler(config);
+
+        let reports = handler.check_templates().await.unwrap();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0].missing.len(), 1);
+        assert_eq!(reports[0].missing[0].name, "_dmarc");
+        assert!(handler.metrics().await.contains(
+            "njalla_webhook_template_missing_records{zone=\"example.com\",template=\"mail\"} 1"
+        ));
+
+        let Json(plan) = handler
+            .apply_template("example.com", "mail", ApplyQuery { apply: false })
+            .await
+            .unwrap();
+        assert_eq!(plan.plan.summary(), "1 to add, 0 to remove");
+        assert!(!added.matched_async().await, "a plan must not write");
+
+        let Json(applied) = handler
+            .apply_template("example.com", "mail", ApplyQuery { apply: true })
+            .await
+            .unwrap();
+        assert!(applied.applied);
+        added.assert_async().await;
+
+        let err = handler
+            .apply_template("example.com", "web", ApplyQuery { apply: false })
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -0,0 +1,323 @@
+use crate::config::TlsConfig;
+use anyhow::{Context, Result};
+use arc_swap::ArcSwap;
+use axum::serve::Listener;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, Semaphore};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, error};
+
+/// The acceptor in use. Reloads swap in one built from the renewed files; connections already
+/// established keep the session they negotiated.
+pub type SharedAcceptor = Arc<ArcSwap<TlsAcceptor>>;
+
+/// Handshakes that take longer than this are dropped so a stalled client can't pin a task.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Handshakes in flight at once. Beyond this the listener stops accepting until one finishes,
+/// so a flood of connections that never complete the handshake can't pile up tasks.
+const MAX_HANDSHAKES: usize = 256;
+
+fn load_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .with_context(|| format!("cannot read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// Build a TLS acceptor from the configured PEM files. With `client_ca_file` set, clients must
+/// present a certificate chaining to that CA.
+pub fn build_acceptor(tls: &TlsConfig) -> Result<TlsAcceptor> {
+    let (Some(cert_file), Some(key_file)) = (&tls.cert_file, &tls.key_file) else {
+        anyhow::bail!("tls.cert_file and tls.key_file are required for TLS");
+    };
+
+    let certs = load_certs(cert_file).context("tls.cert_file")?;
+    let key = PrivateKeyDer::from_pem_file(key_file)
+        .with_context(|| format!("tls.key_file: cannot read {}", key_file.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .context("unsupported TLS protocol configuration")?;
+
+    let builder = match &tls.client_ca_file {
+        Some(ca_file) => {
+            let mut roots = RootCertStore::empty();
+            for cert in load_certs(ca_file).context("tls.client_ca_file")? {
+                roots.add(cert).with_context(|| {
+                    format!("tls.client_ca_file: invalid CA in {}", ca_file.display())
+                })?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .context("tls.client_ca_file: cannot build client verifier")?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+
+    let mut config = builder
+        .with_single_cert(certs, key)
+        .context("tls: certificate and key do not match")?;
+    config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// A listener that terminates TLS. TCP connections are accepted and handshaken on background
+/// tasks, so one slow handshake never holds up the next `accept`; at most [`MAX_HANDSHAKES`]
+/// run at once.
+pub struct TlsListener {
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+    local_addr: SocketAddr,
+}
+
+impl TlsListener {
+    pub fn new(listener: TcpListener, acceptor: SharedAcceptor) -> std::io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (tx, connections) = mpsc::channel(64);
+
+        let handshakes = Arc::new(Semaphore::new(MAX_HANDSHAKES));
+        tokio::spawn(async move {
+            let mut listener = listener;
+            loop {
+                let Ok(permit) = handshakes.clone().acquire_owned().await else {
+                    return;
+                };
+                let (stream, addr) = Listener::accept(&mut listener).await;
+                let acceptor = acceptor.load_full();
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let _permit = permit;
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = tx.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => debug!(peer = %addr, "TLS handshake failed: {}", e),
+                        Err(_) => debug!(peer = %addr, "TLS handshake timed out"),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            connections,
+            local_addr,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            None => {
+                // The accept task only stops if the runtime is shutting down.
+                error!("TLS accept loop stopped");
+                std::future::pending().await
+            }
+        }
+    }
+
+    fn local_addr(&self) -> std::io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+/// A readable identity for a client certificate: its subject CN, else its first DNS SAN,
+/// else the full subject.
+pub fn client_identity(stream: &TlsStream<TcpStream>) -> Option<String> {
+    let cert = stream.get_ref().1.peer_certificates()?.first()?;
+    let (_, cert) = x509_parser::parse_x509_certificate(cert.as_ref()).ok()?;
+
+    if let Some(cn) = cert
+        .subject()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+    {
+        return Some(cn.to_string());
+    }
+    if let Ok(Some(san)) = cert.subject_alternative_name() {
+        for name in &san.value.general_names {
+            if let x509_parser::extensions::GeneralName::DNSName(dns) = name {
+                return Some(dns.to_string());
+            }
+        }
+    }
+    Some(cert.subject().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rcgen::{BasicConstraints, CertificateParams, DnType, IsCa, KeyPair};
+    use std::io::Write;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    struct Pki {
+        ca: rcgen::Certificate,
+        ca_key: KeyPair,
+    }
+
+    impl Pki {
+        fn new() -> Self {
+            let mut params = CertificateParams::new(Vec::new()).unwrap();
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+            params
+                .distinguished_name
+                .push(DnType::CommonName, "test ca");
+            let ca_key = KeyPair::generate().unwrap();
+            let ca = params.self_signed(&ca_key).unwrap();
+            Self { ca, ca_key }
+        }
+
+        fn issue(&self, name: &str) -> (rcgen::Certificate, KeyPair) {
+            let mut params = CertificateParams::new(vec![name.to_string()]).unwrap();
+            params.distinguished_name.push(DnType::CommonName, name);
+            let key = KeyPair::generate().unwrap();
+            let cert = params.signed_by(&key, &self.ca, &self.ca_key).unwrap();
+            (cert, key)
+        }
+    }
+
+    fn pem_file(contents: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file
+    }
+
+    fn client_config(
+        pki: &Pki,
+        client: Option<(&rcgen::Certificate, &KeyPair)>,
+    ) -> rustls::ClientConfig {
+        let mut roots = RootCertStore::empty();
+        roots.add(pki.ca.der().clone()).unwrap();
+        let builder = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_root_certificates(roots);
+        match client {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    vec![cert.der().clone()],
+                    PrivateKeyDer::try_from(key.serialize_der()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        }
+    }
+
+    /// Serve one connection: echo the client identity back, then close.
+    async fn serve_once(tls: &TlsConfig) -> SocketAddr {
+        let acceptor = Arc::new(ArcSwap::from_pointee(build_acceptor(tls).unwrap()));
+        let tcp = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut listener = TlsListener::new(tcp, acceptor).unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await;
+            let identity = client_identity(&stream).unwrap_or_else(|| "anonymous".to_string());
+            stream.write_all(identity.as_bytes()).await.unwrap();
+            stream.shutdown().await.unwrap();
+        });
+        addr
+    }
+
+    async fn connect(addr: SocketAddr, config: rustls::ClientConfig) -> std::io::Result<String> {
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let tcp = TcpStream::connect(addr).await?;
+        let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        let mut stream = connector.connect(name, tcp).await?;
+        let mut identity = String::new();
+        stream.read_to_string(&mut identity).await?;
+        Ok(identity)
+    }
+
+    #[tokio::test]
+    async fn serves_tls_without_client_auth() {
+        let pki = Pki::new();
+        let (cert, key) = pki.issue("localhost");
+        let cert_file = pem_file(&cert.pem());
+        let key_file = pem_file(&key.serialize_pem());
+        let tls = TlsConfig {
+            cert_file: Some(cert_file.path().to_path_buf()),
+            key_file: Some(key_file.path().to_path_buf()),
+            client_ca_file: None,
+        };
+
+        let addr = serve_once(&tls).await;
+        let identity = connect(addr, client_config(&pki, None)).await.unwrap();
+        assert_eq!(identity, "anonymous");
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_records_client_identity() {
+        let pki = Pki::new();
+        let (cert, key) = pki.issue("localhost");
+        let (client_cert, client_key) = pki.issue("external-dns");
+        let cert_file = pem_file(&cert.pem());
+        let key_file = pem_file(&key.serialize_pem());
+        let ca_file = pem_file(&pki.ca.pem());
+        let tls = TlsConfig {
+            cert_file: Some(cert_file.path().to_path_buf()),
+            key_file: Some(key_file.path().to_path_buf()),
+            client_ca_file: Some(ca_file.path().to_path_buf()),
+        };
+
+        let addr = serve_once(&tls).await;
+        let identity = connect(addr, client_config(&pki, Some((&client_cert, &client_key))))
+            .await
+            .unwrap();
+        assert_eq!(identity, "external-dns");
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_rejects_client_without_certificate() {
+        let pki = Pki::new();
+        let (cert, key) = pki.issue("localhost");
+        let cert_file = pem_file(&cert.pem());
+        let key_file = pem_file(&key.serialize_pem());
+        let ca_file = pem_file(&pki.ca.pem());
+        let tls = TlsConfig {
+            cert_file: Some(cert_file.path().to_path_buf()),
+            key_file: Some(key_file.path().to_path_buf()),
+            client_ca_file: Some(ca_file.path().to_path_buf()),
+        };
+
+        let addr = serve_once(&tls).await;
+        assert!(connect(addr, client_config(&pki, None)).await.is_err());
+    }
+
+    #[test]
+    fn mismatched_key_is_rejected() {
+        let pki = Pki::new();
+        let (cert, _) = pki.issue("localhost");
+        let (_, other_key) = pki.issue("other");
+        let cert_file = pem_file(&cert.pem());
+        let key_file = pem_file(&other_key.serialize_pem());
+        let tls = TlsConfig {
+            cert_file: Some(cert_file.path().to_path_buf()),
+            key_file: Some(key_file.path().to_path_buf()),
+            client_ca_file: None,
+        };
+        assert!(build_acceptor(&tls).is_err());
+    }
+}
@@ -0,0 +1,306 @@
+//! Admin operations on raw Njalla records, ids included, outside the external-dns protocol.
+//!
+//! Zones, records and search are served under `/admin` behind the admin token. The CLI uses
+//! the same methods, plus the single-record adds and removes.
+
+use super::handlers::{Batch, WebhookHandler};
+use super::types::{SearchHit, SearchQuery};
+use crate::config::Config;
+use crate::error::{Error, Result};
+use crate::journal::Operation;
+use crate::njalla::{Budget, DnsRecord, Domain};
+use crate::planner;
+use axum::Json;
+use tracing::warn;
+
+impl WebhookHandler {
+    /// The Njalla domains this webhook may manage, with their status and expiry.
+    pub async fn zones(&self) -> Result<Json<Vec<Domain>>> {
+        let domains = self
+            .domain_lister
+            .list_domains(&Budget::unlimited())
+            .await?
+            .into_iter()
+            .filter(|d| self.config.is_domain_allowed(&d.name))
+            .collect();
+        Ok(Json(domains))
+    }
+
+    /// The raw Njalla records of one zone, ids included.
+    pub async fn zone_records(&self, zone: &str) -> Result<Json<Vec<DnsRecord>>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let records = self
+            .njalla_client
+            .list_records(&zone, &Budget::unlimited())
+            .await?;
+        Ok(Json(records))
+    }
+
+    /// Records of every managed zone matching all criteria of `query`. A zone that can't be
+    /// read is logged and skipped.
+    pub async fn search(&self, query: SearchQuery) -> Result<Json<Vec<SearchHit>>> {
+        if query.name.is_none() && query.record_type.is_none() && query.content.is_none() {
+            return Err(Error::InvalidRequest(
+                "search needs at least one of name, type or content".to_string(),
+            ));
+        }
+        let name = query.name.as_deref().map(str::to_ascii_lowercase);
+        let budget = Budget::unlimited();
+
+        let mut hits = Vec::new();
+        let mut last_error = None;
+        let mut read = 0;
+        for zone in self.managed_zones(&budget).await? {
+            let records = match self.njalla_client.list_records(&zone, &budget).await {
+                Ok(records) => records,
+                Err(e) => {
+                    warn!("Failed to read zone {} for search: {}", zone, e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+            read += 1;
+            for record in records {
+                let dns_name = planner::fqdn(&record.name, &zone);
+                let matches = name
+                    .as_ref()
+                    .is_none_or(|n| dns_name.to_ascii_lowercase().contains(n.as_str()))
+                    && query
+                        .record_type
+                        .as_ref()
+                        .is_none_or(|t| record.record_type.eq_ignore_ascii_case(t))
+                    && query
+                        .content
+                        .as_ref()
+                        .is_none_or(|c| record.content.contains(c.as_str()));
+                if matches {
+                    hits.push(SearchHit {
+                        zone: zone.clone(),
+                        dns_name,
+                        record,
+                    });
+                }
+            }
+        }
+        match last_error {
+            Some(e) if read == 0 => Err(e),
+            _ => Ok(Json(hits)),
+        }
+    }
+
+    /// Add one record to `zone` unless an identical one exists; a twin with another TTL or
+    /// priority is replaced. Returns the operations carried out (or, in dry-run, planned).
+    pub async fn add_record(&self, zone: &str, record: DnsRecord) -> Result<Vec<Operation>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        self.ensure_record_type_allowed(&zone, &record.record_type)?;
+        let record = DnsRecord {
+            ttl: record.ttl.or(Some(self.default_ttl(&zone))),
+            ..record
+        };
+
+        let description = format!(
+            "Add {} {} {}",
+            record.record_type,
+            planner::fqdn(&record.name, &zone),
+            record.content
+        );
+
+        let batch = Batch::new(self.batch_budget(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        let plan = planner::diff(&zone, &[record], &live, |_| false, |_| true);
+        self.run_change(&description, &plan.operations, &batch)
+            .await?;
+        Ok(plan.operations)
+    }
+
+    /// Remove the record with Njalla id `id` from `zone`. Returns the operation carried out
+    /// (or, in dry-run, planned).
+    pub async fn remove_record(&self, zone: &str, id: &str) -> Result<Operation> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+
+        let batch = Batch::new(self.batch_budget(), None);
+        let record = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?
+            .into_iter()
+            .find(|r| r.id == id)
+            .ok_or_else(|| Error::NotFound(format!("no record with id {id} in {zone}")))?;
+        self.ensure_record_type_allowed(&zone, &record.record_type)?;
+        let op = Operation::Remove {
+            domain: zone.clone(),
+            record,
+        };
+        self.run_change(&format!("Remove {op}"), std::slice::from_ref(&op), &batch)
+            .await?;
+        Ok(op)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use crate::webhook::types::{Changes, Endpoint};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn search_matches_every_given_criterion_across_zones() {
+        let mut njalla = MockNjalla::new().await;
+        for (zone, records) in [
+            (
+                "example.com",
+                json!([
+                    {"id": "1", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 300},
+                    {"id": "2", "name": "@", "type": "TXT", "content": "v=spf1 -all", "ttl": 300}
+                ]),
+            ),
+            (
+                "example.org",
+                json!([
+                    {"id": "3", "name": "WWW", "type": "A", "content": "192.0.2.2", "ttl": 300}
+                ]),
+            ),
+        ] {
+            njalla.zone_records(Some(zone), records).await;
+        }
+
+        let config = Config {
+            domain_filter: Some(vec!["example.com".to_string(), "example.org".to_string()]),
+            ..testing::config()
+        };
+        let handler = njalla.handler(config);
+
+        let search = |name: Option<&str>, record_type: Option<&str>| SearchQuery {
+            name: name.map(str::to_string),
+            record_type: record_type.map(str::to_string),
+            content: None,
+        };
+        let Json(hits) = handler
+            .search(search(Some("www."), Some("a")))
+            .await
+            .unwrap();
+        let names: Vec<&str> = hits.iter().map(|h| h.dns_name.as_str()).collect();
+        assert_eq!(names, ["www.example.com", "WWW.example.org"]);
+
+        let Json(hits) = handler.search(search(None, Some("TXT"))).await.unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].dns_name, "example.com");
+        assert_eq!(hits[0].record.id, "2");
+
+        let err = handler.search(search(None, None)).await.unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+
+        let Json(records) = handler.zone_records("Example.org.").await.unwrap();
+        assert_eq!(records[0].id, "3");
+        assert!(matches!(
+            handler.zone_records("example.net").await,
+            Err(Error::DomainNotAllowed(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn zones_lists_only_allowed_domains() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .answer(
+                "list-domains",
+                json!({}),
+                json!({"domains": [
+                    {"name": "example.com", "status": "active", "expiry": null},
+                    {"name": "other.net", "status": "active", "expiry": null}
+                ]}),
+            )
+            .await;
+        let Json(zones) = njalla.handler(testing::config()).zones().await.unwrap();
+        assert_eq!(zones.len(), 1);
+        assert_eq!(zones[0].name, "example.com");
+        assert_eq!(zones[0].status, "active");
+    }
+
+    #[tokio::test]
+    async fn plans_read_records_and_single_record_changes_go_through_run_change() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 3600}
+            ]))
+            .await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({}),
+                json!({"id": "2", "name": "api",
+                    "type": "A", "content": "192.0.2.2"}),
+                1,
+            )
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "1"}), json!({}), 1)
+            .await;
+
+        let handler = njalla.handler(testing::config());
+
+        let changes = Changes {
+            create: vec![
+                Endpoint::new(
+                    "www.example.com".into(),
+                    "A".into(),
+                    vec!["192.0.2.1".into()],
+                )
+                .with_ttl(3600),
+                Endpoint::new(
+                    "api.example.com".into(),
+                    "A".into(),
+                    vec!["192.0.2.2".into()],
+                ),
+            ],
+            ..Changes::default()
+        };
+        let ops = handler.plan_changes(&changes).await.unwrap();
+        assert_eq!(ops.len(), 1, "the existing www record is left out: {ops:?}");
+        assert!(matches!(&ops[0], Operation::Add(r) if r.name == "api"));
+        assert!(!added.matched_async().await, "a plan must not write");
+
+        let record = |name: &str, content: &str| DnsRecord {
+            id: String::new(),
+            name: name.to_string(),
+            record_type: "A".to_string(),
+            content: content.to_string(),
+            ttl: None,
+            priority: None,
+        };
+        let existing = handler
+            .add_record("example.com", record("www", "192.0.2.1"))
+            .await
+            .unwrap();
+        assert!(existing.is_empty());
+        let ops = handler
+            .add_record("example.com", record("api", "192.0.2.2"))
+            .await
+            .unwrap();
+        assert_eq!(ops.len(), 1);
+        added.assert_async().await;
+
+        handler.remove_record("example.com", "1").await.unwrap();
+        removed.assert_async().await;
+        let err = handler
+            .remove_record("example.com", "99")
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::NOT_FOUND);
+    }
+}
@@ -1,15 +1,33 @@
+//! The external-dns provider endpoints and the batching every change goes through.
+//!
+//! Admin operations extend [`WebhookHandler`] next to the module they belong to (history,
+//! snapshots, drift, dedupe, registry, retarget, templates, sync, zone files), and the raw
+//! zone and record operations live in [`super::admin`].
+
 use super::types::*;
 use crate::config::Config;
+use crate::drift::DriftTracker;
 use crate::error::{Error, Result};
-use crate::njalla::{self, Client as NjallaClient, Domain, DomainLister};
+use crate::history::{Action, History, HistoryEntry};
+use crate::journal::{Journal, Operation};
+use crate::metrics;
+use crate::njalla::{self, Budget, Client as NjallaClient, Domain, DomainLister};
+use crate::planner;
+use crate::registry::is_affixed_apex_of;
 use axum::{extract::Query, http::StatusCode, Json};
-use std::sync::Arc;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use tracing::{debug, error, info};
+use uuid::Uuid;
 
 pub struct WebhookHandler {
-    njalla_client: Arc<NjallaClient>,
-    domain_lister: Arc<dyn DomainLister>,
-    config: Config,
+    pub(crate) njalla_client: Arc<NjallaClient>,
+    pub(crate) domain_lister: Arc<dyn DomainLister>,
+    journal: Option<Arc<Journal>>,
+    pub(crate) history: Option<Arc<History>>,
+    pub(crate) drift: Arc<DriftTracker>,
+    pub(crate) config: Config,
 }
 
 impl WebhookHandler {
@@ -18,10 +36,51 @@ impl WebhookHandler {
         Self {
             njalla_client,
             domain_lister,
+            journal: None,
+            history: None,
+            drift: Arc::default(),
             config,
         }
     }
 
+    /// Journal every change before applying it.
+    pub fn with_journal(mut self, journal: Arc<Journal>) -> Self {
+        self.journal = Some(journal);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn njalla_client(&self) -> &NjallaClient {
+        &self.njalla_client
+    }
+
+    pub fn journal(&self) -> Option<&Arc<Journal>> {
+        self.journal.as_ref()
+    }
+
+    /// Record every applied operation in `history`.
+    pub fn with_history(mut self, history: Arc<History>) -> Self {
+        self.history = Some(history);
+        self
+    }
+
+    pub fn history_store(&self) -> Option<&Arc<History>> {
+        self.history.as_ref()
+    }
+
+    /// Track drift against `drift`'s baselines instead of starting with none.
+    pub fn with_drift_tracker(mut self, drift: Arc<DriftTracker>) -> Self {
+        self.drift = drift;
+        self
+    }
+
+    pub fn drift_tracker(&self) -> &Arc<DriftTracker> {
+        &self.drift
+    }
+
     pub async fn health(&self) -> Result<Json<HealthResponse>> {
         Ok(Json(HealthResponse {
             status: "healthy".to_string(),
@@ -31,7 +90,10 @@ impl WebhookHandler {
 
     pub async fn ready(&self) -> Result<Json<HealthResponse>> {
         // Check if we can connect to Njalla API
-        let domains = self.njalla_client.list_domains().await?;
+        let domains = self
+            .njalla_client
+            .list_domains(&Budget::unlimited())
+            .await?;
         info!("Ready check: found {} domains", domains.len());
 
         Ok(Json(HealthResponse {
@@ -40,21 +102,14 @@ impl WebhookHandler {
         }))
     }
 
-    pub async fn negotiate(&self) -> Result<impl axum::response::IntoResponse> {
-        // External-DNS expects negotiation endpoint to return domain filters
-        // with proper content type header
+    pub async fn negotiate(&self) -> Result<Json<serde_json::Value>> {
+        // External-DNS expects negotiation endpoint to return domain filters; the versioned
+        // content type is set by `media::negotiate_media_type`
         let filters = self.config.domain_filter.clone().unwrap_or_default();
 
-        Ok((
-            StatusCode::OK,
-            [(
-                "content-type",
-                "application/external.dns.webhook+json;version=1",
-            )],
-            Json(serde_json::json!({
-                "filters": filters
-            })),
-        ))
+        Ok(Json(serde_json::json!({
+            "filters": filters
+        })))
     }
 
     pub async fn get_records(&self, query: Query<GetRecordsQuery>) -> Result<Json<Vec<Endpoint>>> {
@@ -68,7 +123,11 @@ impl WebhookHandler {
             }
 
             // Fetch records from Njalla
-            let records = self.njalla_client.list_records(zone_name).await?;
+            let records = self
+                .njalla_client
+                .list_records(zone_name, &Budget::unlimited())
+                .await?;
+            self.drift.observe(zone_name, &records);
 
             // Convert Njalla records to external-dns endpoints
             let endpoints: Vec<Endpoint> = records
@@ -94,28 +153,20 @@ impl WebhookHandler {
             // No zone specified - return records for all configured domains
             info!("Getting records for all configured domains");
 
-            let domains = if let Some(ref domain_filter) = self.config.domain_filter {
-                // Use configured domain filter
-                info!("Using configured domain filter: {:?}", domain_filter);
-                domain_filter.clone()
-            } else {
-                // List all domains from Njalla API
-                info!("Fetching all domains from Njalla API");
-                self.njalla_client
-                    .list_domains()
-                    .await?
-                    .into_iter()
-                    .map(|d| d.name)
-                    .collect()
-            };
+            let domains = self.managed_zones(&Budget::unlimited()).await?;
 
             let mut all_endpoints = Vec::new();
 
             for domain in &domains {
                 info!("Fetching records for domain: {}", domain);
 
-                match self.njalla_client.list_records(domain).await {
+                match self
+                    .njalla_client
+                    .list_records(domain, &Budget::unlimited())
+                    .await
+                {
                     Ok(records) => {
+                        self.drift.observe(domain, &records);
                         let endpoints: Vec<Endpoint> = records
                             .iter()
                             .filter(|r| {
@@ -130,9 +181,15 @@ impl WebhookHandler {
                         info!("Found {} endpoints for domain {}", endpoints.len(), domain);
                         all_endpoints.extend(endpoints);
                     }
+                    Err(e) if e.is_retryable() => {
+                        // An incomplete listing would make external-dns plan against
+                        // records that merely failed to load; fail softly so it retries.
+                        error!("Failed to fetch records for domain {}: {}", domain, e);
+                        return Err(e);
+                    }
                     Err(e) => {
                         error!("Failed to fetch records for domain {}: {}", domain, e);
-                        // Continue with other domains even if one fails
+                        // A permanent failure won't go away on retry; continue with other domains
                     }
                 }
             }
@@ -142,6 +199,28 @@ impl WebhookHandler {
         }
     }
 
+    /// The zones this webhook manages: the domain filter, or every domain on the account.
+    pub async fn managed_zones(&self, budget: &Budget) -> Result<Vec<String>> {
+        if let Some(ref domain_filter) = self.config.domain_filter {
+            // Use configured domain filter
+            info!("Using configured domain filter: {:?}", domain_filter);
+            Ok(domain_filter.clone())
+        } else {
+            // List all domains from Njalla API
+            info!("Fetching all domains from Njalla API");
+            Ok(self
+                .njalla_client
+                .list_domains(budget)
+                .await?
+                .into_iter()
+                .map(|d| d.name)
+                .collect())
+        }
+    }
+
+    /// Apply external-dns changes; `204 No Content` when they all went through. In dry-run,
+    /// nothing is written and the plan is logged; the response is still `204`, since
+    /// external-dns treats anything else as a failed apply.
     pub async fn apply_changes(
         &self,
         Json(request): Json<ApplyChangesRequest>,
@@ -178,14 +257,53 @@ impl WebhookHandler {
             );
         }
 
+        if self.config.dry_run {
+            let operations = self.plan_changes(&changes).await?;
+            for op in &operations {
+                info!("DRY RUN: Would {}", op);
+            }
+            info!("DRY RUN: {}", planner::Counts::of(&operations));
+            return Ok(StatusCode::NO_CONTENT);
+        }
+
         if changes.is_empty() {
             info!("No changes to apply");
             return Ok(StatusCode::NO_CONTENT);
         }
 
+        // Every Njalla call for this batch shares one deadline and retry allowance, so we
+        // stop (and say what was left undone) before external-dns gives up on the request.
+        let batch = Batch::new(self.batch_budget(), None);
+        info!("Applying changes as batch {}", batch.id);
+        self.apply_batch(&changes, &batch).await
+    }
+
+    /// `POST /plan`: the operations external-dns changes would make, without making them.
+    pub async fn plan(
+        &self,
+        Json(request): Json<ApplyChangesRequest>,
+    ) -> Result<Json<PlanResponse>> {
+        let operations = self.plan_changes(&request.into_changes()).await?;
+        Ok(Json(PlanResponse::new(operations)))
+    }
+
+    /// Run the operations that `changes` plans without carrying any of them out, and return
+    /// them. Records are read as for a real apply, so creates that already exist are left out.
+    pub async fn plan_changes(&self, changes: &Changes) -> Result<Vec<Operation>> {
+        if changes.is_empty() {
+            return Ok(Vec::new());
+        }
+        let batch = Batch::plan_only(self.batch_budget());
+        self.apply_batch(changes, &batch).await?;
+        Ok(batch.into_planned())
+    }
+
+    async fn apply_batch(&self, changes: &Changes, batch: &Batch) -> Result<StatusCode> {
+        let budget = &batch.budget;
+
         // Pre-fetch owned domains once for the entire batch when no domain filter is set.
         let owned_domains = if self.config.domain_filter.is_none() {
-            match self.domain_lister.list_domains().await {
+            match self.domain_lister.list_domains(budget).await {
                 Ok(domains) => Some(domains),
                 Err(e) => {
                     tracing::warn!(
@@ -200,56 +318,57 @@ impl WebhookHandler {
         };
         let owned_domains_ref = owned_domains.as_deref();
 
-        let mut applied_count = 0;
-        let mut errors = Vec::new();
+        let mut report = BatchReport::default();
 
         // Process deletions first
         for endpoint in &changes.delete {
-            if let Err(e) = self.delete_endpoint(endpoint, owned_domains_ref).await {
-                error!("Failed to delete endpoint {}: {}", endpoint.dns_name, e);
-                errors.push(format!("Delete {}: {}", endpoint.dns_name, e));
-            } else {
-                applied_count += 1;
+            let change = format!("Delete {}", endpoint.dns_name);
+            if budget.is_expired() {
+                report.not_attempted(change);
+                continue;
             }
+            let result = self
+                .delete_endpoint(endpoint, owned_domains_ref, batch)
+                .await;
+            report.record(change, result);
         }
 
         // Process updates (delete old, create new)
         for (old, new) in changes.update_old.iter().zip(changes.update_new.iter()) {
-            if let Err(e) = self.update_endpoint(old, new, owned_domains_ref).await {
-                error!("Failed to update endpoint {}: {}", new.dns_name, e);
-                errors.push(format!("Update {}: {}", new.dns_name, e));
-            } else {
-                applied_count += 1;
+            let change = format!("Update {}", new.dns_name);
+            if budget.is_expired() {
+                report.not_attempted(change);
+                continue;
             }
+            let result = self
+                .update_endpoint(old, new, owned_domains_ref, batch)
+                .await;
+            report.record(change, result);
         }
 
         // Process creations
         for endpoint in &changes.create {
-            if let Err(e) = self.create_endpoint(endpoint, owned_domains_ref).await {
-                error!("Failed to create endpoint {}: {}", endpoint.dns_name, e);
-                errors.push(format!("Create {}: {}", endpoint.dns_name, e));
-            } else {
-                applied_count += 1;
+            let change = format!("Create {}", endpoint.dns_name);
+            if budget.is_expired() {
+                report.not_attempted(change);
+                continue;
             }
+            let result = self
+                .create_endpoint(endpoint, owned_domains_ref, batch)
+                .await;
+            report.record(change, result);
         }
 
-        if errors.is_empty() {
-            info!("Successfully applied {} changes", applied_count);
-            Ok(StatusCode::NO_CONTENT)
-        } else {
-            error!(
-                "Applied {} changes with {} errors: {:?}",
-                applied_count,
-                errors.len(),
-                errors
-            );
-            Err(Error::Internal(format!(
-                "Partial failure: {} succeeded, {} failed: {:?}",
-                applied_count,
-                errors.len(),
-                errors
-            )))
-        }
+        report.into_result()
+    }
+
+    /// The deadline and retry allowance for one `POST /records` batch.
+    pub(crate) fn batch_budget(&self) -> Budget {
+        Budget::new(
+            (self.config.apply_deadline_seconds > 0)
+                .then(|| Duration::from_secs(self.config.apply_deadline_seconds)),
+            (self.config.apply_retry_budget > 0).then_some(self.config.apply_retry_budget),
+        )
     }
 
     pub async fn adjust_endpoints(
@@ -263,20 +382,80 @@ impl WebhookHandler {
 
     // Helper methods for record operations
 
+    /// Create the endpoint's records. Returns whether anything was (or, in dry-run, would be)
+    /// added; `false` means every record already existed.
     async fn create_endpoint(
         &self,
         endpoint: &Endpoint,
         owned_domains: Option<&[Domain]>,
-    ) -> Result<()> {
-        let zone = self.extract_zone(&endpoint.dns_name, owned_domains).await?;
+        batch: &Batch,
+    ) -> Result<bool> {
+        let ops = self
+            .plan_endpoint(None, Some(endpoint), owned_domains, &batch.budget)
+            .await?;
+        self.run_change(&format!("Create {}", endpoint.dns_name), &ops, batch)
+            .await
+    }
+
+    async fn update_endpoint(
+        &self,
+        old: &Endpoint,
+        new: &Endpoint,
+        owned_domains: Option<&[Domain]>,
+        batch: &Batch,
+    ) -> Result<bool> {
+        // Both halves are planned up front so the journal holds the whole update before its
+        // first call.
+        let ops = self
+            .plan_endpoint(Some(old), Some(new), owned_domains, &batch.budget)
+            .await?;
+        self.run_change(&format!("Update {}", new.dns_name), &ops, batch)
+            .await
+    }
+
+    /// Delete the endpoint's records. Returns whether any matching record was (or, in dry-run,
+    /// would be) removed.
+    async fn delete_endpoint(
+        &self,
+        endpoint: &Endpoint,
+        owned_domains: Option<&[Domain]>,
+        batch: &Batch,
+    ) -> Result<bool> {
+        let ops = self
+            .plan_endpoint(Some(endpoint), None, owned_domains, &batch.budget)
+            .await?;
+        self.run_change(&format!("Delete {}", endpoint.dns_name), &ops, batch)
+            .await
+    }
+
+    /// The operations that replace the records of `old` (if any) with those of `new` (if any),
+    /// planned with [`planner::diff`] like a zone sync: a record of `new` with an identical live
+    /// twin is kept (replaced if its TTL or priority differ), the others are added, and live
+    /// records of `old` without a twin in `new` are removed. A create (no `old`) only adds the
+    /// records without a twin and never replaces one.
+    async fn plan_endpoint(
+        &self,
+        old: Option<&Endpoint>,
+        new: Option<&Endpoint>,
+        owned_domains: Option<&[Domain]>,
+        budget: &Budget,
+    ) -> Result<Vec<Operation>> {
+        let Some(endpoint) = new.or(old) else {
+            return Ok(Vec::new());
+        };
+        let zone = self
+            .extract_zone(&endpoint.dns_name, owned_domains, budget)
+            .await?;
 
         if !self.config.is_domain_allowed(&zone) {
             return Err(Error::DomainNotAllowed(zone));
         }
 
-        let name = self.extract_record_name(&endpoint.dns_name, &zone);
+        for e in old.iter().chain(new.iter()) {
+            self.ensure_record_type_allowed(&zone, &e.record_type)?;
+        }
 
-        // Fetch existing records once so creation is idempotent. Njalla's
+        // Fetch the live records so creation is idempotent. Njalla's
         // `add-record` always appends a new record (there is no upsert and no
         // uniqueness constraint), so a redundant CREATE — which external-dns
         // emits whenever its view of current records is missing this entry
@@ -284,118 +463,244 @@ impl WebhookHandler {
         // silently produces a duplicate. Repeated over many reconciliations
         // this floods the zone with thousands of copies and eventually makes
         // the authoritative nameservers fail to serve it (SERVFAIL).
-        // Skipped in dry-run, which must not touch the API at all.
-        let existing = if self.config.dry_run {
-            Vec::new()
-        } else {
-            self.njalla_client.list_records(&zone).await?
+        // Dry-run and plans read too: reads are safe, and without them the plan is wrong.
+        let live = self.njalla_client.list_records(&zone, budget).await?;
+
+        let mut desired: Vec<njalla::DnsRecord> = new
+            .map(|e| self.endpoint_records(e, &zone))
+            .unwrap_or_default();
+        if old.is_none() {
+            // external-dns repeats creates routinely; replacing a twin over a TTL or priority
+            // difference would churn the record (and its id) on every one of them.
+            desired.retain(|r| !planner::exists(&live, &r.name, &r.record_type, &r.content));
+        }
+        let old_records = old.map(|e| self.endpoint_records(e, &zone));
+        let at_endpoint = |record: &njalla::DnsRecord| {
+            old.iter().chain(new.iter()).any(|e| {
+                planner::relative_name(&record.name)
+                    .eq_ignore_ascii_case(&self.extract_record_name(&e.dns_name, &zone))
+                    && record.record_type.eq_ignore_ascii_case(&e.record_type)
+            })
         };
-
-        for target in &endpoint.targets {
-            // Skip if an identical record (same name/type/content) already
-            // exists. Name normalization mirrors `delete_endpoint`.
-            let already_exists = existing.iter().any(|record| {
-                let record_name = if record.name.is_empty() || record.name == "@" {
-                    ""
-                } else {
-                    record.name.as_str()
-                };
-                record_name == name.as_str()
-                    && record.record_type == endpoint.record_type
-                    && record.content == *target
-            });
-
-            if already_exists {
+        let live: Vec<njalla::DnsRecord> = live.into_iter().filter(at_endpoint).collect();
+
+        let plan = planner::diff(
+            &zone,
+            &desired,
+            &live,
+            |record| {
+                old_records.iter().flatten().any(|r| {
+                    planner::relative_name(&record.name).eq_ignore_ascii_case(&r.name)
+                        && record.record_type.eq_ignore_ascii_case(&r.record_type)
+                        && record.content == r.content
+                })
+            },
+            |_| true,
+        );
+        if let Some(new) = new {
+            let adds = plan
+                .operations
+                .iter()
+                .filter(|op| matches!(op, Operation::Add(_)))
+                .count();
+            if adds < new.targets.len() {
                 info!(
-                    "Record already exists, skipping create: {} {} -> {}",
-                    endpoint.record_type, endpoint.dns_name, target
+                    "{} of {} record(s) already exist, skipping their create: {} {}",
+                    new.targets.len() - adds,
+                    new.targets.len(),
+                    new.record_type,
+                    new.dns_name
                 );
-                continue;
             }
+        }
+        Ok(plan.operations)
+    }
 
-            let priority = endpoint
-                .provider_specific
-                .iter()
-                .find(|ps| ps.name == "priority")
-                .and_then(|ps| ps.value.parse().ok());
-
-            let request = njalla::AddRecordRequest {
-                domain: zone.clone(),
+    /// The records an endpoint stands for, as [`planner::diff`] takes them.
+    fn endpoint_records(&self, endpoint: &Endpoint, zone: &str) -> Vec<njalla::DnsRecord> {
+        let name = self.extract_record_name(&endpoint.dns_name, zone);
+        let priority = endpoint
+            .provider_specific
+            .iter()
+            .find(|ps| ps.name == "priority")
+            .and_then(|ps| ps.value.parse().ok());
+        let ttl = endpoint
+            .record_ttl
+            .map(|ttl| ttl as u32)
+            .unwrap_or_else(|| self.default_ttl(zone));
+        endpoint
+            .targets
+            .iter()
+            .map(|target| njalla::DnsRecord {
+                id: String::new(),
                 name: name.clone(),
                 record_type: endpoint.record_type.clone(),
                 content: target.clone(),
-                ttl: endpoint.record_ttl.unwrap_or(3600) as u32,
+                ttl: Some(ttl),
                 priority,
-            };
+            })
+            .collect()
+    }
 
-            if self.config.dry_run {
-                info!("DRY RUN: Would create record: {:?}", request);
-            } else {
-                self.njalla_client.add_record(request).await?;
+    /// Carry out a change's operations in order, journaling them first when a journal is
+    /// configured. Returns whether there was anything to do.
+    pub(crate) async fn run_change(
+        &self,
+        description: &str,
+        ops: &[Operation],
+        batch: &Batch,
+    ) -> Result<bool> {
+        if ops.is_empty() {
+            return Ok(false);
+        }
+
+        if let Some(planned) = &batch.planned {
+            planned.lock().unwrap().extend(ops.iter().cloned());
+            return Ok(true);
+        }
+
+        if self.config.dry_run {
+            for op in ops {
+                match op {
+                    Operation::Add(request) => {
+                        info!("DRY RUN: Would create record: {:?}", request)
+                    }
+                    Operation::Remove { domain, record } => info!(
+                        "DRY RUN: Would delete record: {:?}",
+                        njalla::RemoveRecordRequest {
+                            domain: domain.clone(),
+                            id: record.id.clone(),
+                        }
+                    ),
+                    Operation::Edit {
+                        domain,
+                        record,
+                        content,
+                    } => info!(
+                        "DRY RUN: Would edit record: {:?}",
+                        Operation::edit_request(domain, record, content)
+                    ),
+                }
             }
+            return Ok(true);
         }
 
-        Ok(())
+        let Some(journal) = &self.journal else {
+            self.run_ops(ops, batch, None).await?;
+            return Ok(true);
+        };
+
+        let change = journal.begin(description, ops).await?;
+        let result = self.run_ops(ops, batch, Some((journal, change.id()))).await;
+        // A failed call is a known outcome reported to external-dns, not an interruption, so
+        // the change is closed either way.
+        change.end().await?;
+        result.map(|()| true)
     }
 
-    async fn update_endpoint(
+    /// Carry out `ops`, marking each done in `journal`'s change as it completes.
+    async fn run_ops(
         &self,
-        old: &Endpoint,
-        new: &Endpoint,
-        owned_domains: Option<&[Domain]>,
+        ops: &[Operation],
+        batch: &Batch,
+        journal: Option<(&Journal, u64)>,
     ) -> Result<()> {
-        // For simplicity, delete old and create new
-        self.delete_endpoint(old, owned_domains).await?;
-        self.create_endpoint(new, owned_domains).await?;
+        for (i, op) in ops.iter().enumerate() {
+            let entry = match op {
+                Operation::Add(request) => {
+                    let added = self
+                        .njalla_client
+                        .add_record(request.clone(), &batch.budget)
+                        .await?;
+                    self.drift.applied(op, Some(&added));
+                    batch.entry(Action::Add, &request.domain, &added)
+                }
+                Operation::Remove { domain, record } => {
+                    let request = njalla::RemoveRecordRequest {
+                        domain: domain.clone(),
+                        id: record.id.clone(),
+                    };
+                    self.njalla_client
+                        .remove_record(request, &batch.budget)
+                        .await?;
+                    self.drift.applied(op, None);
+                    batch.entry(Action::Remove, domain, record)
+                }
+                Operation::Edit {
+                    domain,
+                    record,
+                    content,
+                } => {
+                    let edited = self
+                        .njalla_client
+                        .update_record(
+                            Operation::edit_request(domain, record, content),
+                            &batch.budget,
+                        )
+                        .await?;
+                    self.drift.applied(op, None);
+                    HistoryEntry::edited(batch.id, batch.undoes, domain, record, &edited)
+                }
+            };
+            if let Some((journal, change)) = journal {
+                journal.done(change, i).await?;
+            }
+            if let Some(history) = &self.history {
+                // The operation happened either way; failing the request would only make
+                // external-dns repeat it.
+                if let Err(e) = history.record(&entry).await {
+                    error!("Failed to record history for batch {}: {}", batch.id, e);
+                }
+            }
+        }
         Ok(())
     }
 
-    async fn delete_endpoint(
+    /// `zone` if given and allowed, otherwise every managed zone.
+    pub(crate) async fn selected_zones(
         &self,
-        endpoint: &Endpoint,
-        owned_domains: Option<&[Domain]>,
-    ) -> Result<()> {
-        let zone = self.extract_zone(&endpoint.dns_name, owned_domains).await?;
-
-        if !self.config.is_domain_allowed(&zone) {
-            return Err(Error::DomainNotAllowed(zone));
+        zone: Option<&str>,
+        budget: &Budget,
+    ) -> Result<Vec<String>> {
+        match zone {
+            Some(zone) => {
+                let zone = Config::normalize_domain(zone);
+                if !self.config.is_domain_allowed(&zone) {
+                    return Err(Error::DomainNotAllowed(zone));
+                }
+                Ok(vec![zone])
+            }
+            None => self.managed_zones(budget).await,
         }
+    }
 
-        // Find matching records
-        let records = self.njalla_client.list_records(&zone).await?;
-        let name = self.extract_record_name(&endpoint.dns_name, &zone);
+    /// Prometheus text exposition of the latest drift and template reports.
+    pub async fn metrics(&self) -> String {
+        metrics::render(&self.drift.reports(), &self.drift.template_reports())
+    }
 
-        for record in records {
-            let record_name = if record.name.is_empty() || record.name == "@" {
-                "".to_string()
-            } else {
-                record.name.clone()
-            };
+    pub(crate) fn default_ttl(&self, zone: &str) -> u32 {
+        self.config
+            .zone(zone)
+            .and_then(|z| z.default_ttl)
+            .unwrap_or(planner::DEFAULT_TTL)
+    }
 
-            if record_name == name
-                && record.record_type == endpoint.record_type
-                && endpoint.targets.contains(&record.content)
-            {
-                let request = njalla::RemoveRecordRequest {
-                    domain: zone.clone(),
-                    id: record.id,
-                };
-
-                if self.config.dry_run {
-                    info!("DRY RUN: Would delete record: {:?}", request);
-                } else {
-                    self.njalla_client.remove_record(request).await?;
-                }
-            }
+    pub(crate) fn ensure_record_type_allowed(&self, zone: &str, record_type: &str) -> Result<()> {
+        if self.config.is_record_type_allowed(zone, record_type) {
+            Ok(())
+        } else {
+            Err(Error::PolicyDenied(format!(
+                "{record_type} records are not managed in zone {zone}"
+            )))
         }
-
-        Ok(())
     }
 
     async fn extract_zone(
         &self,
         dns_name: &str,
         prefetched_domains: Option<&[Domain]>,
+        budget: &Budget,
     ) -> Result<String> {
         // Normalize dns_name; filter entries are already canonical from Config::from_env
         let normalized_name = dns_name
@@ -445,7 +750,7 @@ impl WebhookHandler {
         let owned_domains = match prefetched_domains {
             Some(domains) => domains,
             None => {
-                fetched = self.domain_lister.list_domains().await?;
+                fetched = self.domain_lister.list_domains(budget).await?;
                 &fetched
             }
         };
@@ -469,7 +774,7 @@ impl WebhookHandler {
         }
     }
 
-    fn extract_record_name(&self, dns_name: &str, zone: &str) -> String {
+    pub(crate) fn extract_record_name(&self, dns_name: &str, zone: &str) -> String {
         // Normalize dns_name to match the canonical zone returned by extract_zone
         let normalized = dns_name
             .strip_suffix('.')
@@ -485,51 +790,173 @@ impl WebhookHandler {
     }
 }
 
-/// True when `name` is external-dns's affixed registry TXT for the APEX of `zone` — i.e.
-/// `<prefix><type>-<zone>` (e.g. `_externaldns.a-whathefolk.com` for zone `whathefolk.com`). The
-/// `<type>-` record-type marker fuses onto the zone's leftmost label, so the normal `.{zone}`
-/// suffix check misses it. The match is constrained to a real DNS record-type marker so a sibling
-/// domain like `api-example.com` is NOT misclassified as belonging to `example.com`.
-fn is_affixed_apex_of(name: &str, zone: &str) -> bool {
-    const AFFIX_TYPES: &[&str] = &[
-        "a", "aaaa", "cname", "txt", "ns", "ptr", "srv", "mx", "naptr", "soa", "caa",
-    ];
-    // Strip the `-<zone>` tail; the remainder must be `<labels>.<type>` — i.e. the `<type>-`
-    // marker is preceded by at least one label (the registry prefix, e.g. `_externaldns.`).
-    // Requiring a non-empty prefix-before-the-marker is what distinguishes a real affix
-    // (`_externaldns.a-<zone>`) from a real sibling host (`a-example.com`) or a real subdomain
-    // (`cname-foo.example.com`), where the type-looking token IS the leftmost label.
-    match name.strip_suffix(&format!("-{zone}")) {
-        Some(prefix) => match prefix.rsplit_once('.') {
-            Some((before, marker)) => !before.is_empty() && AFFIX_TYPES.contains(&marker),
-            None => false,
-        },
-        None => false,
+/// Changes applied together: the id their history entries share and the budget their Njalla
+/// calls share.
+pub(crate) struct Batch {
+    pub(crate) id: Uuid,
+    /// The batch this one reverts, for undos.
+    undoes: Option<Uuid>,
+    pub(crate) budget: Budget,
+    /// Set for a plan-only batch, which collects its operations instead of carrying them out.
+    planned: Option<Mutex<Vec<Operation>>>,
+}
+
+impl Batch {
+    pub(crate) fn new(budget: Budget, undoes: Option<Uuid>) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            undoes,
+            budget,
+            planned: None,
+        }
+    }
+
+    fn plan_only(budget: Budget) -> Self {
+        Self {
+            planned: Some(Mutex::default()),
+            ..Self::new(budget, None)
+        }
+    }
+
+    fn into_planned(self) -> Vec<Operation> {
+        self.planned
+            .map(|planned| planned.into_inner().unwrap())
+            .unwrap_or_default()
+    }
+
+    fn entry(&self, action: Action, zone: &str, record: &njalla::DnsRecord) -> HistoryEntry {
+        HistoryEntry::new(self.id, self.undoes, action, zone, record)
+    }
+}
+
+/// What became of one change in a batch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum ChangeState {
+    /// Records were added or removed (or would have been, in dry-run).
+    Done,
+    /// Nothing to do: the records were already in the requested state.
+    Skipped,
+    Failed(String),
+    /// The batch deadline passed before this change was started.
+    NotAttempted,
+}
+
+impl fmt::Display for ChangeState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Done => f.write_str("done"),
+            Self::Skipped => f.write_str("skipped"),
+            Self::Failed(reason) => write!(f, "failed: {reason}"),
+            Self::NotAttempted => f.write_str("not attempted"),
+        }
+    }
+}
+
+/// Per-change outcomes of a `POST /records` batch, folded into the response at the end.
+#[derive(Debug, Default)]
+struct BatchReport {
+    changes: Vec<(String, ChangeState)>,
+    /// Whether any failure could succeed on retry.
+    retryable: bool,
+}
+
+impl BatchReport {
+    fn record(&mut self, change: String, result: Result<bool>) {
+        let state = match result {
+            Ok(true) => ChangeState::Done,
+            Ok(false) => ChangeState::Skipped,
+            Err(e) => {
+                error!("{} failed: {}", change, e);
+                self.retryable |= e.is_retryable();
+                ChangeState::Failed(e.to_string())
+            }
+        };
+        self.changes.push((change, state));
+    }
+
+    fn not_attempted(&mut self, change: String) {
+        self.changes.push((change, ChangeState::NotAttempted));
+    }
+
+    fn count(&self, matches: impl Fn(&ChangeState) -> bool) -> usize {
+        self.changes
+            .iter()
+            .filter(|(_, state)| matches(state))
+            .count()
+    }
+
+    /// Changes that didn't complete, with their state.
+    fn incomplete(&self) -> Vec<String> {
+        self.changes
+            .iter()
+            .filter(|(_, state)| {
+                matches!(state, ChangeState::Failed(_) | ChangeState::NotAttempted)
+            })
+            .map(|(change, state)| format!("{change}: {state}"))
+            .collect()
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} done, {} skipped, {} failed, {} not attempted",
+            self.count(|s| *s == ChangeState::Done),
+            self.count(|s| *s == ChangeState::Skipped),
+            self.count(|s| matches!(s, ChangeState::Failed(_))),
+            self.count(|s| *s == ChangeState::NotAttempted),
+        )
+    }
+
+    fn into_result(self) -> Result<StatusCode> {
+        let incomplete = self.incomplete();
+        if incomplete.is_empty() {
+            info!("Successfully applied changes: {}", self.summary());
+            return Ok(StatusCode::NO_CONTENT);
+        }
+
+        for (change, state) in &self.changes {
+            info!(change = %change, state = %state, "Change outcome");
+        }
+        let message = format!("Partial failure: {}: {:?}", self.summary(), incomplete);
+        error!("{}", message);
+
+        // external-dns resends the whole batch after a soft (5xx) error, so one transient
+        // failure (or running out of time) makes the batch worth retrying. Only when every
+        // failure is permanent would a retry fail identically.
+        if self.count(|s| *s == ChangeState::NotAttempted) > 0 {
+            Err(Error::Timeout(format!("batch deadline reached: {message}")))
+        } else if self.retryable {
+            Err(Error::Unavailable(message))
+        } else {
+            Err(Error::Rejected(message))
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
     use serde_json::json;
 
     fn test_handler() -> WebhookHandler {
-        let config = Config {
-            njalla_api_token: "dummy-token".to_string(),
-            webhook_host: "127.0.0.1".to_string(),
-            webhook_port: 8888,
-            domain_filter: Some(vec![Config::normalize_domain("example.com")]),
+        testing::offline_handler(Config {
             dry_run: true,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
-        };
+            ..testing::config()
+        })
+    }
 
-        let client = Arc::new(
-            NjallaClient::new("dummy-token", 0, std::time::Duration::from_millis(0))
-                .expect("client should build"),
-        );
-        WebhookHandler::new(client, config)
+    /// A handler for example.com whose zone holds `records`. Writes need further mocks.
+    async fn zone_handler(
+        records: serde_json::Value,
+        dry_run: bool,
+    ) -> (MockNjalla, WebhookHandler) {
+        let mut njalla = MockNjalla::new().await;
+        njalla.records(records).await;
+        let handler = njalla.handler(Config {
+            dry_run,
+            ..testing::config()
+        });
+        (njalla, handler)
     }
 
     struct MockDomainLister {
@@ -538,7 +965,7 @@ mod tests {
 
     #[async_trait::async_trait]
     impl DomainLister for MockDomainLister {
-        async fn list_domains(&self) -> crate::error::Result<Vec<Domain>> {
+        async fn list_domains(&self, _budget: &Budget) -> crate::error::Result<Vec<Domain>> {
             Ok(self.domains.clone())
         }
     }
@@ -547,41 +974,20 @@ mod tests {
 
     #[async_trait::async_trait]
     impl DomainLister for PanickingDomainLister {
-        async fn list_domains(&self) -> crate::error::Result<Vec<Domain>> {
+        async fn list_domains(&self, _budget: &Budget) -> crate::error::Result<Vec<Domain>> {
             panic!("list_domains should not be called when domain filter is set");
         }
     }
 
     fn handler_with_filter(domains: Vec<&str>) -> WebhookHandler {
-        let config = Config {
-            njalla_api_token: "dummy-token".to_string(),
-            webhook_host: "127.0.0.1".to_string(),
-            webhook_port: 8888,
+        testing::offline_handler(Config {
             domain_filter: Some(domains.into_iter().map(Config::normalize_domain).collect()),
             dry_run: true,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
-        };
-        let client = Arc::new(
-            NjallaClient::new("dummy-token", 0, std::time::Duration::from_millis(0))
-                .expect("client should build"),
-        );
-        WebhookHandler::new(client, config)
+            ..testing::config()
+        })
     }
 
     fn handler_with_mock_domains(domains: Vec<&str>) -> WebhookHandler {
-        let config = Config {
-            njalla_api_token: "dummy-token".to_string(),
-            webhook_host: "127.0.0.1".to_string(),
-            webhook_port: 8888,
-            domain_filter: None,
-            dry_run: true,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
-        };
-
         let mock_lister = Arc::new(MockDomainLister {
             domains: domains
                 .into_iter()
@@ -592,36 +998,43 @@ mod tests {
                 })
                 .collect(),
         });
-
-        let client = Arc::new(
-            NjallaClient::new("dummy-token", 0, std::time::Duration::from_millis(0))
-                .expect("client should build"),
-        );
         WebhookHandler {
-            njalla_client: client,
             domain_lister: mock_lister,
-            config,
+            ..testing::offline_handler(Config {
+                domain_filter: None,
+                dry_run: true,
+                ..testing::config()
+            })
         }
     }
 
     #[tokio::test]
     async fn extract_zone_returns_canonical_zone() {
         let handler = test_handler();
-        let zone = handler.extract_zone("www.example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("www.example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "example.com");
     }
 
     #[tokio::test]
     async fn extract_zone_mixed_case_filter() {
         let handler = handler_with_filter(vec!["Example.COM"]);
-        let zone = handler.extract_zone("www.example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("www.example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "example.com");
     }
 
     #[tokio::test]
     async fn extract_zone_trailing_dot_filter() {
         let handler = handler_with_filter(vec!["example.com."]);
-        let zone = handler.extract_zone("www.example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("www.example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "example.com");
     }
 
@@ -633,7 +1046,7 @@ mod tests {
         // name is kept as the record name (Njalla stores it verbatim, so it round-trips).
         let handler = handler_with_filter(vec!["whathefolk.com"]);
         let zone = handler
-            .extract_zone("_externaldns.a-whathefolk.com", None)
+            .extract_zone("_externaldns.a-whathefolk.com", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "whathefolk.com");
@@ -646,7 +1059,7 @@ mod tests {
         // Regression: the old-format apex ownership TXT keeps resolving via the dotted suffix.
         let handler = handler_with_filter(vec!["whathefolk.com"]);
         let zone = handler
-            .extract_zone("_externaldns.whathefolk.com", None)
+            .extract_zone("_externaldns.whathefolk.com", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "whathefolk.com");
@@ -659,7 +1072,10 @@ mod tests {
         // `api-example.com` is a real sibling apex, not an external-dns affix of `example.com`
         // (`api` isn't a record type), so it must resolve to itself — not to `example.com`.
         let handler = handler_with_filter(vec!["example.com", "api-example.com"]);
-        let zone = handler.extract_zone("api-example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("api-example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "api-example.com");
     }
 
@@ -668,7 +1084,10 @@ mod tests {
         // `a-example.com` is its own registered zone. Even though `a` IS a record-type marker
         // and `example.com` is listed FIRST, the exact match must win over the affix reading.
         let handler = handler_with_filter(vec!["example.com", "a-example.com"]);
-        let zone = handler.extract_zone("a-example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("a-example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "a-example.com");
     }
 
@@ -678,7 +1097,7 @@ mod tests {
         // subdomain of `example.com` — the longer (more specific) zone must win.
         let handler = handler_with_filter(vec!["example.com", "sub.example.com"]);
         let zone = handler
-            .extract_zone("_externaldns.a-sub.example.com", None)
+            .extract_zone("_externaldns.a-sub.example.com", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "sub.example.com");
@@ -690,7 +1109,7 @@ mod tests {
         // to its parent zone (a dotted-suffix match), not be misread as an apex affix.
         let handler = handler_with_filter(vec!["example.com"]);
         let zone = handler
-            .extract_zone("cname-foo.example.com", None)
+            .extract_zone("cname-foo.example.com", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "example.com");
@@ -701,7 +1120,11 @@ mod tests {
         // Same affix handling on the no-filter (owned-domains) path.
         let handler = handler_with_mock_domains(vec!["whathefolk.com"]);
         let zone = handler
-            .extract_zone("_externaldns.cname-whathefolk.com", None)
+            .extract_zone(
+                "_externaldns.cname-whathefolk.com",
+                None,
+                &Budget::unlimited(),
+            )
             .await
             .unwrap();
         assert_eq!(zone, "whathefolk.com");
@@ -711,7 +1134,7 @@ mod tests {
     async fn extract_zone_trailing_dot_dns_name() {
         let handler = test_handler();
         let zone = handler
-            .extract_zone("www.example.com.", None)
+            .extract_zone("www.example.com.", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "example.com");
@@ -721,7 +1144,7 @@ mod tests {
     async fn extract_zone_fallback_strips_trailing_dot() {
         let handler = handler_with_filter(vec!["other.com"]);
         let zone = handler
-            .extract_zone("www.fallback.org.", None)
+            .extract_zone("www.fallback.org.", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "fallback.org");
@@ -730,7 +1153,10 @@ mod tests {
     #[tokio::test]
     async fn extract_record_name_with_mixed_case() {
         let handler = handler_with_filter(vec!["Example.COM"]);
-        let zone = handler.extract_zone("WWW.Example.COM", None).await.unwrap();
+        let zone = handler
+            .extract_zone("WWW.Example.COM", None, &Budget::unlimited())
+            .await
+            .unwrap();
         let name = handler.extract_record_name("WWW.Example.COM", &zone);
         assert_eq!(name, "www");
     }
@@ -739,7 +1165,7 @@ mod tests {
     async fn extract_record_name_with_trailing_dot() {
         let handler = test_handler();
         let zone = handler
-            .extract_zone("app.example.com.", None)
+            .extract_zone("app.example.com.", None, &Budget::unlimited())
             .await
             .unwrap();
         let name = handler.extract_record_name("app.example.com.", &zone);
@@ -749,7 +1175,10 @@ mod tests {
     #[tokio::test]
     async fn extract_record_name_exact_zone_match() {
         let handler = test_handler();
-        let zone = handler.extract_zone("example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         let name = handler.extract_record_name("example.com", &zone);
         assert_eq!(name, "");
     }
@@ -758,7 +1187,7 @@ mod tests {
     async fn extract_zone_multi_label_tld_returns_longest_match() {
         let handler = handler_with_mock_domains(vec!["co.uk", "example.co.uk"]);
         let zone = handler
-            .extract_zone("app.example.co.uk", None)
+            .extract_zone("app.example.co.uk", None, &Budget::unlimited())
             .await
             .unwrap();
         assert_eq!(zone, "example.co.uk");
@@ -767,45 +1196,64 @@ mod tests {
     #[tokio::test]
     async fn extract_zone_case_insensitive_match() {
         let handler = handler_with_mock_domains(vec!["example.com"]);
-        let zone = handler.extract_zone("App.Example.COM", None).await.unwrap();
+        let zone = handler
+            .extract_zone("App.Example.COM", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "example.com");
     }
 
     #[tokio::test]
     async fn extract_zone_no_match_returns_error() {
         let handler = handler_with_mock_domains(vec!["example.com"]);
-        let result = handler.extract_zone("unknown.org", None).await;
+        let result = handler
+            .extract_zone("unknown.org", None, &Budget::unlimited())
+            .await;
         assert!(result.is_err());
     }
 
     #[tokio::test]
     async fn extract_zone_with_domain_filter_does_not_call_list_domains() {
-        let config = Config {
-            njalla_api_token: "dummy-token".to_string(),
-            webhook_host: "127.0.0.1".to_string(),
-            webhook_port: 8888,
-            domain_filter: Some(vec!["example.com".to_string()]),
-            dry_run: true,
-            cache_ttl_seconds: 60,
-            njalla_max_retries: 3,
-            njalla_retry_base_ms: 500,
-        };
-        let client = Arc::new(
-            NjallaClient::new("dummy-token", 0, std::time::Duration::from_millis(0))
-                .expect("client should build"),
-        );
         let handler = WebhookHandler {
-            njalla_client: client,
             domain_lister: Arc::new(PanickingDomainLister),
-            config,
+            ..test_handler()
         };
-        let zone = handler.extract_zone("app.example.com", None).await.unwrap();
+        let zone = handler
+            .extract_zone("app.example.com", None, &Budget::unlimited())
+            .await
+            .unwrap();
         assert_eq!(zone, "example.com");
     }
 
+    #[tokio::test]
+    async fn create_endpoint_rejects_record_type_outside_zone_policy() {
+        let mut handler = test_handler();
+        handler.config.zones.insert(
+            "example.com".to_string(),
+            crate::config::ZoneConfig {
+                record_types: Some(vec!["A".to_string()]),
+                ..Default::default()
+            },
+        );
+        let endpoint = Endpoint::new(
+            "app.example.com".to_string(),
+            "TXT".to_string(),
+            vec!["hello".to_string()],
+        );
+
+        let err = handler
+            .create_endpoint(&endpoint, None, &Batch::new(Budget::unlimited(), None))
+            .await
+            .expect_err("TXT is outside the zone policy");
+        assert!(
+            matches!(err, Error::PolicyDenied(_)),
+            "expected Error::PolicyDenied, got: {err:?}"
+        );
+    }
+
     #[tokio::test]
     async fn apply_changes_returns_error_on_partial_failure() {
-        let handler = test_handler();
+        let (_njalla, handler) = zone_handler(json!([]), true).await;
         let request: ApplyChangesRequest = serde_json::from_value(json!({
             "create": [
                 {
@@ -827,9 +1275,10 @@ mod tests {
             .await
             .expect_err("partial failure should return error");
         assert!(
-            matches!(err, Error::Internal(_)),
-            "expected Error::Internal, got: {err:?}"
+            matches!(err, Error::Rejected(_)),
+            "expected Error::Rejected, got: {err:?}"
         );
+        assert!(!err.is_retryable());
     }
 
     #[tokio::test]
@@ -874,14 +1323,23 @@ mod tests {
             .await
             .expect_err("all-disallowed should return error");
         assert!(
-            matches!(err, Error::Internal(_)),
-            "expected Error::Internal, got: {err:?}"
+            matches!(err, Error::Rejected(_)),
+            "expected Error::Rejected, got: {err:?}"
         );
+        assert_eq!(err.status(), StatusCode::UNPROCESSABLE_ENTITY);
     }
 
     #[tokio::test]
     async fn apply_changes_accepts_external_dns_payload_and_returns_no_content() {
-        let handler = test_handler();
+        let (mut njalla, handler) = zone_handler(json!([]), false).await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"name": "app", "content": "192.0.2.10"}),
+                json!({"id": "1", "name": "app", "type": "A", "content": "192.0.2.10", "ttl": 3600}),
+                1,
+            )
+            .await;
         let request: ApplyChangesRequest = serde_json::from_value(json!({
             "create": [
                 {
@@ -899,5 +1357,353 @@ mod tests {
             .expect("request should succeed");
 
         assert_eq!(status, StatusCode::NO_CONTENT);
+        added.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn dry_run_answers_no_content_and_plan_returns_the_exact_plan() {
+        let (_njalla, handler) = zone_handler(
+            json!([
+                {"id": "1", "name": "app", "type": "A", "content": "192.0.2.10", "ttl": 3600},
+                {"id": "2", "name": "old", "type": "A", "content": "192.0.2.20", "ttl": 3600}
+            ]),
+            true,
+        )
+        .await;
+        let request = || -> ApplyChangesRequest {
+            serde_json::from_value(json!({
+                "create": [
+                    {
+                        "dnsName": "app.example.com",
+                        "targets": ["192.0.2.10", "192.0.2.11"],
+                        "recordType": "A"
+                    }
+                ],
+                "delete": [
+                    {
+                        "dnsName": "old.example.com",
+                        "targets": ["192.0.2.20"],
+                        "recordType": "A"
+                    }
+                ]
+            }))
+            .expect("payload should deserialize")
+        };
+
+        // external-dns fails its whole sync on anything but 204, dry-run or not.
+        let status = handler
+            .apply_changes(Json(request()))
+            .await
+            .expect("request should succeed");
+        assert_eq!(status, StatusCode::NO_CONTENT);
+
+        let Json(plan) = handler
+            .plan(Json(request()))
+            .await
+            .expect("plan should succeed");
+        let plan = serde_json::to_value(plan).unwrap();
+        assert_eq!(
+            (&plan["adds"], &plan["edits"], &plan["removes"]),
+            (&json!(1), &json!(0), &json!(1)),
+            "the existing app record is not created again: {plan}"
+        );
+        assert_eq!(plan["operations"][0]["op"], "remove");
+        assert_eq!(plan["operations"][0]["record"]["id"], "2");
+        assert_eq!(plan["operations"][1]["content"], "192.0.2.11");
+    }
+
+    /// A live (not dry-run) handler whose Njalla API always answers `status`.
+    async fn failing_upstream_handler(status: usize) -> (MockNjalla, WebhookHandler) {
+        let mut njalla = MockNjalla::new().await;
+        njalla.fail(status).await;
+        let handler = njalla.handler(testing::config());
+        (njalla, handler)
+    }
+
+    fn create_request(names: &[&str]) -> ApplyChangesRequest {
+        let create: Vec<_> = names
+            .iter()
+            .map(|name| json!({"dnsName": name, "targets": ["192.0.2.10"], "recordType": "A"}))
+            .collect();
+        serde_json::from_value(json!({ "create": create })).expect("payload should deserialize")
+    }
+
+    #[tokio::test]
+    async fn apply_changes_is_retryable_when_njalla_is_rate_limited() {
+        let (_njalla, handler) = failing_upstream_handler(429).await;
+
+        let err = handler
+            .apply_changes(Json(create_request(&["app.example.com"])))
+            .await
+            .expect_err("rate-limited create should fail");
+        assert!(
+            matches!(err, Error::Unavailable(_)),
+            "expected Error::Unavailable, got: {err:?}"
+        );
+        assert_eq!(err.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn apply_changes_with_transient_and_permanent_failures_is_retryable() {
+        let (_njalla, handler) = failing_upstream_handler(502).await;
+
+        let err = handler
+            .apply_changes(Json(create_request(&[
+                "app.blocked.com",
+                "app.example.com",
+            ])))
+            .await
+            .expect_err("both creates should fail");
+        assert!(
+            err.is_retryable(),
+            "expected a retryable error, got: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn apply_changes_rejected_by_njalla_is_permanent() {
+        let mut njalla = MockNjalla::new().await;
+        njalla.reject(400, "invalid content").await;
+        let handler = njalla.handler(testing::config());
+
+        let err = handler
+            .apply_changes(Json(create_request(&["app.example.com"])))
+            .await
+            .expect_err("invalid record should fail");
+        assert!(
+            matches!(err, Error::Rejected(_)),
+            "expected Error::Rejected, got: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_records_fails_softly_when_njalla_is_down() {
+        let (_njalla, handler) = failing_upstream_handler(503).await;
+
+        let err = handler
+            .get_records(Query(GetRecordsQuery { zone_name: None }))
+            .await
+            .expect_err("listing should fail rather than return partial records");
+        assert!(
+            err.is_retryable(),
+            "expected a retryable error, got: {err:?}"
+        );
+    }
+
+    #[tokio::test]
+    async fn get_records_for_disallowed_zone_is_permanent() {
+        let handler = test_handler();
+        let err = handler
+            .get_records(Query(GetRecordsQuery {
+                zone_name: Some("blocked.com".to_string()),
+            }))
+            .await
+            .expect_err("zone outside the filter");
+        assert_eq!(err.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn batch_report_with_unstarted_changes_is_a_timeout() {
+        let mut report = BatchReport::default();
+        report.record("Delete a.example.com".into(), Ok(true));
+        report.record("Delete b.example.com".into(), Ok(false));
+        report.not_attempted("Create c.example.com".into());
+
+        assert_eq!(
+            report.summary(),
+            "1 done, 1 skipped, 0 failed, 1 not attempted"
+        );
+        assert_eq!(report.incomplete(), ["Create c.example.com: not attempted"]);
+        let err = report.into_result().expect_err("batch was cut short");
+        assert!(matches!(err, Error::Timeout(_)), "{err:?}");
+        assert_eq!(err.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn batch_report_with_only_done_or_skipped_changes_succeeds() {
+        let mut report = BatchReport::default();
+        report.record("Create a.example.com".into(), Ok(true));
+        report.record("Create b.example.com".into(), Ok(false));
+        assert_eq!(report.into_result().unwrap(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn expired_deadline_leaves_changes_not_attempted() {
+        let mut njalla = MockNjalla::new().await;
+        // Every change starts by reading its zone.
+        let untouched = njalla
+            .expect("list-records", json!({}), json!({"records": []}), 0)
+            .await;
+        let handler = njalla.handler(testing::config());
+
+        // A batch whose deadline has already passed starts nothing.
+        let batch = Batch::new(Budget::new(Some(Duration::ZERO), None), None);
+        let changes = create_request(&["a.example.com", "b.example.com"]).into_changes();
+        let err = handler
+            .apply_batch(&changes, &batch)
+            .await
+            .expect_err("deadline should cut the batch short");
+        assert!(matches!(err, Error::Timeout(_)), "{err:?}");
+        assert!(err.to_string().contains("not attempted"), "{err}");
+        untouched.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn create_leaves_a_live_twin_with_another_ttl_alone() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "7", "name": "www", "type": "A", "content": "192.0.2.10", "ttl": 300}
+            ]))
+            .await;
+        let untouched = njalla
+            .expect("remove-record", json!({}), json!({}), 0)
+            .await;
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"content": "192.0.2.11"}),
+                json!({"id": "8", "name": "www", "type": "A", "content": "192.0.2.11"}),
+                1,
+            )
+            .await;
+        let handler = njalla.handler(testing::config());
+
+        let request = serde_json::from_value(json!({
+            "create": [{"dnsName": "www.example.com", "targets": ["192.0.2.10", "192.0.2.11"],
+                        "recordType": "A", "recordTTL": 60}]
+        }))
+        .unwrap();
+        let status = handler.apply_changes(Json(request)).await.unwrap();
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        untouched.assert_async().await;
+        added.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn live_records_match_endpoints_regardless_of_case() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "7", "name": "WWW", "type": "a", "content": "192.0.2.10", "ttl": 3600}
+            ]))
+            .await;
+        let added = njalla.expect("add-record", json!({}), json!({}), 0).await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "7"}), json!({}), 1)
+            .await;
+        let handler = njalla.handler(testing::config());
+
+        let endpoint = json!({"dnsName": "www.example.com", "targets": ["192.0.2.10"],
+                              "recordType": "A"});
+        let create = serde_json::from_value(json!({ "create": [endpoint.clone()] })).unwrap();
+        assert_eq!(
+            handler.apply_changes(Json(create)).await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        added.assert_async().await;
+
+        let delete = serde_json::from_value(json!({ "delete": [endpoint] })).unwrap();
+        assert_eq!(
+            handler.apply_changes(Json(delete)).await.unwrap(),
+            StatusCode::NO_CONTENT
+        );
+        removed.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn journaled_update_replaces_a_record_with_the_same_content() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "7", "name": "www", "type": "A", "content": "192.0.2.10", "ttl": 3600}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "7"}), json!({}), 1)
+            .await;
+        // Only the TTL changes; the old record is being removed, so it mustn't count as
+        // "already exists".
+        let added = njalla
+            .expect(
+                "add-record",
+                json!({"ttl": 60}),
+                json!({"id": "8", "name": "www", "type": "A", "content": "192.0.2.10", "ttl": 60}),
+                1,
+            )
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let journal_path = dir.path().join("journal");
+        let (journal, _) = Journal::open(&journal_path).unwrap();
+        let handler = njalla
+            .handler(testing::config())
+            .with_journal(Arc::new(journal));
+
+        let endpoint = |ttl: i64| {
+            json!({"dnsName": "www.example.com", "targets": ["192.0.2.10"],
+                   "recordType": "A", "recordTTL": ttl})
+        };
+        let request = serde_json::from_value(json!({
+            "updateOld": [endpoint(3600)],
+            "updateNew": [endpoint(60)],
+        }))
+        .unwrap();
+        let status = handler.apply_changes(Json(request)).await.unwrap();
+
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        removed.assert_async().await;
+        added.assert_async().await;
+        assert_eq!(std::fs::read_to_string(&journal_path).unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn a_timed_out_request_abandons_its_journaled_change() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "7", "name": "www", "type": "A", "content": "192.0.2.10", "ttl": 3600}
+            ]))
+            .await;
+        let removed = njalla
+            .expect("remove-record", json!({"id": "7"}), json!({}), 1)
+            .await;
+        njalla
+            .stall("add-record", json!({}), Duration::from_secs(1))
+            .await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let journal_path = dir.path().join("journal");
+        let (journal, _) = Journal::open(&journal_path).unwrap();
+        let handler = njalla
+            .handler(testing::config())
+            .with_journal(Arc::new(journal));
+
+        let endpoint = |ttl: i64| {
+            json!({"dnsName": "www.example.com", "targets": ["192.0.2.10"],
+                   "recordType": "A", "recordTTL": ttl})
+        };
+        let request = serde_json::from_value(json!({
+            "updateOld": [endpoint(3600)],
+            "updateNew": [endpoint(60)],
+        }))
+        .unwrap();
+        // Gives up after the removal, while the add is in flight, like the request timeout.
+        let apply = handler.apply_changes(Json(request));
+        assert!(tokio::time::timeout(Duration::from_millis(200), apply)
+            .await
+            .is_err());
+        removed.assert_async().await;
+
+        // The change is closed as abandoned on the blocking pool rather than left for
+        // recovery to replay.
+        for _ in 0..100 {
+            if std::fs::read_to_string(&journal_path).unwrap().is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(std::fs::read_to_string(&journal_path).unwrap(), "");
     }
 }
@@ -0,0 +1,350 @@
+//! Media type and version negotiation for the external-dns webhook protocol.
+//!
+//! external-dns sends `Accept` and `Content-Type` as
+//! `application/external.dns.webhook+json;version=<n>`. [`negotiate_media_type`] checks both
+//! for every provider route and labels successful responses with the negotiated media type.
+//! The versions it settles on go into the request extensions as [`Negotiated`], so supporting a
+//! new payload version means adding an [`ApiVersion`] variant and having the handlers whose
+//! payload shape differs extract it.
+
+use crate::error::error_response;
+use axum::{
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, Method, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+
+/// Media type of the webhook protocol, without the version parameter.
+pub const MEDIA_TYPE: &str = "application/external.dns.webhook+json";
+
+/// A version of the webhook payload format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ApiVersion {
+    V1,
+}
+
+impl ApiVersion {
+    /// Every version we can speak, oldest first.
+    pub const SUPPORTED: &'static [ApiVersion] = &[ApiVersion::V1];
+
+    /// Used when a client accepts or sends the media type (or plain JSON) without a version.
+    pub const DEFAULT: ApiVersion = ApiVersion::V1;
+
+    pub fn from_number(version: &str) -> Option<Self> {
+        match version.trim() {
+            "1" => Some(Self::V1),
+            _ => None,
+        }
+    }
+
+    pub fn number(self) -> u32 {
+        match self {
+            Self::V1 => 1,
+        }
+    }
+
+    /// The full media type, e.g. `application/external.dns.webhook+json;version=1`.
+    pub fn media_type(self) -> String {
+        format!("{MEDIA_TYPE};version={}", self.number())
+    }
+}
+
+/// The versions [`negotiate_media_type`] settled on for a request, available to handlers as an
+/// `Extension<Negotiated>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Negotiated {
+    /// The version the response is written in, from `Accept`.
+    pub response: ApiVersion,
+    /// The version of the request body, from `Content-Type`; `None` for requests without one
+    /// (anything but POST).
+    pub request: Option<ApiVersion>,
+}
+
+/// One media type from a header: lowercased `type/subtype` plus its parameters.
+struct MediaRange<'a> {
+    essence: String,
+    params: Vec<(String, &'a str)>,
+}
+
+impl<'a> MediaRange<'a> {
+    fn parse(s: &'a str) -> Self {
+        let mut parts = s.split(';');
+        let essence = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let params = parts
+            .filter_map(|p| p.split_once('='))
+            .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().trim_matches('"')))
+            .collect();
+        Self { essence, params }
+    }
+
+    fn param(&self, name: &str) -> Option<&'a str> {
+        self.params.iter().find(|(k, _)| k == name).map(|(_, v)| *v)
+    }
+
+    /// Which version this range selects: `Some(None)` for a range that accepts any version
+    /// (wildcards, plain JSON), `None` for a range we can't serve at all.
+    fn version(&self) -> Option<Option<ApiVersion>> {
+        match self.essence.as_str() {
+            MEDIA_TYPE => match self.param("version") {
+                Some(v) => ApiVersion::from_number(v).map(Some),
+                None => Some(None),
+            },
+            "*/*" | "application/*" | "application/json" => Some(None),
+            _ => None,
+        }
+    }
+}
+
+/// Pick the response version from an `Accept` header: the highest-quality range we can serve,
+/// preferring the newest version on ties. A missing header accepts anything.
+pub fn negotiate_accept(accept: Option<&str>) -> Option<ApiVersion> {
+    let Some(accept) = accept.filter(|a| !a.trim().is_empty()) else {
+        return Some(ApiVersion::DEFAULT);
+    };
+
+    let mut best: Option<(f32, ApiVersion)> = None;
+    for range in accept.split(',').map(MediaRange::parse) {
+        let quality = range
+            .param("q")
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if quality <= 0.0 {
+            continue;
+        }
+        let Some(version) = range.version() else {
+            continue;
+        };
+        let version = version.unwrap_or(ApiVersion::DEFAULT);
+        if best.is_none_or(|(q, v)| (quality, version) > (q, v)) {
+            best = Some((quality, version));
+        }
+    }
+    best.map(|(_, version)| version)
+}
+
+/// The version of a request body from its `Content-Type`. A body without one is taken to be
+/// in the default version, as external-dns doesn't always label it.
+pub fn negotiate_content_type(content_type: Option<&str>) -> Option<ApiVersion> {
+    let Some(content_type) = content_type else {
+        return Some(ApiVersion::DEFAULT);
+    };
+    let range = MediaRange::parse(content_type);
+    match range.essence.as_str() {
+        "*/*" | "application/*" => None,
+        _ => range.version().map(|v| v.unwrap_or(ApiVersion::DEFAULT)),
+    }
+}
+
+fn supported_media_types() -> String {
+    ApiVersion::SUPPORTED
+        .iter()
+        .map(|v| v.media_type())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn header_str(headers: &HeaderMap, name: header::HeaderName) -> Option<&str> {
+    headers.get(name).and_then(|v| v.to_str().ok())
+}
+
+/// Reject requests whose `Accept` (406) or `Content-Type` (415) names no version we support,
+/// and answer successful requests with the negotiated versioned media type. A POST without a
+/// `Content-Type` is labelled with the default media type so the JSON extractors accept it.
+/// The versions are recorded in the request extensions as [`Negotiated`].
+pub async fn negotiate_media_type(mut request: Request, next: Next) -> Response {
+    let headers = request.headers();
+
+    let Some(response) = negotiate_accept(header_str(headers, header::ACCEPT)) else {
+        return error_response(
+            StatusCode::NOT_ACCEPTABLE,
+            format!(
+                "cannot produce any media type in Accept; supported: {}",
+                supported_media_types()
+            ),
+        );
+    };
+
+    let mut negotiated = Negotiated {
+        response,
+        request: None,
+    };
+    if request.method() == Method::POST {
+        let content_type = header_str(headers, header::CONTENT_TYPE);
+        let Some(version) = negotiate_content_type(content_type) else {
+            return error_response(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                format!(
+                    "unsupported Content-Type; expected one of: {}",
+                    supported_media_types()
+                ),
+            );
+        };
+        if content_type.is_none() {
+            let media_type = HeaderValue::from_str(&version.media_type())
+                .expect("media types are valid header values");
+            request
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, media_type);
+        }
+        negotiated.request = Some(version);
+    }
+    request.extensions_mut().insert(negotiated);
+
+    let mut res = next.run(request).await;
+    // Only relabel JSON success bodies; errors keep `application/json` and 204s have no body.
+    let is_json = header_str(res.headers(), header::CONTENT_TYPE)
+        .is_some_and(|ct| ct.starts_with("application/json") || ct.starts_with(MEDIA_TYPE));
+    if res.status().is_success() && is_json {
+        let media_type = HeaderValue::from_str(&response.media_type())
+            .expect("media types are valid header values");
+        res.headers_mut().insert(header::CONTENT_TYPE, media_type);
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        routing::{get, post},
+        Extension, Json, Router,
+    };
+    use tower::ServiceExt;
+
+    const V1: &str = "application/external.dns.webhook+json;version=1";
+
+    #[test]
+    fn accept_without_header_or_with_wildcards_defaults_to_v1() {
+        assert_eq!(negotiate_accept(None), Some(ApiVersion::V1));
+        assert_eq!(negotiate_accept(Some("*/*")), Some(ApiVersion::V1));
+        assert_eq!(
+            negotiate_accept(Some("application/json")),
+            Some(ApiVersion::V1)
+        );
+    }
+
+    #[test]
+    fn accept_picks_supported_version() {
+        assert_eq!(negotiate_accept(Some(V1)), Some(ApiVersion::V1));
+        assert_eq!(
+            negotiate_accept(Some(
+                "application/external.dns.webhook+json; version=\"2\", \
+                 application/external.dns.webhook+json;version=1;q=0.5"
+            )),
+            Some(ApiVersion::V1)
+        );
+    }
+
+    #[test]
+    fn accept_rejects_unsupported_versions_and_types() {
+        assert_eq!(
+            negotiate_accept(Some("application/external.dns.webhook+json;version=2")),
+            None
+        );
+        assert_eq!(negotiate_accept(Some("text/html")), None);
+        assert_eq!(negotiate_accept(Some("application/json;q=0")), None);
+    }
+
+    #[test]
+    fn content_type_versions() {
+        assert_eq!(negotiate_content_type(Some(V1)), Some(ApiVersion::V1));
+        assert_eq!(
+            negotiate_content_type(Some("application/json; charset=utf-8")),
+            Some(ApiVersion::V1)
+        );
+        assert_eq!(
+            negotiate_content_type(Some("application/external.dns.webhook+json;version=3")),
+            None
+        );
+        assert_eq!(negotiate_content_type(Some("text/plain")), None);
+        assert_eq!(negotiate_content_type(None), Some(ApiVersion::V1));
+    }
+
+    fn app() -> Router {
+        Router::new()
+            .route("/records", get(|| async { Json(Vec::<u8>::new()) }))
+            .route("/records", post(|| async { StatusCode::NO_CONTENT }))
+            .layer(axum::middleware::from_fn(negotiate_media_type))
+    }
+
+    async fn send(request: axum::http::request::Builder) -> Response {
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn json_responses_carry_versioned_media_type() {
+        let res = send(Request::get("/records").header("accept", V1)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], V1);
+    }
+
+    #[tokio::test]
+    async fn unsupported_accept_is_406() {
+        let res = send(
+            Request::get("/records")
+                .header("accept", "application/external.dns.webhook+json;version=9"),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+
+    #[tokio::test]
+    async fn unsupported_content_type_is_415() {
+        let res = send(
+            Request::post("/records")
+                .header("accept", V1)
+                .header("content-type", "text/plain"),
+        )
+        .await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let res = send(Request::post("/records").header("content-type", V1)).await;
+        assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[tokio::test]
+    async fn missing_content_type_is_read_as_the_webhook_media_type() {
+        let app = Router::new()
+            .route(
+                "/records",
+                post(|Json(body): Json<Vec<u8>>| async move { Json(body) }),
+            )
+            .layer(axum::middleware::from_fn(negotiate_media_type));
+        let request = Request::post("/records")
+            .header("accept", V1)
+            .body(Body::from("[1]"))
+            .unwrap();
+        let res = app.oneshot(request).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], V1);
+    }
+
+    #[tokio::test]
+    async fn handlers_see_the_negotiated_versions() {
+        let versions = |Extension(negotiated): Extension<Negotiated>| async move {
+            format!("{:?} {:?}", negotiated.response, negotiated.request)
+        };
+        let app = Router::new()
+            .route("/records", get(versions).post(versions))
+            .layer(axum::middleware::from_fn(negotiate_media_type));
+
+        let request = Request::get("/records").body(Body::empty()).unwrap();
+        let res = app.clone().oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(res.into_body(), 64).await.unwrap();
+        assert_eq!(body, "V1 None");
+
+        let request = Request::post("/records")
+            .header("accept", V1)
+            .header("content-type", V1)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(request).await.unwrap();
+        let body = axum::body::to_bytes(res.into_body(), 64).await.unwrap();
+        assert_eq!(body, "V1 Some(V1)");
+    }
+}
@@ -1,3 +1,7 @@
+pub mod admin;
 pub mod handlers;
+pub mod media;
 pub mod routes;
+#[cfg(test)]
+pub(crate) mod testing;
 pub mod types;
@@ -1,38 +1,190 @@
 use super::handlers::WebhookHandler;
-use crate::config::Config;
-use crate::njalla::Client as NjallaClient;
+use super::media;
+use crate::metrics;
+use arc_swap::ArcSwap;
 use axum::{
+    extract::{Path, Query},
+    http::header,
+    middleware,
     routing::{get, post},
-    Router,
+    Json, Router,
 };
 use std::sync::Arc;
 
-pub fn create_routes(njalla_client: NjallaClient, config: Config) -> Router {
-    let handler = Arc::new(WebhookHandler::new(Arc::new(njalla_client), config));
+/// The active handler. Reloads swap in a new one; each request loads the current handler once,
+/// so in-flight requests finish against the config and client they started with.
+pub type SharedHandler = Arc<ArcSwap<WebhookHandler>>;
 
-    Router::new()
+/// All routes. The external-dns provider routes go through media type negotiation; the health
+/// probes, metrics and the admin API (under `/admin`) don't.
+pub fn create_routes(handler: SharedHandler) -> Router {
+    let provider = Router::new()
         .route("/", {
             let h = handler.clone();
-            get(move || async move { h.negotiate().await })
+            get(move || async move { h.load_full().negotiate().await })
+        })
+        .route("/records", {
+            let h = handler.clone();
+            get(move |query| async move { h.load_full().get_records(query).await })
+        })
+        .route("/records", {
+            let h = handler.clone();
+            post(move |body| async move { h.load_full().apply_changes(body).await })
+        })
+        .route("/plan", {
+            let h = handler.clone();
+            post(move |body| async move { h.load_full().plan(body).await })
+        })
+        .route("/adjustendpoints", {
+            let h = handler.clone();
+            post(move |body| async move { h.load_full().adjust_endpoints(body).await })
         })
+        .route_layer(middleware::from_fn(media::negotiate_media_type));
+
+    let admin = Router::new()
+        .route("/history", {
+            let h = handler.clone();
+            get(move |Query(filter)| async move { h.load_full().history(filter).await })
+        })
+        .route("/history/{batch}/undo", {
+            let h = handler.clone();
+            post(move |Path(batch)| async move { h.load_full().undo_batch(batch).await })
+        })
+        .route("/snapshots", {
+            let h = handler.clone();
+            get(move |Query(query)| async move { h.load_full().list_snapshots(query).await })
+        })
+        .route("/snapshots", {
+            let h = handler.clone();
+            post(move || async move { h.load_full().take_snapshots().await.map(Json) })
+        })
+        .route("/snapshots/{zone}/{id}/restore", {
+            let h = handler.clone();
+            post(
+                move |Path((zone, id)): Path<(String, String)>, Query(query)| async move {
+                    h.load_full().restore_snapshot(&zone, &id, query).await
+                },
+            )
+        })
+        .route("/dedupe", {
+            let h = handler.clone();
+            post(move |Query(query)| async move { h.load_full().dedupe(query).await.map(Json) })
+        })
+        .route("/registry/orphans", {
+            let h = handler.clone();
+            post(move |Query(query)| async move {
+                h.load_full().registry_orphans(query).await.map(Json)
+            })
+        })
+        .route("/registry/migrate", {
+            let h = handler.clone();
+            post(move |Query(query)| async move {
+                h.load_full().migrate_registry(query).await.map(Json)
+            })
+        })
+        .route("/retarget", {
+            let h = handler.clone();
+            post(move |Query(query)| async move { h.load_full().retarget(query).await.map(Json) })
+        });
+
+    Router::new()
+        .merge(provider)
+        .nest(
+            "/admin",
+            admin
+                .merge(zone_routes(&handler))
+                .merge(drift_routes(&handler)),
+        )
         .route("/healthz", {
             let h = handler.clone();
-            get(move || async move { h.health().await })
+            get(move || async move { h.load_full().health().await })
         })
         .route("/ready", {
             let h = handler.clone();
-            get(move || async move { h.ready().await })
+            get(move || async move { h.load_full().ready().await })
         })
-        .route("/records", {
+        .route("/metrics", {
             let h = handler.clone();
-            get(move |query| async move { h.get_records(query).await })
+            get(move || async move {
+                (
+                    [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+                    h.load_full().metrics().await,
+                )
+            })
         })
-        .route("/records", {
+}
+
+/// Views of Njalla zones and records, zone file export and import and templates, under
+/// `/admin`.
+fn zone_routes(handler: &SharedHandler) -> Router {
+    Router::new()
+        .route("/zones", {
             let h = handler.clone();
-            post(move |body| async move { h.apply_changes(body).await })
+            get(move || async move { h.load_full().zones().await })
         })
-        .route("/adjustendpoints", {
+        .route("/zones/{zone}/records", {
+            let h = handler.clone();
+            get(move |Path(zone): Path<String>| async move {
+                h.load_full().zone_records(&zone).await
+            })
+        })
+        .route("/zones/{zone}/export", {
+            let h = handler.clone();
+            get(move |Path(zone): Path<String>| async move {
+                h.load_full().export_zone(&zone).await
+            })
+        })
+        .route("/zones/{zone}/import", {
+            let h = handler.clone();
+            post(
+                move |Path(zone): Path<String>, Query(query), body: String| async move {
+                    h.load_full().import_zone(&zone, &body, query).await
+                },
+            )
+        })
+        .route("/zones/{zone}/templates/{template}", {
+            let h = handler.clone();
+            post(
+                move |Path((zone, template)): Path<(String, String)>, Query(query)| async move {
+                    h.load_full().apply_template(&zone, &template, query).await
+                },
+            )
+        })
+        .route("/search", {
+            let h = handler.clone();
+            get(move |Query(query)| async move { h.load_full().search(query).await })
+        })
+}
+
+/// Drift reports, checks, repairs and accepts, under `/admin`.
+fn drift_routes(handler: &SharedHandler) -> Router {
+    Router::new()
+        .route("/drift", {
+            let h = handler.clone();
+            get(move || async move { h.load_full().drift_reports().await })
+        })
+        .route("/drift", {
+            let h = handler.clone();
+            post(move || async move { h.load_full().check_drift().await.map(Json) })
+        })
+        .route("/drift/templates", {
+            let h = handler.clone();
+            get(move || async move { h.load_full().template_reports().await })
+        })
+        .route("/drift/templates", {
+            let h = handler.clone();
+            post(move || async move { h.load_full().check_templates().await.map(Json) })
+        })
+        .route("/drift/{zone}/repair", {
+            let h = handler.clone();
+            post(move |Path(zone): Path<String>, Query(query)| async move {
+                h.load_full().repair_drift(&zone, query).await
+            })
+        })
+        .route("/drift/{zone}/accept", {
             let h = handler.clone();
-            post(move |body| async move { h.adjust_endpoints(body).await })
+            post(move |Path(zone): Path<String>| async move {
+                h.load_full().accept_drift(&zone).await
+            })
         })
 }
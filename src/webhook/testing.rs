@@ -0,0 +1,144 @@
+//! Fixtures for handler tests: a mocked Njalla JSON-RPC API and handlers that talk to it.
+
+use super::handlers::WebhookHandler;
+use crate::config::Config;
+use crate::njalla::Client as NjallaClient;
+use crate::secret::SecretString;
+use mockito::{Matcher, Mock, ServerGuard};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// The configuration of a handler that manages example.com only.
+pub fn config() -> Config {
+    Config {
+        njalla_api_token: SecretString::from("token"),
+        domain_filter: Some(vec!["example.com".to_string()]),
+        ..Config::default()
+    }
+}
+
+/// A handler whose Njalla client points at the real API, for tests that never reach it.
+pub fn offline_handler(config: Config) -> WebhookHandler {
+    let client = NjallaClient::new(&SecretString::from("token"), 0, Duration::ZERO)
+        .expect("client should build");
+    WebhookHandler::new(Arc::new(client), config)
+}
+
+/// A mocked Njalla API. Calls are matched on their JSON-RPC method and a subset of their
+/// params; when several mocks match, the first one created answers.
+pub struct MockNjalla {
+    server: ServerGuard,
+}
+
+impl MockNjalla {
+    pub async fn new() -> Self {
+        Self {
+            server: mockito::Server::new_async().await,
+        }
+    }
+
+    /// Answer `list-records` for any zone with `records`.
+    pub async fn records(&mut self, records: Value) -> Mock {
+        self.zone_records(None, records).await
+    }
+
+    /// Answer `list-records` for `zone` (any zone if `None`) with `records`.
+    pub async fn zone_records(&mut self, zone: Option<&str>, records: Value) -> Mock {
+        let params = zone.map_or_else(|| json!({}), |zone| json!({ "domain": zone }));
+        self.answer("list-records", params, json!({ "records": records }))
+            .await
+    }
+
+    /// Answer every call of `method` whose params include `params` with `result`.
+    pub async fn answer(&mut self, method: &str, params: Value, result: Value) -> Mock {
+        self.mock(method, params, result).create_async().await
+    }
+
+    /// Like [`Self::answer`], for a call that must be made exactly `hits` times.
+    pub async fn expect(
+        &mut self,
+        method: &str,
+        params: Value,
+        result: Value,
+        hits: usize,
+    ) -> Mock {
+        self.mock(method, params, result)
+            .expect(hits)
+            .create_async()
+            .await
+    }
+
+    /// Answer every call with HTTP `status`.
+    pub async fn fail(&mut self, status: usize) -> Mock {
+        self.server
+            .mock("POST", "/")
+            .with_status(status)
+            .with_body("upstream trouble")
+            .create_async()
+            .await
+    }
+
+    /// Answer every call with the JSON-RPC error `code`.
+    pub async fn reject(&mut self, code: i32, message: &str) -> Mock {
+        let error = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": code, "message": message}});
+        self.server
+            .mock("POST", "/")
+            .with_body(error.to_string())
+            .create_async()
+            .await
+    }
+
+    /// Answer every call of `method` whose params include `params` with the JSON-RPC error
+    /// `code`.
+    pub async fn reject_call(
+        &mut self,
+        method: &str,
+        params: Value,
+        code: i32,
+        message: &str,
+    ) -> Mock {
+        let error = json!({"jsonrpc": "2.0", "id": 1, "error": {"code": code, "message": message}});
+        self.matching(method, params)
+            .with_body(error.to_string())
+            .create_async()
+            .await
+    }
+
+    /// Keep every call of `method` whose params include `params` waiting for `delay` before
+    /// it fails, for tests that give up on a call in flight.
+    pub async fn stall(&mut self, method: &str, params: Value, delay: Duration) -> Mock {
+        self.matching(method, params)
+            .with_chunked_body(move |_| {
+                std::thread::sleep(delay);
+                Err(std::io::ErrorKind::TimedOut.into())
+            })
+            .create_async()
+            .await
+    }
+
+    fn mock(&mut self, method: &str, params: Value, result: Value) -> Mock {
+        self.matching(method, params)
+            .with_body(json!({ "jsonrpc": "2.0", "id": 1, "result": result }).to_string())
+    }
+
+    fn matching(&mut self, method: &str, params: Value) -> Mock {
+        self.server
+            .mock("POST", "/")
+            .match_body(Matcher::PartialJson(
+                json!({ "method": method, "params": params }),
+            ))
+    }
+
+    /// A handler with `config` that calls this API, without retries.
+    pub fn handler(&self, config: Config) -> WebhookHandler {
+        let client = NjallaClient::with_api_url(
+            &SecretString::from("token"),
+            0,
+            Duration::ZERO,
+            &self.server.url(),
+        )
+        .expect("client should build");
+        WebhookHandler::new(Arc::new(client), config)
+    }
+}
@@ -244,3 +244,237 @@ mod tests {
         assert_eq!(changes.create[0].dns_name, "wrapped.example.com");
     }
 }
+
+/// Result of undoing a batch through the admin API.
+#[derive(Debug, Serialize)]
+pub struct UndoResponse {
+    /// The new batch the inverse operations were applied as.
+    pub batch: uuid::Uuid,
+    pub undoes: uuid::Uuid,
+    pub dry_run: bool,
+    pub applied: Vec<String>,
+    /// Operations of the original batch with nothing left to revert.
+    pub skipped: Vec<String>,
+}
+
+/// Query of `GET /admin/snapshots`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnapshotQuery {
+    pub zone: Option<String>,
+}
+
+/// Query of admin endpoints that plan a change (snapshot restore, drift repair).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ApplyQuery {
+    /// Apply the plan; without it the endpoint only reports what it would do.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Result of a snapshot restore: the plan, and whether it was carried out.
+#[derive(Debug, Serialize)]
+pub struct RestoreResponse {
+    pub snapshot: String,
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// Query of `POST /admin/dedupe`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DedupeQuery {
+    /// Only this zone; all managed zones when unset.
+    pub zone: Option<String>,
+    /// Remove the duplicates; without it the run only reports them.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Query of `POST /admin/registry/orphans`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OrphanQuery {
+    /// Only this zone; all managed zones when unset.
+    pub zone: Option<String>,
+    /// Only delete orphans owned by this external-dns `--txt-owner-id`.
+    pub owner: Option<String>,
+    /// Delete the orphaned ownership records; without it the run only reports them.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Ownership records of one zone without a record to own, and records without an owner.
+#[derive(Debug, Serialize)]
+pub struct OrphanReport {
+    pub zone: String,
+    pub orphaned: Vec<crate::registry::RegistryRecord>,
+    pub unowned: Vec<crate::njalla::DnsRecord>,
+    /// Orphans deleted by this run; 0 for a report.
+    pub removed: usize,
+    /// Why the zone couldn't be read or cleaned up; other zones carry on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Query of `POST /admin/registry/migrate`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MigrateQuery {
+    /// Only this zone; all managed zones when unset.
+    pub zone: Option<String>,
+    /// Remove each old-format record once its replacements exist.
+    #[serde(default)]
+    pub remove_old: bool,
+    /// Apply the plan; without it the migration only reports what it would do.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Progress of the registry migration of one zone.
+#[derive(Debug, Serialize)]
+pub struct MigrationReport {
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    /// Why the zone couldn't be migrated; other zones carry on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// Result of applying a template: the records to add, and whether they were added.
+#[derive(Debug, Serialize)]
+pub struct TemplateResponse {
+    pub template: String,
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// The Njalla operations a set of external-dns changes makes, none of them carried out.
+/// Returned by `POST /plan`.
+#[derive(Debug, Serialize)]
+pub struct PlanResponse {
+    #[serde(flatten)]
+    pub counts: crate::planner::Counts,
+    pub operations: Vec<crate::journal::Operation>,
+}
+
+impl PlanResponse {
+    pub fn new(operations: Vec<crate::journal::Operation>) -> Self {
+        Self {
+            counts: crate::planner::Counts::of(&operations),
+            operations,
+        }
+    }
+}
+
+/// Result of syncing a zone to a desired-state file: the plan, and whether it was carried out.
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// Query of `POST /admin/zones/{zone}/import`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportQuery {
+    /// Also remove live records the file doesn't list. Without it, the import only adds and
+    /// replaces.
+    #[serde(default)]
+    pub prune: bool,
+    /// Apply the plan; without it the endpoint only reports what it would do.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Result of importing a zone file: the plan, what the file held that was left out, and
+/// whether the plan was carried out.
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    /// Records listed more than once in the file.
+    pub duplicates: Vec<String>,
+    /// Records Njalla manages itself (SOA, apex NS).
+    pub ignored: Vec<String>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// Result of a drift repair: the plan back to the baseline, and whether it was carried out.
+#[derive(Debug, Serialize)]
+pub struct RepairResponse {
+    pub applied: bool,
+    /// History batch of the applied plan.
+    pub batch: Option<uuid::Uuid>,
+    #[serde(flatten)]
+    pub plan: crate::planner::Plan,
+}
+
+/// Query of `GET /admin/search`. Every given criterion must match.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchQuery {
+    /// Case-insensitive substring of the record's full DNS name.
+    pub name: Option<String>,
+    /// Record type, e.g. `TXT`.
+    #[serde(rename = "type")]
+    pub record_type: Option<String>,
+    /// Substring of the record content.
+    pub content: Option<String>,
+}
+
+/// A record found by `GET /admin/search`.
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub zone: String,
+    /// Full DNS name of the record.
+    pub dns_name: String,
+    pub record: crate::njalla::DnsRecord,
+}
+
+/// Query of `POST /admin/retarget`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetargetQuery {
+    /// The old target: an address, a host name or a network like `192.0.2.0/24`.
+    pub from: String,
+    /// The new target, of the same kind as `from`.
+    pub to: String,
+    /// Only this zone; all managed zones when unset.
+    pub zone: Option<String>,
+    /// Edit the records; without it the run only reports the plan.
+    #[serde(default)]
+    pub apply: bool,
+}
+
+/// Result of a retarget run: the edits per zone, and how far applying them got.
+#[derive(Debug, Serialize)]
+pub struct RetargetResponse {
+    /// The mapping, as `from -> to`.
+    pub mapping: String,
+    pub applied: bool,
+    /// History batch of the applied edits.
+    pub batch: Option<uuid::Uuid>,
+    /// Records edited by this run; 0 for a report.
+    pub edited: usize,
+    /// Why applying stopped early.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Plans of the zones with something to edit or skip.
+    pub plans: Vec<crate::planner::Plan>,
+}
@@ -0,0 +1,722 @@
+//! RFC 1035 master file ("BIND zone file") rendering and parsing of Njalla records.
+//!
+//! Rendering writes names relative to `$ORIGIN`, MX and SRV priorities in front of the content
+//! and host names fully qualified. Parsing reads the same syntax back into Njalla's shape (host
+//! names without the root dot, priorities split off), so a zone can be exported, kept in git
+//! and imported again. SOA and apex NS records belong to Njalla and are left out of imports.
+
+use crate::config::{Config, KNOWN_RECORD_TYPES};
+use crate::error::{self, Error};
+use crate::njalla::DnsRecord;
+use crate::planner::{self, fqdn, DEFAULT_TTL};
+use crate::webhook::handlers::{Batch, WebhookHandler};
+use crate::webhook::types::{ImportQuery, ImportResponse};
+use axum::Json;
+use std::collections::HashMap;
+use std::fmt::Write;
+use tracing::{info, warn};
+
+/// Record types whose content is a host name, written fully qualified.
+const HOST_TYPES: &[&str] = &["CNAME", "MX", "NS", "PTR", "SRV"];
+
+/// Render `records` as a master file for `zone`. Names are relative to `$ORIGIN` (`@` for the
+/// apex), MX and SRV priorities precede the content and host names get a trailing dot.
+pub fn render(zone: &str, records: &[DnsRecord], comment: &str) -> String {
+    let mut out = String::new();
+    if !comment.is_empty() {
+        for line in comment.lines() {
+            let _ = writeln!(out, "; {line}");
+        }
+    }
+    let _ = writeln!(out, "$ORIGIN {zone}.");
+    let _ = writeln!(out, "$TTL {DEFAULT_TTL}");
+
+    let mut sorted: Vec<&DnsRecord> = records.iter().collect();
+    sorted.sort_by(|a, b| {
+        (owner(&a.name), &a.record_type, &a.content).cmp(&(
+            owner(&b.name),
+            &b.record_type,
+            &b.content,
+        ))
+    });
+    for record in sorted {
+        let _ = writeln!(
+            out,
+            "{}\t{}\tIN\t{}\t{}",
+            owner(&record.name),
+            record.ttl.unwrap_or(DEFAULT_TTL),
+            record.record_type,
+            rdata(record)
+        );
+    }
+    out
+}
+
+fn owner(name: &str) -> &str {
+    if name.is_empty() {
+        "@"
+    } else {
+        name
+    }
+}
+
+fn rdata(record: &DnsRecord) -> String {
+    let content = match record.record_type.as_str() {
+        "TXT" => quote_txt(&record.content),
+        t if HOST_TYPES.contains(&t) => fully_qualified(&record.content),
+        _ => record.content.clone(),
+    };
+    match (record.record_type.as_str(), record.priority) {
+        ("MX" | "SRV", Some(priority)) => format!("{priority} {content}"),
+        _ => content,
+    }
+}
+
+/// SRV content is `weight port target`; only the last word is a host name.
+fn fully_qualified(content: &str) -> String {
+    match content.rsplit_once(' ') {
+        Some((rest, host)) => format!("{rest} {}", fully_qualified(host)),
+        None if content.ends_with('.') => content.to_string(),
+        None => format!("{content}."),
+    }
+}
+
+/// Quote TXT content, escaping quotes and backslashes and splitting it into the 255-byte
+/// character strings the format allows.
+fn quote_txt(content: &str) -> String {
+    let bytes = content.as_bytes();
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() || chunks.is_empty() {
+        let mut end = (start + 255).min(bytes.len());
+        while !content.is_char_boundary(end) {
+            end -= 1;
+        }
+        let chunk = content[start..end]
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        chunks.push(format!("\"{chunk}\""));
+        start = end;
+    }
+    chunks.join(" ")
+}
+
+/// The records of a master file, as Njalla records.
+#[derive(Debug, Default)]
+pub struct Parsed {
+    /// Records with names relative to the zone and without ids, each once.
+    pub records: Vec<DnsRecord>,
+    /// Records listed more than once; only the first is kept.
+    pub duplicates: Vec<String>,
+    /// Records Njalla manages itself (SOA, apex NS), left out.
+    pub ignored: Vec<String>,
+}
+
+/// A word of a master file entry; quoted words may hold spaces.
+#[derive(Debug)]
+struct Token {
+    text: String,
+    quoted: bool,
+}
+
+/// One entry: a line, or several joined by parentheses.
+#[derive(Debug)]
+struct Entry {
+    line: usize,
+    /// Starts with blank space, so the owner is the previous entry's.
+    indented: bool,
+    tokens: Vec<Token>,
+}
+
+/// Parse the master file `text` for `zone`. Records without a TTL get the last `$TTL`, or
+/// `default_ttl` before any. Errors name the line; a CNAME sharing its name with other records
+/// is an error too, so a bad file is refused as a whole.
+pub fn parse(zone: &str, text: &str, default_ttl: u32) -> Result<Parsed, String> {
+    let zone = zone.strip_suffix('.').unwrap_or(zone).to_ascii_lowercase();
+    let mut origin = zone.clone();
+    let mut ttl = default_ttl;
+    let mut last_owner: Option<String> = None;
+    let mut first_seen: HashMap<(String, String, String), usize> = HashMap::new();
+    let mut parsed = Parsed::default();
+
+    for entry in entries(text)? {
+        let line = entry.line;
+        let at = |msg: String| format!("line {line}: {msg}");
+        let Some(first) = entry.tokens.first() else {
+            continue;
+        };
+        if !first.quoted && first.text.starts_with('$') && !entry.indented {
+            let argument = entry.tokens.get(1).map(|t| t.text.as_str());
+            match (first.text.to_ascii_uppercase().as_str(), argument) {
+                ("$ORIGIN", Some(name)) => origin = absolute(name, &origin),
+                ("$TTL", Some(value)) => {
+                    ttl = parse_ttl(value).ok_or_else(|| at(format!("invalid TTL '{value}'")))?;
+                }
+                (directive @ ("$ORIGIN" | "$TTL"), None) => {
+                    return Err(at(format!("{directive} needs a value")))
+                }
+                (directive, _) => return Err(at(format!("{directive} is not supported"))),
+            }
+            continue;
+        }
+
+        let mut rest = &entry.tokens[..];
+        let owner = if entry.indented {
+            last_owner
+                .clone()
+                .ok_or_else(|| at("record without an owner name".to_string()))?
+        } else {
+            rest = &rest[1..];
+            absolute(&first.text, &origin)
+        };
+        last_owner = Some(owner.clone());
+
+        // TTL and class come in either order, both optional.
+        let mut record_ttl = None;
+        while let Some(token) = rest.first().filter(|t| !t.quoted) {
+            if token.text.eq_ignore_ascii_case("IN") {
+                rest = &rest[1..];
+            } else if record_ttl.is_none() && token.text.starts_with(|c: char| c.is_ascii_digit()) {
+                record_ttl = Some(
+                    parse_ttl(&token.text)
+                        .ok_or_else(|| at(format!("invalid TTL '{}'", token.text)))?,
+                );
+                rest = &rest[1..];
+            } else {
+                break;
+            }
+        }
+        let Some((record_type, rdata)) = rest.split_first() else {
+            return Err(at("missing record type".to_string()));
+        };
+        let record_type = record_type.text.to_ascii_uppercase();
+        let name =
+            relative(&owner, &zone).ok_or_else(|| at(format!("{owner} is outside zone {zone}")))?;
+
+        if record_type == "SOA" || (record_type == "NS" && name.is_empty()) {
+            parsed.ignored.push(format!(
+                "line {line}: {record_type} {owner}: managed by Njalla"
+            ));
+            continue;
+        }
+        if !KNOWN_RECORD_TYPES.contains(&record_type.as_str()) {
+            return Err(at(format!("record type {record_type} is not supported")));
+        }
+        let (priority, content) = content(&record_type, rdata, &origin).map_err(at)?;
+        let record = DnsRecord {
+            id: String::new(),
+            name,
+            record_type,
+            content,
+            ttl: Some(record_ttl.unwrap_or(ttl)),
+            priority,
+        };
+
+        let key = (
+            record.name.to_ascii_lowercase(),
+            record.record_type.clone(),
+            record.content.clone(),
+        );
+        match first_seen.get(&key) {
+            Some(first) => parsed.duplicates.push(format!(
+                "line {line}: {} {} {} repeats line {first}",
+                fqdn(&record.name, &zone),
+                record.record_type,
+                record.content
+            )),
+            None => {
+                first_seen.insert(key, line);
+                parsed.records.push(record);
+            }
+        }
+    }
+
+    let conflicts = conflicts(&parsed.records, &zone);
+    if !conflicts.is_empty() {
+        return Err(conflicts.join("; "));
+    }
+    Ok(parsed)
+}
+
+/// Split `text` into entries, dropping comments and joining parenthesized lines.
+fn entries(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    let mut current: Option<Entry> = None;
+    let mut depth = 0usize;
+    for (i, line) in text.lines().enumerate() {
+        let line_no = i + 1;
+        let mut tokens = Vec::new();
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => break,
+                '(' => depth += 1,
+                ')' => {
+                    depth = depth
+                        .checked_sub(1)
+                        .ok_or_else(|| format!("line {line_no}: unbalanced ')'"))?;
+                }
+                '"' => {
+                    let mut bytes = Vec::new();
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => unescape(&mut chars, &mut bytes)
+                                .ok_or_else(|| format!("line {line_no}: invalid escape"))?,
+                            Some(c) => bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                            None => return Err(format!("line {line_no}: unterminated quote")),
+                        }
+                    }
+                    tokens.push(Token {
+                        text: utf8(bytes, line_no)?,
+                        quoted: true,
+                    });
+                }
+                c if c.is_whitespace() => {}
+                c => {
+                    let mut bytes = Vec::new();
+                    let mut next = Some(c);
+                    while let Some(c) = next {
+                        if c == '\\' {
+                            unescape(&mut chars, &mut bytes)
+                                .ok_or_else(|| format!("line {line_no}: invalid escape"))?;
+                        } else {
+                            bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        next = chars.next_if(|&c| {
+                            !c.is_whitespace() && !matches!(c, ';' | '(' | ')' | '"')
+                        });
+                    }
+                    tokens.push(Token {
+                        text: utf8(bytes, line_no)?,
+                        quoted: false,
+                    });
+                }
+            }
+        }
+        match current.as_mut() {
+            Some(entry) => entry.tokens.extend(tokens),
+            None if !tokens.is_empty() || depth > 0 => {
+                current = Some(Entry {
+                    line: line_no,
+                    indented: line.starts_with([' ', '\t']),
+                    tokens,
+                });
+            }
+            None => {}
+        }
+        if depth == 0 {
+            entries.extend(current.take());
+        }
+    }
+    match current {
+        Some(entry) => Err(format!("line {}: unbalanced '('", entry.line)),
+        None => Ok(entries),
+    }
+}
+
+/// Decode the escape after a backslash: `\DDD` is a byte in decimal, `\X` is `X`.
+fn unescape(
+    chars: &mut std::iter::Peekable<std::str::Chars<'_>>,
+    bytes: &mut Vec<u8>,
+) -> Option<()> {
+    let c = chars.next()?;
+    if c.is_ascii_digit() {
+        let digits = [c, chars.next()?, chars.next()?];
+        let value: String = digits.iter().collect();
+        bytes.push(value.parse().ok()?);
+    } else {
+        bytes.extend(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    Some(())
+}
+
+fn utf8(bytes: Vec<u8>, line: usize) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|_| format!("line {line}: text is not valid UTF-8"))
+}
+
+/// A TTL in seconds, or with units like `1h30m`.
+fn parse_ttl(value: &str) -> Option<u32> {
+    let mut total: u32 = 0;
+    let mut number: Option<u32> = None;
+    for c in value.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            number = Some(number.unwrap_or(0).checked_mul(10)?.checked_add(digit)?);
+            continue;
+        }
+        let unit = match c.to_ascii_lowercase() {
+            's' => 1,
+            'm' => 60,
+            'h' => 3600,
+            'd' => 86400,
+            'w' => 604800,
+            _ => return None,
+        };
+        total = total.checked_add(number.take()?.checked_mul(unit)?)?;
+    }
+    match number {
+        Some(seconds) => total.checked_add(seconds),
+        None if total > 0 => Some(total),
+        None => None,
+    }
+}
+
+/// `name` as a fully qualified name without the root dot.
+fn absolute(name: &str, origin: &str) -> String {
+    if name == "@" {
+        origin.to_string()
+    } else if let Some(name) = name.strip_suffix('.') {
+        name.to_string()
+    } else {
+        format!("{name}.{origin}")
+    }
+}
+
+/// `name` relative to `zone`, empty for the apex; `None` outside the zone.
+fn relative(name: &str, zone: &str) -> Option<String> {
+    if name.eq_ignore_ascii_case(zone) {
+        return Some(String::new());
+    }
+    let split = name.len().checked_sub(zone.len() + 1)?;
+    // A split inside a multi-byte character can't be a label boundary.
+    let (label, suffix) = name.split_at_checked(split)?;
+    (suffix.starts_with('.') && suffix[1..].eq_ignore_ascii_case(zone)).then(|| label.to_string())
+}
+
+/// The priority and Njalla content of the rdata of a `record_type` record.
+fn content(
+    record_type: &str,
+    rdata: &[Token],
+    origin: &str,
+) -> Result<(Option<u32>, String), String> {
+    let number = |token: &Token, what: &str| {
+        token
+            .text
+            .parse::<u32>()
+            .map_err(|_| format!("{record_type} {what} '{}' is not a number", token.text))
+    };
+    match (record_type, rdata) {
+        (_, []) => Err(format!("{record_type} record without data")),
+        ("TXT", strings) => Ok((None, strings.iter().map(|t| t.text.as_str()).collect())),
+        ("MX", [preference, host]) => Ok((
+            Some(number(preference, "preference")?),
+            absolute(&host.text, origin),
+        )),
+        ("MX", _) => Err("MX needs a preference and a host".to_string()),
+        ("SRV", [priority, weight, port, target]) => {
+            number(weight, "weight")?;
+            number(port, "port")?;
+            Ok((
+                Some(number(priority, "priority")?),
+                format!(
+                    "{} {} {}",
+                    weight.text,
+                    port.text,
+                    absolute(&target.text, origin)
+                ),
+            ))
+        }
+        ("SRV", _) => Err("SRV needs a priority, weight, port and target".to_string()),
+        ("CNAME" | "NS", [host]) => Ok((None, absolute(&host.text, origin))),
+        ("CNAME" | "NS", _) => Err(format!("{record_type} needs exactly one host")),
+        (_, words) => Ok((
+            None,
+            words
+                .iter()
+                .map(|t| {
+                    if t.quoted {
+                        format!("\"{}\"", t.text)
+                    } else {
+                        t.text.clone()
+                    }
+                })
+                .collect::<Vec<_>>()
+                .join(" "),
+        )),
+    }
+}
+
+/// Names where a CNAME shares its name with other records, which DNS doesn't allow.
+fn conflicts(records: &[DnsRecord], zone: &str) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    for (i, cname) in records.iter().enumerate() {
+        if cname.record_type != "CNAME" {
+            continue;
+        }
+        let others = records
+            .iter()
+            .enumerate()
+            .filter(|(j, r)| *j != i && r.name.eq_ignore_ascii_case(&cname.name))
+            .count();
+        if others > 0 {
+            conflicts.push(format!(
+                "{}: a CNAME can't share its name with {others} other record(s)",
+                fqdn(&cname.name, zone)
+            ));
+        }
+    }
+    conflicts
+}
+
+// The zone file admin endpoints.
+impl WebhookHandler {
+    /// The live records of `zone` as a master file.
+    pub async fn export_zone(&self, zone: &str) -> error::Result<String> {
+        let Json(records) = self.zone_records(zone).await?;
+        let zone = Config::normalize_domain(zone);
+        let comment = format!(
+            "Njalla zone {} exported at {}",
+            zone,
+            chrono::Utc::now().to_rfc3339()
+        );
+        Ok(render(&zone, &records, &comment))
+    }
+
+    /// Plan making `zone` hold the records of the master file `text`: missing records are
+    /// added and records with another TTL or priority replaced; with `prune`, live records the
+    /// file doesn't list are removed too. With `apply`, carry the plan out as one batch.
+    pub async fn import_zone(
+        &self,
+        zone: &str,
+        text: &str,
+        query: ImportQuery,
+    ) -> error::Result<Json<ImportResponse>> {
+        let zone = Config::normalize_domain(zone);
+        if !self.config.is_domain_allowed(&zone) {
+            return Err(Error::DomainNotAllowed(zone));
+        }
+        let parsed = parse(&zone, text, self.default_ttl(&zone))
+            .map_err(|e| Error::InvalidRequest(format!("zone file: {e}")))?;
+        for duplicate in &parsed.duplicates {
+            warn!("Zone file for {}: {}", zone, duplicate);
+        }
+
+        let batch = Batch::new(self.batch_budget(), None);
+        let live = self
+            .njalla_client
+            .list_records(&zone, &batch.budget)
+            .await?;
+        let plan = planner::diff(
+            &zone,
+            &parsed.records,
+            &live,
+            |_| query.prune,
+            |record_type| self.config.is_record_type_allowed(&zone, record_type),
+        );
+        info!("Zone file import into {}: {}", zone, plan.summary());
+
+        let mut applied = false;
+        if query.apply && !plan.is_empty() {
+            self.run_change(
+                &format!("Import zone file into {zone}"),
+                &plan.operations,
+                &batch,
+            )
+            .await?;
+            applied = !self.config.dry_run;
+        }
+        Ok(Json(ImportResponse {
+            applied,
+            batch: applied.then_some(batch.id),
+            duplicates: parsed.duplicates,
+            ignored: parsed.ignored,
+            plan,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::webhook::testing::{self, MockNjalla};
+    use axum::http::StatusCode;
+    use serde_json::json;
+
+    fn record(name: &str, record_type: &str, content: &str, priority: Option<u32>) -> DnsRecord {
+        DnsRecord {
+            id: "1".to_string(),
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            content: content.to_string(),
+            ttl: Some(300),
+            priority,
+        }
+    }
+
+    #[test]
+    fn renders_a_master_file() {
+        let records = [
+            record("www", "CNAME", "example.com", None),
+            record("@", "MX", "mail.example.com", Some(10)),
+            record("", "A", "192.0.2.1", None),
+            record("_sip._tcp", "SRV", "5 5060 sip.example.com", Some(20)),
+            record("@", "TXT", "v=spf1 \"quoted\" -all", None),
+        ];
+        let zone = render("example.com", &records, "snapshot 1");
+        assert_eq!(
+            zone,
+            "; snapshot 1\n\
+             $ORIGIN example.com.\n\
+             $TTL 3600\n\
+             @\t300\tIN\tA\t192.0.2.1\n\
+             @\t300\tIN\tMX\t10 mail.example.com.\n\
+             @\t300\tIN\tTXT\t\"v=spf1 \\\"quoted\\\" -all\"\n\
+             _sip._tcp\t300\tIN\tSRV\t20 5 5060 sip.example.com.\n\
+             www\t300\tIN\tCNAME\texample.com.\n"
+        );
+    }
+
+    #[test]
+    fn long_txt_is_split_into_character_strings() {
+        let quoted = quote_txt(&"a".repeat(300));
+        assert_eq!(
+            quoted,
+            format!("\"{}\" \"{}\"", "a".repeat(255), "a".repeat(45))
+        );
+        assert_eq!(quote_txt(""), "\"\"");
+    }
+
+    #[test]
+    fn parses_what_it_renders() {
+        let records = [
+            record("www", "CNAME", "example.com", None),
+            record("", "MX", "mail.example.com", Some(10)),
+            record("_sip._tcp", "SRV", "5 5060 sip.example.com", Some(20)),
+            record(
+                "",
+                "TXT",
+                &format!("v=spf1 \"quoted\" \\ {}", "a".repeat(300)),
+                None,
+            ),
+            record("", "CAA", "0 issue \"letsencrypt.org\"", None),
+        ];
+        let parsed = parse("example.com", &render("example.com", &records, "x"), 60).unwrap();
+        let mut expected: Vec<DnsRecord> = records
+            .iter()
+            .map(|r| DnsRecord {
+                id: String::new(),
+                ..r.clone()
+            })
+            .collect();
+        expected.sort_by(|a, b| (&a.name, &a.record_type).cmp(&(&b.name, &b.record_type)));
+        assert_eq!(parsed.records, expected);
+        assert!(parsed.duplicates.is_empty() && parsed.ignored.is_empty());
+    }
+
+    #[test]
+    fn parses_common_master_file_syntax() {
+        let text = "\
+$TTL 1h
+@   IN SOA ns1.njalla.no. hostmaster.example.com. (
+        2024010101 ; serial
+        3600 600 86400 300 )
+    IN NS ns1.njalla.no.
+    IN 300 A 192.0.2.1
+www 1d IN A 192.0.2.2
+    AAAA 2001:db8::2
+$ORIGIN sub.example.com.
+mail    MX 10 @
+WWW.example.com.  A 192.0.2.2
+";
+        let parsed = parse("example.com.", text, 60).unwrap();
+        let described: Vec<String> = parsed
+            .records
+            .iter()
+            .map(|r| {
+                format!(
+                    "{} {} {} {:?} {:?}",
+                    r.name, r.record_type, r.content, r.ttl, r.priority
+                )
+            })
+            .collect();
+        assert_eq!(
+            described,
+            [
+                " A 192.0.2.1 Some(300) None",
+                "www A 192.0.2.2 Some(86400) None",
+                "www AAAA 2001:db8::2 Some(3600) None",
+                "mail.sub MX sub.example.com Some(3600) Some(10)",
+            ]
+        );
+        assert_eq!(parsed.ignored.len(), 2);
+        assert_eq!(parsed.duplicates.len(), 1);
+        assert!(
+            parsed.duplicates[0].starts_with("line 11:"),
+            "{:?}",
+            parsed.duplicates
+        );
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        for (text, error) in [
+            (
+                "www A 192.0.2.1\nwww CNAME example.net.\n",
+                "a CNAME can't share",
+            ),
+            (
+                "www.example.org. A 192.0.2.1\n",
+                "line 1: www.example.org is outside",
+            ),
+            ("www PTR host.example.com.\n", "line 1: record type PTR"),
+            ("mx MX host.example.com.\n", "line 1: MX needs"),
+            ("www TXT \"open\n", "line 1: unterminated quote"),
+            ("www TXT ( \"a\"\n", "line 1: unbalanced '('"),
+            ("$INCLUDE other.zone\n", "line 1: $INCLUDE is not supported"),
+            ("  A 192.0.2.1\n", "line 1: record without an owner"),
+            (
+                "äaaaaaaaaaaa. 300 IN A 192.0.2.1\n",
+                "line 1: äaaaaaaaaaaa is outside",
+            ),
+        ] {
+            let err = parse("example.com", text, 3600).unwrap_err();
+            assert!(err.contains(error), "{text:?}: {err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn import_plans_against_live_records() {
+        let mut njalla = MockNjalla::new().await;
+        njalla
+            .records(json!([
+                {"id": "1", "name": "www", "type": "A", "content": "192.0.2.1", "ttl": 3600},
+                {"id": "2", "name": "old", "type": "A", "content": "192.0.2.9", "ttl": 3600}
+            ]))
+            .await;
+
+        let handler = njalla.handler(testing::config());
+        let file = "$TTL 300\n@ SOA ns1.njalla.no. hostmaster.example.com. 1 2 3 4 5\n\
+                    www A 192.0.2.1\nmail A 192.0.2.2\nmail A 192.0.2.2\n";
+
+        let Json(plan) = handler
+            .import_zone("example.com", file, ImportQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(plan.plan.summary(), "2 to add, 1 to remove");
+        assert_eq!((plan.duplicates.len(), plan.ignored.len()), (1, 1));
+
+        let Json(pruned) = handler
+            .import_zone(
+                "example.com",
+                file,
+                ImportQuery {
+                    prune: true,
+                    apply: false,
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(pruned.plan.summary(), "2 to add, 2 to remove");
+        assert!(!pruned.applied);
+
+        let err = handler
+            .import_zone(
+                "example.com",
+                "www CNAME a.\nwww A 192.0.2.1\n",
+                ImportQuery::default(),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), StatusCode::BAD_REQUEST);
+    }
+}